@@ -0,0 +1,127 @@
+// ALSA backend: RX audio is played into one PCM and TX audio recorded from another,
+// either a sound card or the two ends of an snd-aloop loopback pair
+
+use std::sync::Mutex;
+use std::time::Duration;
+
+use alsa::pcm::{Access, Format, HwParams, PCM};
+use alsa::{Direction, ValueOr};
+use anyhow::{bail, Context};
+
+use crate::audio::AudioBackend;
+
+const BUFFER_TIME_US: u32 = 100_000;
+const PERIOD_TIME_US: u32 = 20_000;
+
+struct Device {
+    pcm: PCM,
+    // Devices that cannot do mono get the same sample on every channel
+    channels: usize,
+    rate: u32,
+}
+
+pub struct AlsaBackend {
+    playback: Mutex<Device>,
+    capture: Mutex<Device>,
+}
+
+// Open `name` as S16 at exactly `rate`, leaving the channel count to the device
+fn open_device(name: &str, direction: Direction, rate: u32) -> anyhow::Result<Device> {
+    let pcm = PCM::new(name, direction, false).with_context(|| format!("Cannot open ALSA device {}", name))?;
+    let channels = {
+        let hwp = HwParams::any(&pcm)?;
+        hwp.set_access(Access::RWInterleaved)?;
+        hwp.set_format(Format::S16LE)?;
+        let channels = hwp.set_channels_near(1)?;
+        let actual = hwp.set_rate_near(rate, ValueOr::Nearest)?;
+        // Check if the device runs at the radio rate; hw: devices usually do not
+        if actual != rate {
+            bail!("ALSA device {} cannot run at {} Hz (nearest is {} Hz), use a plughw: device", name, rate, actual);
+        }
+        hwp.set_buffer_time_near(BUFFER_TIME_US, ValueOr::Nearest)?;
+        hwp.set_period_time_near(PERIOD_TIME_US, ValueOr::Nearest)?;
+        pcm.hw_params(&hwp).with_context(|| format!("Cannot configure ALSA device {}", name))?;
+        channels as usize
+    };
+    Ok(Device { pcm, channels, rate })
+}
+
+pub fn setup_alsa(rx_device: &str, tx_device: &str, audio_rx_rate: u32, audio_tx_rate: u32) -> anyhow::Result<AlsaBackend> {
+    let playback = open_device(rx_device, Direction::Playback, audio_rx_rate)?;
+    let capture = open_device(tx_device, Direction::Capture, audio_tx_rate)?;
+    capture.pcm.start()?;
+    Ok(AlsaBackend {
+        playback: Mutex::new(playback),
+        capture: Mutex::new(capture),
+    })
+}
+
+impl AudioBackend for AlsaBackend {
+    fn name(&self) -> &'static str {
+        "alsa"
+    }
+
+    fn write_rx(&self, samples: &[f32]) {
+        let device = self.playback.lock().unwrap();
+        let mut frames = Vec::with_capacity(samples.len() * device.channels);
+        for &sample in samples {
+            let value = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+            frames.extend(std::iter::repeat_n(value, device.channels));
+        }
+        let Ok(io) = device.pcm.io_i16() else {
+            return;
+        };
+        let mut written = 0;
+        while written < samples.len() {
+            match io.writei(&frames[written * device.channels..]) {
+                Ok(n) => written += n,
+                Err(e) => {
+                    // Check if the underrun left while transmitting can be recovered
+                    if device.pcm.try_recover(e, true).is_err() {
+                        return;
+                    }
+                }
+            }
+        }
+    }
+
+    fn rx_delay(&self) -> Option<Duration> {
+        let device = self.playback.lock().unwrap();
+        let frames = device.pcm.delay().ok()?.max(0);
+        Some(Duration::from_secs_f64(frames as f64 / device.rate as f64))
+    }
+
+    fn read_tx(&self, samples: &mut [i16]) -> anyhow::Result<()> {
+        let device = self.capture.lock().unwrap();
+        let mut frames = vec![0i16; samples.len() * device.channels];
+        let io = device.pcm.io_i16()?;
+        let mut read = 0;
+        while read < samples.len() {
+            match io.readi(&mut frames[read * device.channels..]) {
+                Ok(n) => read += n,
+                Err(e) => {
+                    device.pcm.try_recover(e, true)?;
+                    bail!("ALSA capture overrun");
+                }
+            }
+        }
+        // Mix multi-channel input down to mono
+        for (out, frame) in samples.iter_mut().zip(frames.chunks_exact(device.channels)) {
+            *out = (frame.iter().map(|&s| s as i32).sum::<i32>() / device.channels as i32) as i16;
+        }
+        Ok(())
+    }
+
+    fn drain_tx(&self) {
+        // Restarting the capture drops what was buffered, including an overrun from receiving
+        let device = self.capture.lock().unwrap();
+        let _ = device.pcm.drop();
+        let _ = device.pcm.prepare();
+        let _ = device.pcm.start();
+    }
+
+    fn close(&self) {
+        let _ = self.playback.lock().unwrap().pcm.drop();
+        let _ = self.capture.lock().unwrap().pcm.drop();
+    }
+}
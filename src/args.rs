@@ -0,0 +1,385 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use anyhow::Result;
+
+use crate::audio;
+use crate::cat::Mode;
+use crate::config::{self, Settings};
+use crate::quantize::TxQuantizer;
+use crate::record::{self, RecordSplit};
+use crate::rxdsp::{RxAgc, RxFilter};
+use crate::resample::ResampleQuality;
+use crate::rigctl;
+use crate::trusdx;
+
+pub const DEFAULT_SINK_NAME: &str = "TRUSDX";
+const DEFAULT_SINK_DESCRIPTION: &str = "trUSDX";
+// snd-aloop pair: applications record RX from hw:Loopback,1,0 and play TX into hw:Loopback,0,1
+const DEFAULT_ALSA_RX_DEVICE: &str = "plughw:Loopback,0,0";
+const DEFAULT_ALSA_TX_DEVICE: &str = "plughw:Loopback,1,1";
+const DEFAULT_DEVICE_RATE: u32 = 48000;
+const DEFAULT_POLL_INTERVAL_SECS: f64 = 2.0;
+const DEFAULT_TX_TIMEOUT_SECS: f64 = 180.0;
+
+const HELP: &str = "\
+trUSDX Linux audio/CAT driver
+
+USAGE:
+  trusdx_linux_driver [OPTIONS]
+
+CONFIGURATION:
+  --config <path>               Config file [default: $XDG_CONFIG_HOME/trusdx/config.toml]
+  --profile <name>              Use this [profiles.<name>] table from the config file
+
+SERIAL DEVICE:
+  --device <path>               Open this serial port instead of searching
+  --usb-id <VID:PID>            USB IDs to match, repeatable or comma separated [default: 1a86:7523]
+  --usb-serial <serial>         Only match the adapter with this USB serial number
+  --list-devices                Print matching serial devices with sysfs details and exit
+
+RIGCTL SERVER:
+  --port <n>                    Port for bind addresses without one [default: 4532]
+  --rigctl-bind <addr>          Listen address, repeatable (IPv4, IPv6, host name) [default: 127.0.0.1]
+  --rigctl-port-fallback <n>    Try up to n ports above a busy one instead of failing [default: 0]
+  --rigctl-max-clients <n>      Maximum simultaneous rigctl connections [default: 8]
+
+RADIO AND AUDIO:
+  --initial-mode <mode>         Mode set at startup: LSB, USB, CW, FM, AM [default: USB]
+  --initial-freq <hz>           Tune VFO A to this frequency at startup
+  --audio-backend <name>        auto, pulse, pipewire, alsa or jack [default: auto]
+  --alsa-rx-device <pcm>        ALSA device RX audio is played into [default: plughw:Loopback,0,0]
+  --alsa-tx-device <pcm>        ALSA device TX audio is recorded from [default: plughw:Loopback,1,1]
+  --device-rate <hz>            Rate the sound system sees, e.g. 48000 or 12000; 0 passes the
+                                radio rates through unconverted [default: 48000]
+  --resample-quality <q>        fast, medium or high [default: medium]
+  --clock-ppm <ppm>             Radio sample clock offset to correct for [default: 0]
+  --no-clock-tracking           Keep --clock-ppm instead of correcting by the measured offset
+  --sink-name <name>            Base name of the audio devices [default: TRUSDX]
+  --sink-description <text>     Prefix of the device names shown in mixers [default: trUSDX]
+  --poll-interval <secs>        Frequency, VFO and mode poll interval, 0 disables polling [default: 2]
+  --tx-timeout <secs>           Force RX after this much continuous TX, 0 disables [default: 180]
+  --tx-gain <factor>            Gain applied to TX audio [default: 1.0]
+  --min-rms <level>             TX audio below this RMS level is not sent [default: 0.05]
+  --tx-quantizer <q>            TX conversion to 8 bits: truncate, tpdf or shaped [default: shaped]
+  --no-tx-limiter               Let TX peaks clip instead of limiting them
+  --tx-compressor               Compress TX audio, for SSB phone
+  --tx-compression <amount>     Compressor strength from 0 to 1 [default: 0.5]
+  --rx-dsp                      Process RX audio for listening; leave off for digital modes
+  --rx-filter <f>               RX bandpass: off, cw (500 Hz), ssb (2.4 kHz), data (3 kHz) [default: ssb]
+  --rx-agc <speed>              RX AGC: off, slow or fast [default: slow]
+  --rx-notch                    Remove steady carriers from RX audio
+  --rx-nr                       Reduce noise in RX audio
+  --vox                         Key the radio when applications play TX audio
+  --vox-threshold <level>       TX audio RMS level that keys the radio [default: 0.05]
+  --vox-attack <ms>             How long audio must stay over the threshold to key [default: 20]
+  --vox-hang <ms>               How long audio must stay quiet to un-key [default: 500]
+  --vox-anti-trip <factor>      Raise the threshold to this share of the RX level, 0 to 1 [default: 0.5]
+  --record                      Record RX audio to WAV files from the start
+  --record-tx                   Record TX audio as well, to separate files
+  --record-split <s>            Start new files: off, over (each RX/TX switch), minute [default: off]
+  --record-dir <path>           Where recordings go [default: $XDG_DATA_HOME/trusdx/recordings]
+  --keyer-message <path>        WAV file for the next keyer slot, repeatable (slots 1, 2, ...)
+  --keyer-repeat                Send keyer messages again after each interval until stopped
+  --keyer-interval <secs>       Listening time between repeated messages [default: 5]
+
+Command-line options override the selected profile, which overrides the top level of the
+config file. With --no-tui, SIGHUP reloads the gains, threshold, TX quantizer, limiter and
+compressor, RX processing, VOX, recording switches, keyer repeat, poll interval, TX timeout
+and clock correction and tracking from the file.
+
+INTERFACE:
+  --no-gui                      Do not open the GTK window
+  --no-tui                      Do not draw the console meters or read ESC from the terminal
+  -h, --help                    Print this help
+  -V, --version                 Print version
+";
+
+#[derive(Clone, Debug)]
+pub struct Options {
+    pub selector: trusdx::DeviceSelector,
+    pub list_devices: bool,
+    pub rigctl: rigctl::RigctlOptions,
+    pub initial_mode: Mode,
+    pub initial_freq: Option<u64>,
+    pub audio_backend: audio::BackendOptions,
+    pub sink_name: String,
+    pub sink_description: String,
+    pub clock_ppm: f64,
+    pub audio: audio::AudioSettings,
+    // None disables the periodic FA/FB/IF poll
+    pub poll_interval: Option<Duration>,
+    // None disables the TX timeout watchdog
+    pub tx_timeout: Option<Duration>,
+    pub record_dir: PathBuf,
+    // Keyer message files, slot 1 first
+    pub keyer_messages: Vec<PathBuf>,
+    pub no_gui: bool,
+    pub no_tui: bool,
+    pub source: ConfigSource,
+}
+
+// Where the options came from, kept so the file can be read again on SIGHUP
+#[derive(Clone, Debug)]
+pub struct ConfigSource {
+    pub path: Option<PathBuf>,
+    pub explicit: bool,
+    pub profile: Option<String>,
+    pub cli: Settings,
+}
+
+fn parse_device_selector(settings: &Settings) -> Result<trusdx::DeviceSelector> {
+    let mut usb_ids = Vec::new();
+    for spec in settings.usb_id.iter().flatten() {
+        for item in spec.split(',').filter(|item| !item.trim().is_empty()) {
+            let id = trusdx::parse_usb_id(item)
+                .ok_or_else(|| anyhow::anyhow!("Invalid usb-id '{}', expected VID:PID in hex", item))?;
+            usb_ids.push(id);
+        }
+    }
+    Ok(trusdx::DeviceSelector {
+        port: settings.device.clone(),
+        usb_ids,
+        usb_serial: settings.usb_serial.clone(),
+    })
+}
+
+fn parse_rigctl_options(settings: &Settings) -> Result<rigctl::RigctlOptions> {
+    let port = settings.port.unwrap_or(rigctl::DEFAULT_PORT);
+    // Check if no bind address was given, keep the historical localhost default
+    let binds = match &settings.rigctl_bind {
+        Some(binds) if !binds.is_empty() => binds.clone(),
+        _ => vec![rigctl::DEFAULT_BIND.to_string()],
+    };
+    let mut listen = Vec::new();
+    for spec in &binds {
+        listen.extend(rigctl::parse_bind(spec, port)?);
+    }
+    Ok(rigctl::RigctlOptions {
+        listen,
+        port_fallback: settings.rigctl_port_fallback.unwrap_or(0),
+        max_clients: settings.rigctl_max_clients.unwrap_or(rigctl::DEFAULT_MAX_CLIENTS),
+    })
+}
+
+fn parse_mode(s: &str) -> Result<Mode> {
+    Mode::from_name(&s.to_ascii_uppercase()).ok_or_else(|| anyhow::anyhow!("Unknown mode '{}', expected LSB, USB, CW, FM or AM", s))
+}
+
+fn parse_quality(s: &str) -> Result<ResampleQuality> {
+    ResampleQuality::from_name(&s.to_ascii_lowercase())
+        .ok_or_else(|| anyhow::anyhow!("Unknown resample quality '{}', expected fast, medium or high", s))
+}
+
+fn parse_quantizer(s: &str) -> Result<TxQuantizer> {
+    TxQuantizer::from_name(&s.to_ascii_lowercase())
+        .ok_or_else(|| anyhow::anyhow!("Unknown TX quantizer '{}', expected truncate, tpdf or shaped", s))
+}
+
+fn parse_rx_filter(s: &str) -> Result<RxFilter> {
+    RxFilter::from_name(&s.to_ascii_lowercase())
+        .ok_or_else(|| anyhow::anyhow!("Unknown RX filter '{}', expected off, cw, ssb or data", s))
+}
+
+fn parse_rx_agc(s: &str) -> Result<RxAgc> {
+    RxAgc::from_name(&s.to_ascii_lowercase())
+        .ok_or_else(|| anyhow::anyhow!("Unknown RX AGC '{}', expected off, slow or fast", s))
+}
+
+fn parse_record_split(s: &str) -> Result<RecordSplit> {
+    RecordSplit::from_name(&s.to_ascii_lowercase())
+        .ok_or_else(|| anyhow::anyhow!("Unknown record split '{}', expected off, over or minute", s))
+}
+
+fn parse_backend(s: &str) -> Result<audio::BackendKind> {
+    audio::BackendKind::from_name(&s.to_ascii_lowercase())
+        .ok_or_else(|| anyhow::anyhow!("Unknown audio backend '{}', expected auto, pulse, pipewire, alsa or jack", s))
+}
+
+// Seconds to a duration, where 0 means "off"
+fn parse_optional_secs(name: &str, secs: f64) -> Result<Option<Duration>> {
+    // Check if value is usable as a duration
+    if !secs.is_finite() || secs < 0.0 {
+        anyhow::bail!("Invalid {} '{}', expected seconds", name, secs);
+    }
+    Ok(if secs == 0.0 { None } else { Some(Duration::from_secs_f64(secs)) })
+}
+
+fn parse_audio_settings(settings: &Settings) -> Result<audio::AudioSettings> {
+    let defaults = audio::AudioSettings::default();
+    let audio = audio::AudioSettings {
+        tx_gain: settings.tx_gain.unwrap_or(defaults.tx_gain),
+        min_rms_threshold: settings.min_rms_threshold.unwrap_or(defaults.min_rms_threshold),
+        input_level_scale: settings.input_level_scale.unwrap_or(defaults.input_level_scale),
+        clock_tracking: settings.clock_tracking.unwrap_or(defaults.clock_tracking),
+        tx_quantizer: settings.tx_quantizer.as_deref().map(parse_quantizer).transpose()?.unwrap_or(defaults.tx_quantizer),
+        tx_limiter: settings.tx_limiter.unwrap_or(defaults.tx_limiter),
+        tx_compressor: settings.tx_compressor.unwrap_or(defaults.tx_compressor),
+        tx_compression: settings.tx_compression.unwrap_or(defaults.tx_compression),
+        rx_dsp: settings.rx_dsp.unwrap_or(defaults.rx_dsp),
+        rx_filter: settings.rx_filter.as_deref().map(parse_rx_filter).transpose()?.unwrap_or(defaults.rx_filter),
+        rx_agc: settings.rx_agc.as_deref().map(parse_rx_agc).transpose()?.unwrap_or(defaults.rx_agc),
+        rx_notch: settings.rx_notch.unwrap_or(defaults.rx_notch),
+        rx_nr: settings.rx_nr.unwrap_or(defaults.rx_nr),
+        vox: settings.vox.unwrap_or(defaults.vox),
+        vox_threshold: settings.vox_threshold.unwrap_or(defaults.vox_threshold),
+        vox_attack_ms: settings.vox_attack_ms.unwrap_or(defaults.vox_attack_ms),
+        vox_hang_ms: settings.vox_hang_ms.unwrap_or(defaults.vox_hang_ms),
+        vox_anti_trip: settings.vox_anti_trip.unwrap_or(defaults.vox_anti_trip),
+        record: settings.record.unwrap_or(defaults.record),
+        record_tx: settings.record_tx.unwrap_or(defaults.record_tx),
+        record_split: settings.record_split.as_deref().map(parse_record_split).transpose()?.unwrap_or(defaults.record_split),
+        keyer_repeat: settings.keyer_repeat.unwrap_or(defaults.keyer_repeat),
+        keyer_interval: settings.keyer_interval.unwrap_or(defaults.keyer_interval),
+    };
+    // Check if any of the levels is negative or not a number
+    if ![audio.tx_gain, audio.min_rms_threshold, audio.input_level_scale, audio.vox_threshold, audio.keyer_interval].iter().all(|v| v.is_finite() && *v >= 0.0) {
+        anyhow::bail!("tx-gain, min-rms, input-level-scale, vox-threshold and keyer-interval must be non-negative numbers");
+    }
+    // Check if the compression amount is within its scale
+    if !(0.0..=1.0).contains(&audio.tx_compression) {
+        anyhow::bail!("Invalid tx-compression '{}', expected a value from 0 to 1", audio.tx_compression);
+    }
+    // Check if the anti-trip share is within its scale
+    if !(0.0..=1.0).contains(&audio.vox_anti_trip) {
+        anyhow::bail!("Invalid vox-anti-trip '{}', expected a value from 0 to 1", audio.vox_anti_trip);
+    }
+    Ok(audio)
+}
+
+// Command-line values, in the same shape as a config file layer
+fn parse_cli_settings(args: &mut pico_args::Arguments) -> Result<Settings> {
+    let usb_id: Vec<String> = args.values_from_str("--usb-id")?;
+    let rigctl_bind: Vec<String> = args.values_from_str("--rigctl-bind")?;
+    let keyer_messages: Vec<String> = args.values_from_str("--keyer-message")?;
+    Ok(Settings {
+        device: args.opt_value_from_str("--device")?,
+        usb_id: if usb_id.is_empty() { None } else { Some(usb_id) },
+        usb_serial: args.opt_value_from_str("--usb-serial")?,
+        port: args.opt_value_from_str("--port")?,
+        rigctl_bind: if rigctl_bind.is_empty() { None } else { Some(rigctl_bind) },
+        rigctl_port_fallback: args.opt_value_from_str("--rigctl-port-fallback")?,
+        rigctl_max_clients: args.opt_value_from_str("--rigctl-max-clients")?,
+        initial_mode: args.opt_value_from_str("--initial-mode")?,
+        initial_freq: args.opt_value_from_str("--initial-freq")?,
+        audio_backend: args.opt_value_from_str("--audio-backend")?,
+        alsa_rx_device: args.opt_value_from_str("--alsa-rx-device")?,
+        alsa_tx_device: args.opt_value_from_str("--alsa-tx-device")?,
+        device_rate: args.opt_value_from_str("--device-rate")?,
+        resample_quality: args.opt_value_from_str("--resample-quality")?,
+        clock_ppm: args.opt_value_from_str("--clock-ppm")?,
+        clock_tracking: args.contains("--no-clock-tracking").then_some(false),
+        sink_name: args.opt_value_from_str("--sink-name")?,
+        sink_description: args.opt_value_from_str("--sink-description")?,
+        poll_interval: args.opt_value_from_str("--poll-interval")?,
+        tx_timeout: args.opt_value_from_str("--tx-timeout")?,
+        tx_gain: args.opt_value_from_str("--tx-gain")?,
+        min_rms_threshold: args.opt_value_from_str("--min-rms")?,
+        tx_quantizer: args.opt_value_from_str("--tx-quantizer")?,
+        tx_limiter: args.contains("--no-tx-limiter").then_some(false),
+        tx_compressor: args.contains("--tx-compressor").then_some(true),
+        tx_compression: args.opt_value_from_str("--tx-compression")?,
+        rx_dsp: args.contains("--rx-dsp").then_some(true),
+        rx_filter: args.opt_value_from_str("--rx-filter")?,
+        rx_agc: args.opt_value_from_str("--rx-agc")?,
+        rx_notch: args.contains("--rx-notch").then_some(true),
+        rx_nr: args.contains("--rx-nr").then_some(true),
+        vox: args.contains("--vox").then_some(true),
+        vox_threshold: args.opt_value_from_str("--vox-threshold")?,
+        vox_attack_ms: args.opt_value_from_str("--vox-attack")?,
+        vox_hang_ms: args.opt_value_from_str("--vox-hang")?,
+        vox_anti_trip: args.opt_value_from_str("--vox-anti-trip")?,
+        record: args.contains("--record").then_some(true),
+        record_tx: args.contains("--record-tx").then_some(true),
+        record_split: args.opt_value_from_str("--record-split")?,
+        record_dir: args.opt_value_from_str("--record-dir")?,
+        keyer_messages: if keyer_messages.is_empty() { None } else { Some(keyer_messages) },
+        keyer_repeat: args.contains("--keyer-repeat").then_some(true),
+        keyer_interval: args.opt_value_from_str("--keyer-interval")?,
+        input_level_scale: None,
+        no_gui: args.contains("--no-gui").then_some(true),
+        no_tui: args.contains("--no-tui").then_some(true),
+    })
+}
+
+// Layer command line over config file and turn the result into options
+fn build_options(source: ConfigSource, list_devices: bool) -> Result<Options> {
+    let file = match &source.path {
+        Some(path) => config::load(path, source.explicit)?,
+        None => config::ConfigFile::default(),
+    };
+    let settings = source.cli.clone().or(file.resolve(source.profile.as_deref())?);
+    let sink_name = settings.sink_name.clone().unwrap_or_else(|| DEFAULT_SINK_NAME.to_string());
+    let clock_ppm = settings.clock_ppm.unwrap_or(0.0);
+    // Check if the correction is a plausible crystal offset
+    if !clock_ppm.is_finite() || clock_ppm.abs() > 10_000.0 {
+        anyhow::bail!("Invalid clock-ppm '{}', expected an offset within +/-10000 ppm", clock_ppm);
+    }
+
+    Ok(Options {
+        selector: parse_device_selector(&settings)?,
+        list_devices,
+        rigctl: parse_rigctl_options(&settings)?,
+        initial_mode: settings.initial_mode.as_deref().map(parse_mode).transpose()?.unwrap_or(Mode::Usb),
+        initial_freq: settings.initial_freq,
+        audio_backend: audio::BackendOptions {
+            kind: settings.audio_backend.as_deref().map(parse_backend).transpose()?.unwrap_or(audio::BackendKind::Auto),
+            alsa_rx_device: settings.alsa_rx_device.clone().unwrap_or_else(|| DEFAULT_ALSA_RX_DEVICE.to_string()),
+            alsa_tx_device: settings.alsa_tx_device.clone().unwrap_or_else(|| DEFAULT_ALSA_TX_DEVICE.to_string()),
+            device_rate: Some(settings.device_rate.unwrap_or(DEFAULT_DEVICE_RATE)).filter(|&rate| rate != 0),
+            resample_quality: settings.resample_quality.as_deref().map(parse_quality).transpose()?.unwrap_or(ResampleQuality::Medium),
+        },
+        sink_name: sink_name.clone(),
+        sink_description: settings.sink_description.clone().unwrap_or_else(|| DEFAULT_SINK_DESCRIPTION.to_string()),
+        clock_ppm,
+        audio: parse_audio_settings(&settings)?,
+        poll_interval: parse_optional_secs("poll-interval", settings.poll_interval.unwrap_or(DEFAULT_POLL_INTERVAL_SECS))?,
+        tx_timeout: parse_optional_secs("tx-timeout", settings.tx_timeout.unwrap_or(DEFAULT_TX_TIMEOUT_SECS))?,
+        record_dir: settings.record_dir.as_deref().map(PathBuf::from).unwrap_or_else(record::default_dir),
+        keyer_messages: settings.keyer_messages.iter().flatten().map(PathBuf::from).collect(),
+        no_gui: settings.no_gui.unwrap_or(false),
+        no_tui: settings.no_tui.unwrap_or(false),
+        source,
+    })
+}
+
+// Returns None when --help or --version was handled and the program should exit
+pub fn parse_args() -> Result<Option<Options>> {
+    let mut args = pico_args::Arguments::from_env();
+
+    // Check if help was requested
+    if args.contains(["-h", "--help"]) {
+        print!("{}", HELP);
+        return Ok(None);
+    }
+    // Check if version was requested
+    if args.contains(["-V", "--version"]) {
+        println!("trusdx_linux_driver {}", env!("CARGO_PKG_VERSION"));
+        return Ok(None);
+    }
+
+    let list_devices = args.contains("--list-devices");
+    let config_path: Option<PathBuf> = args.opt_value_from_str("--config")?;
+    let profile: Option<String> = args.opt_value_from_str("--profile")?;
+    let cli = parse_cli_settings(&mut args)?;
+
+    let rest = args.finish();
+    // Check if anything on the command line was not understood
+    if !rest.is_empty() {
+        let rest: Vec<String> = rest.iter().map(|arg| arg.to_string_lossy().into_owned()).collect();
+        anyhow::bail!("Unexpected arguments: {} (see --help)", rest.join(" "));
+    }
+
+    let source = ConfigSource {
+        explicit: config_path.is_some(),
+        path: config_path.or_else(config::default_path),
+        profile,
+        cli,
+    };
+    build_options(source, list_devices).map(Some)
+}
+
+// Read the config file again with the same profile and command line
+pub fn reload(options: &Options) -> Result<Options> {
+    build_options(options.source.clone(), options.list_devices)
+}
@@ -1,5 +1,5 @@
 use std::io::Write;
-use std::process::Command;
+use std::path::PathBuf;
 use std::sync::{
     atomic::{AtomicBool, Ordering},
     Arc, Mutex,
@@ -7,131 +7,276 @@ use std::sync::{
 use std::thread;
 use std::time::Duration;
 
-use libpulse_binding as pulse;
-use libpulse_binding::def::BufferAttr;
-use libpulse_simple_binding as psimple;
 use serialport;
 
+use crate::cat::{CatCommand, CatDecoder, CatEvent, CatResponse, Mode, Vfo};
+use crate::drift::{DriftEstimator, FillTracker};
+use crate::keyer::{Keyer, KeyerAction};
+use crate::quantize::{Quantizer, TxQuantizer};
+use crate::record::{RecordSplit, Recorder};
+use crate::rxdsp::{RxAgc, RxDsp, RxFilter};
+use crate::txdsp::TxDsp;
+use crate::vox::{Vox, VoxAction};
+use crate::resample::{ResampleQuality, ResampledBackend};
+
 // Constants
+// Sample rates of the trUSDX's own audio streams over CAT
+pub const RADIO_RX_RATE: u32 = 7812;
+pub const RADIO_TX_RATE: u32 = 11520;
 const TX_FALLING_DELAY_MS: u64 = 30;
 const STREAMING_TIMEOUT_MS: u64 = 200;
 const STREAMING_RETRY_TIMEOUT_MS: u64 = 100;
 const POLL_INTERVAL_MS: u64 = 5;
-const DRAIN_ATTEMPTS: usize = 10;
-const MIN_RMS_THRESHOLD: f32 = 0.05;
-const INPUT_LEVEL_SCALE: f32 = 2.1;
-const TX_GAIN: f32 = 1.0;
-const WAVE_BUF_MAX_SIZE: usize = 512;
+const DRIFT_UPDATE_INTERVAL_MS: u64 = 1000;
+const DEFAULT_MIN_RMS_THRESHOLD: f32 = 0.05;
+const DEFAULT_INPUT_LEVEL_SCALE: f32 = 2.1;
+const DEFAULT_TX_GAIN: f32 = 1.0;
 const AUDIO_SAMPLE_SIZE: usize = 48;
 const I16_TO_F32_SCALE: f32 = 32768.0;
 const U8_TO_F32_OFFSET: f32 = 128.0;
 const U8_TO_F32_SCALE: f32 = 128.0;
 
-pub fn cleanup_trusdx_audio_interfaces() {
-    // Check if pactl command executed successfully
-    if let Ok(output) = Command::new("pactl")
-        .arg("list")
-        .arg("short")
-        .arg("modules")
-        .output()
-    {
-        let stdout = String::from_utf8_lossy(&output.stdout);
-        for line in stdout.lines() {
-            // Check if line contains TRUSDX module identifier
-            if line.contains("TRUSDX") {
-                let parts: Vec<&str> = line.split_whitespace().collect();
-                // Check if module ID exists in parsed line
-                if let Some(module_id) = parts.first() {
-                    let _ = Command::new("pactl")
-                        .arg("unload-module")
-                        .arg(module_id)
-                        .status();
-                }
-            }
+// Sound devices the driver creates, all named after the configured sink name
+#[derive(Clone, Debug)]
+pub struct VirtualDevices {
+    // Source apps record RX audio from (remap of rx_sink's monitor)
+    pub rx_source: String,
+    // Null sink the bridge plays RX audio into
+    pub rx_sink: String,
+    // Null sink apps play TX audio into; the bridge records its monitor
+    pub tx_sink: String,
+    // Sink name used before the RX/TX split, still cleaned up if found
+    pub(crate) legacy_sink: String,
+}
+
+impl VirtualDevices {
+    pub fn new(base: &str) -> VirtualDevices {
+        VirtualDevices {
+            rx_source: format!("{}_RX", base),
+            rx_sink: format!("{}_RX_OUT", base),
+            tx_sink: format!("{}_TX", base),
+            legacy_sink: base.to_string(),
         }
     }
 }
 
-pub fn create_trusdx_audio_interface(_audio_tx_rate: u32) -> Option<u32> {
-    let sink_output = Command::new("pactl")
-        .args([
-            "load-module",
-            "module-null-sink",
-            "sink_name=TRUSDX",
-            "sink_properties=device.description=\"TRUSDX Audio\"",
-        ])
-        .output();
-
-    sink_output
-        .ok()
-        // Check if command execution was successful
-        .filter(|result| result.status.success())
-        .and_then(|result| {
-            String::from_utf8_lossy(&result.stdout)
-                .trim()
-                .parse::<u32>()
-                .ok()
-        })
+// Settings the bridge reads on every pass, so they can change while running
+#[derive(Clone, Copy, Debug)]
+pub struct AudioSettings {
+    pub tx_gain: f32,
+    pub min_rms_threshold: f32,
+    pub input_level_scale: f32,
+    // Let the measured radio clock offset steer the resampler
+    pub clock_tracking: bool,
+    pub tx_quantizer: TxQuantizer,
+    pub tx_limiter: bool,
+    pub tx_compressor: bool,
+    // Compressor strength from 0 (none) to 1
+    pub tx_compression: f32,
+    // Master switch for the RX processing below; off leaves the stream untouched for decoders
+    pub rx_dsp: bool,
+    pub rx_filter: RxFilter,
+    pub rx_agc: RxAgc,
+    pub rx_notch: bool,
+    pub rx_nr: bool,
+    // Key the radio from TX audio, for applications without CAT control
+    pub vox: bool,
+    // TX audio RMS level that keys the radio
+    pub vox_threshold: f32,
+    // How long audio must stay over the threshold to key, and under it to un-key
+    pub vox_attack_ms: u32,
+    pub vox_hang_ms: u32,
+    // Share of the RX audio level the threshold is raised to while receiving, 0 to 1
+    pub vox_anti_trip: f32,
+    // Write RX audio, and TX audio if record_tx is set, to WAV files
+    pub record: bool,
+    pub record_tx: bool,
+    pub record_split: RecordSplit,
+    // Send keyer messages again after keyer_interval seconds of listening, until stopped
+    pub keyer_repeat: bool,
+    pub keyer_interval: f32,
+}
+
+impl Default for AudioSettings {
+    fn default() -> Self {
+        AudioSettings {
+            tx_gain: DEFAULT_TX_GAIN,
+            min_rms_threshold: DEFAULT_MIN_RMS_THRESHOLD,
+            input_level_scale: DEFAULT_INPUT_LEVEL_SCALE,
+            clock_tracking: true,
+            tx_quantizer: TxQuantizer::Shaped,
+            tx_limiter: true,
+            tx_compressor: false,
+            tx_compression: 0.5,
+            rx_dsp: false,
+            rx_filter: RxFilter::Ssb,
+            rx_agc: RxAgc::Slow,
+            rx_notch: false,
+            rx_nr: false,
+            vox: false,
+            vox_threshold: DEFAULT_MIN_RMS_THRESHOLD,
+            vox_attack_ms: 20,
+            vox_hang_ms: 500,
+            vox_anti_trip: 0.5,
+            record: false,
+            record_tx: false,
+            record_split: RecordSplit::Off,
+            keyer_repeat: false,
+            keyer_interval: 5.0,
+        }
+    }
+}
+
+// One sound system connection: RX audio out to applications, TX audio in from them
+pub trait AudioBackend: Send + Sync {
+    fn name(&self) -> &'static str;
+    // Mono f32 samples at the radio RX rate
+    fn write_rx(&self, samples: &[f32]);
+    // Fills `samples` (mono, radio TX rate) completely, or fails so the caller can retry
+    fn read_tx(&self, samples: &mut [i16]) -> anyhow::Result<()>;
+    // Whether read_tx could fill `samples` samples without waiting; backends that are paced by a
+    // running device clock always can
+    fn tx_ready(&self, _samples: usize) -> bool {
+        true
+    }
+    // Discard TX audio that piled up while receiving
+    fn drain_tx(&self);
+    // Radio sample clock offset in ppm; only backends that resample can follow it
+    fn set_clock_correction(&self, _ppm: f64) {}
+    // Whether set_clock_correction has any effect
+    fn corrects_clock(&self) -> bool {
+        false
+    }
+    // How long RX audio written now waits before the device plays it, if the backend can tell
+    fn rx_delay(&self) -> Option<Duration> {
+        None
+    }
+    // Tear down the devices this backend created
+    fn close(&self);
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BackendKind {
+    // Native PipeWire when built in and running, PulseAudio otherwise
+    Auto,
+    Pulse,
+    PipeWire,
+    Alsa,
+    Jack,
+}
+
+impl BackendKind {
+    pub fn from_name(name: &str) -> Option<BackendKind> {
+        match name {
+            "auto" => Some(BackendKind::Auto),
+            "pulse" | "pulseaudio" => Some(BackendKind::Pulse),
+            "pipewire" => Some(BackendKind::PipeWire),
+            "alsa" => Some(BackendKind::Alsa),
+            "jack" => Some(BackendKind::Jack),
+            _ => None,
+        }
+    }
+}
+
+// Which backend to use, plus the settings only some backends look at
+#[derive(Clone, Debug)]
+pub struct BackendOptions {
+    pub kind: BackendKind,
+    // ALSA PCM the RX audio is played into
+    #[cfg_attr(not(feature = "alsa"), allow(dead_code))]
+    pub alsa_rx_device: String,
+    // ALSA PCM the TX audio is recorded from
+    #[cfg_attr(not(feature = "alsa"), allow(dead_code))]
+    pub alsa_tx_device: String,
+    // Rate the sound system sees, None to hand it the radio rates directly
+    pub device_rate: Option<u32>,
+    pub resample_quality: ResampleQuality,
 }
 
 #[derive(Clone)]
 pub struct AudioHandles {
-    pub pa_playback: Arc<psimple::Simple>,
-    pub pa_record: Arc<psimple::Simple>,
+    pub backend: Arc<dyn AudioBackend>,
+    // Nominal rate of the radio's RX stream, what the drift measurement compares against
+    pub rx_rate: u32,
+    pub tx_rate: u32,
 }
 
-fn create_buffer_attr(rate: u32, sample_size: usize) -> BufferAttr {
-    let sample_size_u32 = sample_size as u32;
-    BufferAttr {
-        maxlength: (rate / 4) * sample_size_u32,
-        tlength: (rate / 50) * sample_size_u32,
-        prebuf: (rate / 100) * sample_size_u32,
-        minreq: (rate / 200) * sample_size_u32,
-        fragsize: (rate / 100) * sample_size_u32,
-    }
+#[cfg(feature = "pipewire")]
+fn setup_pipewire(devices: &VirtualDevices, description: &str, rx_rate: u32, tx_rate: u32) -> anyhow::Result<Arc<dyn AudioBackend>> {
+    Ok(Arc::new(crate::pipewire_backend::setup_pipewire(devices, description, rx_rate, tx_rate)?))
+}
+
+#[cfg(not(feature = "pipewire"))]
+fn setup_pipewire(_devices: &VirtualDevices, _description: &str, _rx_rate: u32, _tx_rate: u32) -> anyhow::Result<Arc<dyn AudioBackend>> {
+    anyhow::bail!("this build has no PipeWire support (enable the \"pipewire\" feature)")
+}
+
+#[cfg(feature = "alsa")]
+fn setup_alsa(options: &BackendOptions, rx_rate: u32, tx_rate: u32) -> anyhow::Result<Arc<dyn AudioBackend>> {
+    Ok(Arc::new(crate::alsa_backend::setup_alsa(&options.alsa_rx_device, &options.alsa_tx_device, rx_rate, tx_rate)?))
+}
+
+#[cfg(not(feature = "alsa"))]
+fn setup_alsa(_options: &BackendOptions, _rx_rate: u32, _tx_rate: u32) -> anyhow::Result<Arc<dyn AudioBackend>> {
+    anyhow::bail!("this build has no ALSA support (enable the \"alsa\" feature)")
+}
+
+// JACK dictates the rate, so it is returned along with the backend
+#[cfg(feature = "jack")]
+fn setup_jack(devices: &VirtualDevices, description: &str) -> anyhow::Result<(Arc<dyn AudioBackend>, u32)> {
+    let backend = crate::jack_backend::setup_jack(devices, description)?;
+    let rate = backend.sample_rate();
+    Ok((Arc::new(backend), rate))
 }
 
-pub fn setup_pulseaudio(audio_rx_rate: u32, audio_tx_rate: u32) -> anyhow::Result<AudioHandles> {
-    let spec_rx = pulse::sample::Spec {
-        format: pulse::sample::Format::F32le,
-        channels: 1,
-        rate: audio_rx_rate,
+#[cfg(not(feature = "jack"))]
+fn setup_jack(_devices: &VirtualDevices, _description: &str) -> anyhow::Result<(Arc<dyn AudioBackend>, u32)> {
+    anyhow::bail!("this build has no JACK support (enable the \"jack\" feature)")
+}
+
+fn setup_pulse(devices: &VirtualDevices, description: &str, rx_rate: u32, tx_rate: u32) -> anyhow::Result<Arc<dyn AudioBackend>> {
+    Ok(Arc::new(crate::pulse_backend::setup_pulseaudio(devices, description, rx_rate, tx_rate)?))
+}
+
+// Create the RX source / TX sink on the selected sound system, resampling in between if asked to
+pub fn setup_audio(
+    options: &BackendOptions,
+    devices: &VirtualDevices,
+    description: &str,
+    rx_rate: u32,
+    tx_rate: u32,
+) -> anyhow::Result<AudioHandles> {
+    let (device_rx_rate, device_tx_rate) = options.device_rate.map_or((rx_rate, tx_rate), |rate| (rate, rate));
+    let mut device_rate = options.device_rate;
+    let backend = match options.kind {
+        BackendKind::Pulse => setup_pulse(devices, description, device_rx_rate, device_tx_rate)?,
+        BackendKind::PipeWire => setup_pipewire(devices, description, device_rx_rate, device_tx_rate)?,
+        BackendKind::Alsa => setup_alsa(options, device_rx_rate, device_tx_rate)?,
+        BackendKind::Jack => {
+            let (backend, rate) = setup_jack(devices, description)?;
+            device_rate = Some(rate);
+            backend
+        }
+        // Check if native PipeWire is available before falling back to PulseAudio
+        BackendKind::Auto => match setup_pipewire(devices, description, device_rx_rate, device_tx_rate) {
+            Ok(backend) => backend,
+            Err(e) => {
+                eprintln!("PipeWire not available ({:#}), using PulseAudio", e);
+                setup_pulse(devices, description, device_rx_rate, device_tx_rate)?
+            }
+        },
     };
-    let spec_tx = pulse::sample::Spec {
-        format: pulse::sample::Format::S16le,
-        channels: 1,
-        rate: audio_tx_rate,
+    let backend: Arc<dyn AudioBackend> = match device_rate {
+        Some(rate) => {
+            eprintln!("Audio backend: {} at {} Hz ({:?} resampling)", backend.name(), rate, options.resample_quality);
+            Arc::new(ResampledBackend::new(backend, rx_rate, tx_rate, rate, options.resample_quality))
+        }
+        None => {
+            eprintln!("Audio backend: {}", backend.name());
+            backend
+        }
     };
-
-    let pb_attr = create_buffer_attr(audio_rx_rate, std::mem::size_of::<f32>());
-    let rec_attr = create_buffer_attr(audio_tx_rate, std::mem::size_of::<i16>());
-
-    let pa_playback = psimple::Simple::new(
-        None,
-        "trusdxAudio",
-        pulse::stream::Direction::Playback,
-        Some("TRUSDX"),
-        "Radio RX Audio",
-        &spec_rx,
-        None,
-        Some(&pb_attr),
-    )?;
-
-    let pa_record = psimple::Simple::new(
-        None,
-        "trusdxAudio",
-        pulse::stream::Direction::Record,
-        Some("TRUSDX.monitor"),
-        "Radio TX Audio",
-        &spec_tx,
-        None,
-        Some(&rec_attr),
-    )?;
-
-    Ok(AudioHandles {
-        pa_playback: Arc::new(pa_playback),
-        pa_record: Arc::new(pa_record),
-    })
+    Ok(AudioHandles { backend, rx_rate, tx_rate })
 }
 
 // Helper function to convert u8 wave samples to f32
@@ -154,25 +299,15 @@ fn calculate_peak_level(wave_buf: &[u8]) -> f32 {
         .fold(0.0f32, f32::max)
 }
 
-// Helper function to write audio buffer to pulseaudio (safe conversion)
-fn write_audio_buffer(audio: &psimple::Simple, f32_buf: &[f32]) {
-    // Safe conversion: f32 to bytes using bytemuck-style approach
-    // Since we're writing F32le format, we can safely reinterpret
-    let bytes = unsafe {
-        std::slice::from_raw_parts(
-            f32_buf.as_ptr() as *const u8,
-            f32_buf.len() * std::mem::size_of::<f32>(),
-        )
-    };
-    let _ = audio.write(bytes);
-}
-
 // Helper function to process and write wave buffer
 fn process_and_write_wave(
     wave_buf: &[u8],
     f32_buf: &mut Vec<f32>,
-    audio: &psimple::Simple,
+    audio: &dyn AudioBackend,
     input_level: &Arc<Mutex<f32>>,
+    levels: &AudioSettings,
+    rx_dsp: &mut RxDsp,
+    vox: &mut Vox,
 ) {
     // Check if wave buffer has any data to process
     if wave_buf.is_empty() {
@@ -182,22 +317,14 @@ fn process_and_write_wave(
     let peak = calculate_peak_level(wave_buf);
     {
         let mut lvl = input_level.lock().unwrap();
-        *lvl = (peak * INPUT_LEVEL_SCALE).min(1.0);
+        *lvl = (peak * levels.input_level_scale).min(1.0);
     }
 
     convert_wave_to_f32(wave_buf, f32_buf);
-    write_audio_buffer(audio, f32_buf);
-}
-
-// Helper function to convert bytes to i16 samples (little-endian)
-fn bytes_to_i16_le(bytes: &[u8], output: &mut [i16]) {
-    for (index, chunk) in bytes.chunks_exact(2).enumerate() {
-        // Check if output buffer has space for more samples
-        if index >= output.len() {
-            break;
-        }
-        output[index] = i16::from_le_bytes([chunk[0], chunk[1]]);
-    }
+    // Check if the RX processing is switched in
+    let written: &[f32] = if levels.rx_dsp { rx_dsp.process(levels, f32_buf) } else { f32_buf };
+    audio.write_rx(written);
+    vox.feed_rx(written);
 }
 
 // Helper function to calculate RMS level
@@ -212,6 +339,16 @@ fn calculate_rms(samples: &[i16]) -> f32 {
     (sum_sq / samples.len() as f32).sqrt().min(1.0)
 }
 
+// Helper function to stop recording after a file error, rather than failing on every block
+fn check_recording(result: anyhow::Result<()>, recorder: &mut Recorder, settings: &Arc<Mutex<AudioSettings>>) {
+    // Check if the recording could not be written
+    if let Err(e) = result {
+        eprintln!("Recording stopped: {:#}", e);
+        recorder.stop();
+        settings.lock().unwrap().record = false;
+    }
+}
+
 // Helper function to wait for streaming to start
 fn wait_for_streaming(streaming_started: &Arc<AtomicBool>, timeout_ms: u64) {
     let start = std::time::Instant::now();
@@ -225,41 +362,51 @@ fn wait_for_streaming(streaming_started: &Arc<AtomicBool>, timeout_ms: u64) {
 pub fn run_audio_bridge(
     ser: Arc<Mutex<Box<dyn serialport::SerialPort + Send>>>,
     audio: AudioHandles,
+    settings: Arc<Mutex<AudioSettings>>,
     stop_flag: Arc<Mutex<bool>>,
     input_level: Arc<Mutex<f32>>,
     output_level: Arc<Mutex<f32>>,
     freq_state: Arc<Mutex<u64>>,
-    mode_state: Arc<Mutex<String>>,
+    vfo_state: Arc<Mutex<crate::trusdx::VfoState>>,
+    mode_state: Arc<Mutex<Mode>>,
     tx_state: Arc<Mutex<bool>>,
-    cat_queue: Arc<Mutex<Vec<Vec<u8>>>>,
+    cat_queue: Arc<Mutex<Vec<CatCommand>>>,
     streaming_started: Arc<AtomicBool>,
+    record_dir: PathBuf,
 ) {
     thread::spawn(move || {
-        let mut inbound_serial_audio = false;
-        let mut text_buf: Vec<u8> = Vec::with_capacity(1024);
-        let mut wave_buf: Vec<u8> = Vec::with_capacity(8192);
+        let mut decoder = CatDecoder::new();
         let mut rx_tmp = [0u8; 512];
         let mut f32_buf: Vec<f32> = Vec::with_capacity(1024);
 
         let mut tx_i16_buf = vec![0i16; AUDIO_SAMPLE_SIZE];
-        let mut tx_byte_buf = vec![0u8; tx_i16_buf.len() * 2];
         let mut u8_buf = vec![0u8; AUDIO_SAMPLE_SIZE];
+        let mut drift = DriftEstimator::new(audio.rx_rate);
+        let mut fill = FillTracker::new();
+        let mut quantizer = Quantizer::new();
+        let mut tx_dsp = TxDsp::new(audio.tx_rate);
+        let mut tx_f32_buf = Vec::with_capacity(AUDIO_SAMPLE_SIZE);
+        let mut rx_dsp = RxDsp::new(audio.rx_rate);
+        let mut vox = Vox::new(audio.rx_rate);
+        let mut recorder = Recorder::new(record_dir, audio.rx_rate, audio.tx_rate);
+        let mut keyer = Keyer::new(audio.tx_rate);
+        let mut last_drift_update = std::time::Instant::now();
 
         let drain_cat = || {
-            let mut writes: Vec<Vec<u8>> = Vec::new();
+            let mut writes: Vec<u8> = Vec::new();
             {
                 let mut guard = cat_queue.lock().unwrap();
-                // Check if CAT queue has pending commands
-                if !guard.is_empty() {
-                    writes.extend(guard.drain(..));
+                for cmd in guard.drain(..) {
+                    cmd.encode_into(&mut writes);
                 }
             }
             // Check if there are commands to write to serial port
             if !writes.is_empty() {
                 // Check if serial port lock acquired successfully
                 if let Ok(mut serial_port) = ser.lock() {
-                    for write_data in writes {
-                        let _ = serial_port.write_all(&write_data);
+                    // Check if write failed (device may have been unplugged)
+                    if let Err(e) = serial_port.write_all(&writes) {
+                        crate::reconnect::note_io_error(&e);
                     }
                     let _ = crate::trusdx::flush_serial_line(&mut **serial_port);
                 }
@@ -276,27 +423,55 @@ pub fn run_audio_bridge(
                 }
             }
 
+            // Check if the serial link is down; keep the audio streams open and wait
+            if !crate::reconnect::is_connected() {
+                decoder.reset();
+                drift.pause();
+                fill.pause();
+                *input_level.lock().unwrap() = 0.0;
+                *output_level.lock().unwrap() = 0.0;
+                thread::sleep(Duration::from_millis(POLL_INTERVAL_MS));
+                continue;
+            }
+
+            let levels = *settings.lock().unwrap();
+            // Check if the keyer wants to start or stop a message
+            match keyer.poll(*tx_state.lock().unwrap()) {
+                KeyerAction::Key => {
+                    // Check if serial port lock acquired successfully
+                    if let Ok(mut serial_port) = ser.lock() {
+                        let _ = crate::trusdx::start_transmit_baseband(&mut **serial_port);
+                    }
+                    *tx_state.lock().unwrap() = true;
+                }
+                KeyerAction::Unkey => {
+                    crate::watchdog::force_rx(&ser, &tx_state);
+                    audio.backend.drain_tx();
+                }
+                KeyerAction::None => {}
+            }
             let is_tx = *tx_state.lock().unwrap();
             let is_rx = !is_tx;
+            // Check if recording was switched off since the last pass
+            if !levels.record {
+                recorder.stop();
+            }
             let starting_tx = is_tx && !prev_tx;
             let starting_rx = !is_tx && prev_tx;
 
             // Check if transitioning from RX to TX mode
             if starting_tx {
-                let mut drain_buf = vec![0u8; 1024];
-                for _ in 0..DRAIN_ATTEMPTS {
-                    // Check if audio read failed (buffer empty)
-                    if audio.pa_record.read(&mut drain_buf).is_err() {
-                        break;
-                    }
+                // Check if VOX keyed on audio it just read; draining would cut off the first words
+                if !crate::vox::is_keyed() {
+                    audio.backend.drain_tx();
                 }
+                quantizer.reset();
+                tx_dsp.reset();
             }
 
             // Check if transitioning from TX to RX mode
             if starting_rx {
-                inbound_serial_audio = false;
-                wave_buf.clear();
-                text_buf.clear();
+                decoder.reset();
                 streaming_started.store(false, Ordering::Relaxed);
 
                 thread::sleep(Duration::from_millis(TX_FALLING_DELAY_MS));
@@ -322,6 +497,8 @@ pub fn run_audio_bridge(
 
             // Check if currently in TX mode
             if is_tx {
+                drift.pause();
+                fill.pause();
                 {
                     // Check if CAT queue has pending commands
                     if !cat_queue.lock().unwrap().is_empty() {
@@ -333,37 +510,50 @@ pub fn run_audio_bridge(
                     *input_level.lock().unwrap() = 0.0;
                 }
 
-                match audio.pa_record.read(&mut tx_byte_buf) {
-                    Ok(_) => {
-                        // Convert bytes to i16 samples
-                        bytes_to_i16_le(&tx_byte_buf, &mut tx_i16_buf);
+                // Check if a keyer message is the TX audio rather than applications
+                let block = if keyer.is_sending() {
+                    // Check if the message has been sent in full
+                    if !keyer.next_block(&levels, &mut tx_i16_buf) {
+                        crate::watchdog::force_rx(&ser, &tx_state);
+                        // Application audio that piled up meanwhile must not trip VOX
+                        audio.backend.drain_tx();
+                        continue;
+                    }
+                    Ok(())
+                } else {
+                    audio.backend.read_tx(&mut tx_i16_buf)
+                };
 
+                match block {
+                    Ok(()) => {
                         // Calculate RMS level
                         let rms = calculate_rms(&tx_i16_buf);
                         {
                             *output_level.lock().unwrap() = rms;
                         }
 
-                        // Check if audio level is below minimum threshold
-                        if rms < MIN_RMS_THRESHOLD {
+                        // Check if VOX has heard the hang time of silence
+                        if vox.update(&levels, rms, true, std::time::Instant::now()) == VoxAction::Unkey {
+                            crate::watchdog::force_rx(&ser, &tx_state);
                             continue;
                         }
 
-                        // Convert i16 to u8 with gain and offset
-                        for (index, &sample_value) in tx_i16_buf.iter().enumerate() {
-                            let scaled =
-                                (sample_value as f32 * TX_GAIN).clamp(-32768.0, 32767.0) as i16;
-                            let byte = 128i16 + (scaled / 256);
-                            u8_buf[index] = byte.clamp(0, 255) as u8;
+                        // Check if audio level is below minimum threshold; pauses in a message are sent as they are
+                        if rms < levels.min_rms_threshold && !keyer.is_sending() {
+                            continue;
                         }
 
-                        // Escape semicolons
-                        for byte in &mut u8_buf {
-                            // Check if byte is semicolon (needs escaping)
-                            if *byte == b';' {
-                                *byte = b':';
-                            }
+                        tx_dsp.process(&levels, &tx_i16_buf, &mut tx_f32_buf);
+                        // Check if the transmitted audio is being recorded
+                        if levels.record {
+                            let freq_a = *freq_state.lock().unwrap();
+                            let vfo = *vfo_state.lock().unwrap();
+                            let mode = *mode_state.lock().unwrap();
+                            let result = recorder.tx(&levels, &tx_f32_buf, vfo.freq_of(vfo.tx_vfo, freq_a), mode);
+                            check_recording(result, &mut recorder, &settings);
                         }
+                        // Convert to u8, keeping clear of the `;` terminator
+                        quantizer.convert(levels.tx_quantizer, &tx_f32_buf, &mut u8_buf);
 
                         // Check if serial port lock acquired successfully
                         if let Ok(mut serial_port) = ser.lock() {
@@ -372,6 +562,10 @@ pub fn run_audio_bridge(
                         }
                     }
                     Err(_) => {
+                        // No audio coming in counts as silence for VOX
+                        if vox.update(&levels, 0.0, true, std::time::Instant::now()) == VoxAction::Unkey {
+                            crate::watchdog::force_rx(&ser, &tx_state);
+                        }
                         continue;
                     }
                 }
@@ -386,90 +580,114 @@ pub fn run_audio_bridge(
                 let bytes_read = {
                     // Check if serial port lock acquired successfully
                     if let Ok(mut serial_port) = ser.lock() {
-                        serial_port.read(&mut rx_tmp).unwrap_or(0)
+                        match serial_port.read(&mut rx_tmp) {
+                            Ok(n) => n,
+                            Err(e) => {
+                                crate::reconnect::note_io_error(&e);
+                                0
+                            }
+                        }
                     } else {
                         0
                     }
                 };
 
-                // Process audio
-                for &byte in &rx_tmp[..bytes_read] {
-                    // Check if currently receiving audio stream data
-                    if inbound_serial_audio {
-                        // Check if byte is end-of-frame marker
-                        if byte == b';' {
-                            // End of wave frame
-                            process_and_write_wave(
-                                &wave_buf,
-                                &mut f32_buf,
-                                &audio.pa_playback,
-                                &input_level,
-                            );
-                            wave_buf.clear();
-                            inbound_serial_audio = false;
-                            drain_cat();
-                        } else {
-                            wave_buf.push(byte);
-                            // Check if wave buffer reached maximum size
-                            if wave_buf.len() >= WAVE_BUF_MAX_SIZE {
-                                // Flush partial buffer
-                                process_and_write_wave(
-                                    &wave_buf,
-                                    &mut f32_buf,
-                                    &audio.pa_playback,
-                                    &input_level,
-                                );
-                                wave_buf.clear();
-                            }
-                        }
-                        continue;
-                    }
+                let read_at = std::time::Instant::now();
+                let mut audio_samples = 0;
 
-                    // Text mode processing
-                    text_buf.push(byte);
-                    // Check if received "US" command (start streaming)
-                    if text_buf.len() == 2 && text_buf[0] == b'U' && text_buf[1] == b'S' {
-                        text_buf.clear();
-                        inbound_serial_audio = true;
+                // Process audio and CAT replies
+                decoder.feed(&rx_tmp[..bytes_read], |event| match event {
+                    CatEvent::StreamStart => {
                         streaming_started.store(true, Ordering::Relaxed);
-                        continue;
                     }
-
-                    // Check if byte is command terminator
-                    if byte == b';' {
-                        // Check if command is frequency update (FA)
-                        if text_buf.len() >= 2 && text_buf[0] == b'F' && text_buf[1] == b'A' {
-                            // Check if text buffer is valid UTF-8
-                            if let Ok(text_string) = std::str::from_utf8(&text_buf) {
-                                let digits: String = text_string
-                                    .chars()
-                                    .skip(2)
-                                    .take_while(|character| character.is_ascii_digit())
-                                    .collect();
-                                // Check if frequency digits parse successfully
-                                if let Ok(frequency_value) = digits.parse::<u64>() {
-                                    *freq_state.lock().unwrap() = frequency_value;
+                    CatEvent::Audio { samples, frame_end } => {
+                        audio_samples += samples.len();
+                        process_and_write_wave(
+                            samples,
+                            &mut f32_buf,
+                            &*audio.backend,
+                            &input_level,
+                            &levels,
+                            &mut rx_dsp,
+                            &mut vox,
+                        );
+                        // Check if the received audio is being recorded, as converted and before any processing
+                        if levels.record {
+                            let freq = vfo_state.lock().unwrap().rx_freq(*freq_state.lock().unwrap());
+                            let mode = *mode_state.lock().unwrap();
+                            let result = recorder.rx(&levels, &f32_buf, freq, mode);
+                            check_recording(result, &mut recorder, &settings);
+                        }
+                        // Check if this was the end of a wave frame
+                        if frame_end {
+                            drain_cat();
+                        }
+                    }
+                    CatEvent::Response(response) => {
+                        match response {
+                            CatResponse::FreqA(hz) => *freq_state.lock().unwrap() = hz,
+                            CatResponse::FreqB(hz) => vfo_state.lock().unwrap().freq_b = hz,
+                            CatResponse::RxVfo(vfo) => vfo_state.lock().unwrap().rx_vfo = vfo,
+                            CatResponse::TxVfo(vfo) => vfo_state.lock().unwrap().tx_vfo = vfo,
+                            CatResponse::Mode(mode) => *mode_state.lock().unwrap() = mode,
+                            CatResponse::Info(info) => {
+                                let mut vfo = vfo_state.lock().unwrap();
+                                // Check if the IF reply names the active VFO
+                                if let Some(active) = info.vfo {
+                                    vfo.rx_vfo = active;
+                                    vfo.tx_vfo = if info.split { vfo.split_vfo() } else { active };
+                                }
+                                // IF reports the frequency of the active VFO
+                                match vfo.rx_vfo {
+                                    Vfo::B => vfo.freq_b = info.freq,
+                                    _ => *freq_state.lock().unwrap() = info.freq,
+                                }
+                                // Check if the IF reply carried a known mode
+                                if let Some(mode) = info.mode {
+                                    *mode_state.lock().unwrap() = mode;
                                 }
                             }
+                            _ => {}
                         }
-                        // Check if command is mode update (MD)
-                        if text_buf.len() >= 2 && text_buf[0] == b'M' && text_buf[1] == b'D' {
-                            let mode_str = match text_buf.get(2).copied().unwrap_or(b'2') {
-                                b'1' => "LSB",
-                                b'2' => "USB",
-                                b'3' => "CW",
-                                b'4' => "FM",
-                                b'5' => "AM",
-                                _ => "USB",
-                            };
-                            *mode_state.lock().unwrap() = mode_str.to_string();
-                        }
-                        text_buf.clear();
                         // Check if CAT queue has pending commands
                         if !cat_queue.lock().unwrap().is_empty() {
                             drain_cat();
                         }
                     }
+                });
+
+                // Check if VOX is listening; TX audio is only read while receiving for it
+                if levels.vox {
+                    // Check if TX audio is waiting and came in; receiving must not stall on it
+                    if audio.backend.tx_ready(tx_i16_buf.len()) && audio.backend.read_tx(&mut tx_i16_buf).is_ok() {
+                        let rms = calculate_rms(&tx_i16_buf);
+                        *output_level.lock().unwrap() = rms;
+                        // Check if the audio has been loud enough for long enough to key
+                        if vox.update(&levels, rms, false, std::time::Instant::now()) == VoxAction::Key {
+                            // Check if serial port lock acquired successfully
+                            if let Ok(mut serial_port) = ser.lock() {
+                                let _ = crate::trusdx::start_transmit_baseband(&mut **serial_port);
+                            }
+                            *tx_state.lock().unwrap() = true;
+                        }
+                    }
+                }
+
+                // Check if this read carried RX audio to time the radio clock with
+                if audio_samples > 0 {
+                    drift.feed(audio_samples, read_at);
+                }
+                // Check if the clock estimate is due for a refresh
+                if last_drift_update.elapsed() >= Duration::from_millis(DRIFT_UPDATE_INTERVAL_MS) {
+                    last_drift_update = std::time::Instant::now();
+                    // Check if the device reports its RX buffer level, to hold it steady
+                    let trim = audio.backend.rx_delay().map_or(0.0, |delay| fill.update(delay));
+                    // Check if there is an estimate and it should steer the resampler
+                    if let Some(ppm) = drift.update() {
+                        if levels.clock_tracking {
+                            audio.backend.set_clock_correction(ppm + trim);
+                        }
+                    }
                 }
             }
         }
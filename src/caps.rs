@@ -0,0 +1,243 @@
+// Capability description of the trUSDX as seen through this driver, rendered in hamlib formats
+
+use crate::hamlib::{self, RigCmd};
+
+// hamlib rmode_t bits
+pub const RIG_MODE_AM: u64 = 1 << 0;
+pub const RIG_MODE_CW: u64 = 1 << 1;
+pub const RIG_MODE_USB: u64 = 1 << 2;
+pub const RIG_MODE_LSB: u64 = 1 << 3;
+pub const RIG_MODE_FM: u64 = 1 << 5;
+pub const RIG_MODE_CWR: u64 = 1 << 7;
+pub const RIG_MODE_PKTLSB: u64 = 1 << 10;
+pub const RIG_MODE_PKTUSB: u64 = 1 << 11;
+
+const MODE_NAMES: &[(u64, &str)] = &[
+    (RIG_MODE_AM, "AM"),
+    (RIG_MODE_CW, "CW"),
+    (RIG_MODE_USB, "USB"),
+    (RIG_MODE_LSB, "LSB"),
+    (RIG_MODE_FM, "FM"),
+    (RIG_MODE_CWR, "CWR"),
+    (RIG_MODE_PKTLSB, "PKTLSB"),
+    (RIG_MODE_PKTUSB, "PKTUSB"),
+];
+
+const SSB_MODES: u64 = RIG_MODE_USB | RIG_MODE_LSB | RIG_MODE_PKTUSB | RIG_MODE_PKTLSB;
+const CW_MODES: u64 = RIG_MODE_CW | RIG_MODE_CWR;
+const ALL_MODES: u64 = SSB_MODES | CW_MODES | RIG_MODE_AM | RIG_MODE_FM;
+
+// hamlib vfo_t / ant_t bits
+const RIG_VFO_A: u32 = 1 << 0;
+const RIG_VFO_B: u32 = 1 << 1;
+const RIG_ANT_1: u32 = 1 << 0;
+
+// Model number reported to NET rigctl clients (hamlib's own "NET rigctl" model)
+const RIGCTLD_MODEL: u32 = 2;
+const ITU_REGION: u32 = 2;
+
+pub struct FreqRange {
+    pub start_hz: u64,
+    pub end_hz: u64,
+    pub modes: u64,
+    // -1 for receive ranges, like hamlib
+    pub low_power_mw: i32,
+    pub high_power_mw: i32,
+}
+
+pub struct RigCaps {
+    pub model_name: &'static str,
+    pub mfg_name: &'static str,
+    pub rx_ranges: &'static [FreqRange],
+    pub tx_ranges: &'static [FreqRange],
+    // (modes, step in Hz)
+    pub tuning_steps: &'static [(u64, u64)],
+    // (modes, passband in Hz); the first entry for a mode is its default
+    pub filters: &'static [(u64, u64)],
+    pub get_funcs: &'static [&'static str],
+    pub set_funcs: &'static [&'static str],
+    pub get_levels: &'static [&'static str],
+    pub set_levels: &'static [&'static str],
+}
+
+const fn tx(start_hz: u64, end_hz: u64) -> FreqRange {
+    FreqRange { start_hz, end_hz, modes: ALL_MODES, low_power_mw: 500, high_power_mw: 5000 }
+}
+
+// Amateur bands the trUSDX band filters cover; which ones a given unit can use depends on its filter board
+pub const TRUSDX: RigCaps = RigCaps {
+    model_name: "trUSDX",
+    mfg_name: "DL2MAN/PE1NNZ",
+    rx_ranges: &[FreqRange { start_hz: 500_000, end_hz: 30_000_000, modes: ALL_MODES, low_power_mw: -1, high_power_mw: -1 }],
+    tx_ranges: &[
+        tx(1_810_000, 2_000_000),
+        tx(3_500_000, 4_000_000),
+        tx(5_351_500, 5_366_500),
+        tx(7_000_000, 7_300_000),
+        tx(10_100_000, 10_150_000),
+        tx(14_000_000, 14_350_000),
+        tx(18_068_000, 18_168_000),
+        tx(21_000_000, 21_450_000),
+        tx(24_890_000, 24_990_000),
+        tx(28_000_000, 29_700_000),
+    ],
+    tuning_steps: &[(ALL_MODES, 10), (ALL_MODES, 100), (ALL_MODES, 1_000), (ALL_MODES, 10_000), (ALL_MODES, 100_000)],
+    filters: &[
+        (SSB_MODES, 2_400),
+        (SSB_MODES, 3_000),
+        (SSB_MODES, 1_800),
+        (CW_MODES, 500),
+        (CW_MODES, 200),
+        (CW_MODES, 100),
+        (CW_MODES, 50),
+        (RIG_MODE_AM, 6_000),
+        (RIG_MODE_FM, 12_000),
+    ],
+    get_funcs: &["COMP", "VOX"],
+    set_funcs: &["COMP", "VOX"],
+    get_levels: &["VOXDELAY", "MICGAIN", "COMP", "VOXGAIN", "ANTIVOX"],
+    set_levels: &["VOXDELAY", "MICGAIN", "COMP", "VOXGAIN", "ANTIVOX"],
+};
+
+// hamlib setting_t bit positions for the level/func names we may advertise
+fn level_bit(name: &str) -> u64 {
+    match name {
+        "PREAMP" => 1 << 0,
+        "ATT" => 1 << 1,
+        "VOXDELAY" => 1 << 2,
+        "AF" => 1 << 3,
+        "RF" => 1 << 4,
+        "SQL" => 1 << 5,
+        "RFPOWER" => 1 << 12,
+        "MICGAIN" => 1 << 13,
+        "COMP" => 1 << 16,
+        "AGC" => 1 << 17,
+        "VOXGAIN" => 1 << 21,
+        "ANTIVOX" => 1 << 22,
+        "STRENGTH" => 1 << 30,
+        _ => 0,
+    }
+}
+
+fn func_bit(name: &str) -> u64 {
+    match name {
+        "NB" => 1 << 1,
+        "COMP" => 1 << 2,
+        "VOX" => 1 << 3,
+        "ANF" => 1 << 8,
+        "NR" => 1 << 9,
+        _ => 0,
+    }
+}
+
+fn mask(names: &[&str], bit: fn(&str) -> u64) -> u64 {
+    names.iter().fold(0, |acc, name| acc | bit(name))
+}
+
+fn mode_list(modes: u64) -> String {
+    MODE_NAMES
+        .iter()
+        .filter(|(bit, _)| modes & bit != 0)
+        .map(|(_, name)| *name)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn range_line(range: &FreqRange) -> String {
+    format!(
+        "{} {} 0x{:x} {} {} 0x{:x} 0x{:x}",
+        range.start_hz,
+        range.end_hz,
+        range.modes,
+        range.low_power_mw,
+        range.high_power_mw,
+        RIG_VFO_A | RIG_VFO_B,
+        RIG_ANT_1
+    )
+}
+
+// NET rigctl (model 2) dump_state, protocol version 0
+pub fn dump_state_lines(caps: &RigCaps) -> Vec<String> {
+    let mut lines = vec!["0".to_string(), RIGCTLD_MODEL.to_string(), ITU_REGION.to_string()];
+    lines.extend(caps.rx_ranges.iter().map(range_line));
+    lines.push("0 0 0 0 0 0 0".to_string());
+    lines.extend(caps.tx_ranges.iter().map(range_line));
+    lines.push("0 0 0 0 0 0 0".to_string());
+    lines.extend(caps.tuning_steps.iter().map(|(modes, step)| format!("0x{:x} {}", modes, step)));
+    lines.push("0 0".to_string());
+    lines.extend(caps.filters.iter().map(|(modes, width)| format!("0x{:x} {}", modes, width)));
+    lines.push("0 0".to_string());
+    // max_rit, max_xit, max_ifshift, announces
+    lines.extend(["0", "0", "0", "0"].iter().map(|l| l.to_string()));
+    // preamp and attenuator lists
+    lines.push("0".to_string());
+    lines.push("0".to_string());
+    lines.push(format!("0x{:x}", mask(caps.get_funcs, func_bit)));
+    lines.push(format!("0x{:x}", mask(caps.set_funcs, func_bit)));
+    lines.push(format!("0x{:x}", mask(caps.get_levels, level_bit)));
+    lines.push(format!("0x{:x}", mask(caps.set_levels, level_bit)));
+    // get/set parm
+    lines.push("0x0".to_string());
+    lines.push("0x0".to_string());
+    lines
+}
+
+fn yes_no(supported: bool) -> &'static str {
+    if supported { "Y" } else { "N" }
+}
+
+// Human readable listing in the spirit of `rigctl --dump-caps`
+pub fn dump_caps_lines(caps: &RigCaps) -> Vec<String> {
+    let mut lines = vec![
+        format!("Caps dump for model:\t{}", RIGCTLD_MODEL),
+        format!("Model name:\t{}", caps.model_name),
+        format!("Mfg name:\t{}", caps.mfg_name),
+        format!("Backend version:\t{}", env!("CARGO_PKG_VERSION")),
+        "Rig type:\tTransceiver".to_string(),
+        "PTT type:\tRig capable".to_string(),
+        "DCD type:\tNone".to_string(),
+        "Port type:\tNetwork link".to_string(),
+        format!("Mode list:\t{}", mode_list(ALL_MODES)),
+        "VFO list:\tVFOA VFOB".to_string(),
+        "Max RIT:\t0 Hz".to_string(),
+        "Max XIT:\t0 Hz".to_string(),
+        "Preamp:\tNone".to_string(),
+        "Attenuator:\tNone".to_string(),
+        format!("Get functions:\t{}", caps.get_funcs.join(" ")),
+        format!("Set functions:\t{}", caps.set_funcs.join(" ")),
+        format!("Get level:\t{}", caps.get_levels.join(" ")),
+        format!("Set level:\t{}", caps.set_levels.join(" ")),
+        "Receive frequency range list:".to_string(),
+    ];
+    for range in caps.rx_ranges {
+        lines.push(format!("\t{} Hz - {} Hz", range.start_hz, range.end_hz));
+        lines.push(format!("\t  Mode list: {}", mode_list(range.modes)));
+    }
+    lines.push("Transmit frequency range list:".to_string());
+    for range in caps.tx_ranges {
+        lines.push(format!(
+            "\t{} Hz - {} Hz, power {} - {} mW",
+            range.start_hz, range.end_hz, range.low_power_mw, range.high_power_mw
+        ));
+    }
+    lines.push("Tuning steps:".to_string());
+    for (modes, step) in caps.tuning_steps {
+        lines.push(format!("\t{} Hz:\t{}", step, mode_list(*modes)));
+    }
+    lines.push("Filters:".to_string());
+    for (modes, width) in caps.filters {
+        lines.push(format!("\t{} Hz:\t{}", width, mode_list(*modes)));
+    }
+    for def in hamlib::COMMANDS {
+        let supported = hamlib::is_supported(def.cmd);
+        // Check if this is a get_/set_ command worth listing as a capability
+        if let Some(what) = def.long.strip_prefix("set_") {
+            lines.push(format!("Can set {}:\t{}", what, yes_no(supported)));
+        } else if let Some(what) = def.long.strip_prefix("get_") {
+            lines.push(format!("Can get {}:\t{}", what, yes_no(supported)));
+        } else if def.cmd != RigCmd::Quit {
+            lines.push(format!("Can {}:\t{}", def.long, yes_no(supported)));
+        }
+    }
+    lines
+}
@@ -0,0 +1,456 @@
+// Kenwood TS-480 style CAT subset implemented by the trUSDX, plus its US audio frames
+
+const WAVE_BUF_MAX_SIZE: usize = 512;
+const TEXT_BUF_MAX_SIZE: usize = 64;
+const IF_PAYLOAD_LEN: usize = 35;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mode {
+    Lsb,
+    Usb,
+    Cw,
+    Fm,
+    Am,
+}
+
+impl Mode {
+    pub fn from_code(code: u8) -> Option<Mode> {
+        match code {
+            b'1' => Some(Mode::Lsb),
+            b'2' => Some(Mode::Usb),
+            b'3' => Some(Mode::Cw),
+            b'4' => Some(Mode::Fm),
+            b'5' => Some(Mode::Am),
+            _ => None,
+        }
+    }
+
+    pub fn code(self) -> u8 {
+        match self {
+            Mode::Lsb => b'1',
+            Mode::Usb => b'2',
+            Mode::Cw => b'3',
+            Mode::Fm => b'4',
+            Mode::Am => b'5',
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Mode> {
+        match name {
+            "LSB" => Some(Mode::Lsb),
+            "USB" => Some(Mode::Usb),
+            "CW" => Some(Mode::Cw),
+            "FM" => Some(Mode::Fm),
+            "AM" => Some(Mode::Am),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Mode::Lsb => "LSB",
+            Mode::Usb => "USB",
+            Mode::Cw => "CW",
+            Mode::Fm => "FM",
+            Mode::Am => "AM",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Vfo {
+    A,
+    B,
+    Memory,
+}
+
+impl Vfo {
+    pub fn from_code(code: u8) -> Option<Vfo> {
+        match code {
+            b'0' => Some(Vfo::A),
+            b'1' => Some(Vfo::B),
+            b'2' => Some(Vfo::Memory),
+            _ => None,
+        }
+    }
+
+    pub fn code(self) -> u8 {
+        match self {
+            Vfo::A => b'0',
+            Vfo::B => b'1',
+            Vfo::Memory => b'2',
+        }
+    }
+}
+
+// UA parameter: UA1 streams with the speaker on, UA2 with it muted
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AudioStream {
+    SpeakerOn,
+    SpeakerOff,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CatCommand {
+    GetFreqA,
+    SetFreqA(u64),
+    GetFreqB,
+    SetFreqB(u64),
+    SetMode(Mode),
+    GetInfo,
+    SetRxVfo(Vfo),
+    SetTxVfo(Vfo),
+    Transmit,
+    Receive,
+    SetAudioStream(AudioStream),
+}
+
+impl CatCommand {
+    pub fn encode_into(&self, out: &mut Vec<u8>) {
+        match self {
+            CatCommand::GetFreqA => out.extend_from_slice(b"FA;"),
+            CatCommand::SetFreqA(hz) => out.extend_from_slice(format!("FA{:011};", hz).as_bytes()),
+            CatCommand::GetFreqB => out.extend_from_slice(b"FB;"),
+            CatCommand::SetFreqB(hz) => out.extend_from_slice(format!("FB{:011};", hz).as_bytes()),
+            CatCommand::SetMode(mode) => out.extend_from_slice(&[b'M', b'D', mode.code(), b';']),
+            CatCommand::GetInfo => out.extend_from_slice(b"IF;"),
+            CatCommand::SetRxVfo(vfo) => out.extend_from_slice(&[b'F', b'R', vfo.code(), b';']),
+            CatCommand::SetTxVfo(vfo) => out.extend_from_slice(&[b'F', b'T', vfo.code(), b';']),
+            CatCommand::Transmit => out.extend_from_slice(b"TX0;"),
+            CatCommand::Receive => out.extend_from_slice(b"RX;"),
+            CatCommand::SetAudioStream(stream) => {
+                let code = match stream {
+                    AudioStream::SpeakerOn => b'1',
+                    AudioStream::SpeakerOff => b'2',
+                };
+                out.extend_from_slice(&[b'U', b'A', code, b';']);
+            }
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(16);
+        self.encode_into(&mut out);
+        out
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IfStatus {
+    pub freq: u64,
+    pub tx: bool,
+    pub mode: Option<Mode>,
+    pub vfo: Option<Vfo>,
+    pub split: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CatResponse {
+    FreqA(u64),
+    FreqB(u64),
+    Mode(Mode),
+    Info(IfStatus),
+    Id(u16),
+    RxVfo(Vfo),
+    TxVfo(Vfo),
+    Error,
+    Unknown(String),
+}
+
+fn parse_digits(body: &[u8]) -> Option<u64> {
+    let digits: Vec<u8> = body.iter().copied().take_while(|c| c.is_ascii_digit()).collect();
+    // Check if there is at least one digit to parse
+    if digits.is_empty() { return None; }
+    std::str::from_utf8(&digits).ok()?.parse::<u64>().ok()
+}
+
+fn parse_info(body: &[u8]) -> Option<IfStatus> {
+    // Check if payload is long enough to hold the split flag
+    if body.len() < IF_PAYLOAD_LEN - 4 { return None; }
+    let freq = parse_digits(&body[0..11])?;
+    Some(IfStatus {
+        freq,
+        tx: body[26] == b'1',
+        mode: Mode::from_code(body[27]),
+        vfo: Vfo::from_code(body[28]),
+        split: body[30] == b'1',
+    })
+}
+
+// Parse one ';'-terminated reply, without the terminator
+pub fn parse_response(text: &[u8]) -> CatResponse {
+    let unknown = || CatResponse::Unknown(String::from_utf8_lossy(text).into_owned());
+    // Check if radio rejected the last command
+    if text == b"?" { return CatResponse::Error; }
+    // Check if reply is long enough to carry a two letter command
+    if text.len() < 2 { return unknown(); }
+    let body = &text[2..];
+    let parsed = match &text[..2] {
+        b"FA" => parse_digits(body).map(CatResponse::FreqA),
+        b"FB" => parse_digits(body).map(CatResponse::FreqB),
+        b"MD" => body.first().copied().and_then(Mode::from_code).map(CatResponse::Mode),
+        b"IF" => parse_info(body).map(CatResponse::Info),
+        b"ID" => parse_digits(body).map(|id| CatResponse::Id(id as u16)),
+        b"FR" => body.first().copied().and_then(Vfo::from_code).map(CatResponse::RxVfo),
+        b"FT" => body.first().copied().and_then(Vfo::from_code).map(CatResponse::TxVfo),
+        _ => None,
+    };
+    parsed.unwrap_or_else(unknown)
+}
+
+pub enum CatEvent<'a> {
+    // "US" prefix seen, the radio is streaming RX audio
+    StreamStart,
+    // u8 audio samples; frame_end is set when the closing ';' arrived
+    Audio { samples: &'a [u8], frame_end: bool },
+    Response(CatResponse),
+}
+
+// Incremental decoder splitting the serial byte stream into CAT replies and US audio frames
+pub struct CatDecoder {
+    inbound_audio: bool,
+    text_buf: Vec<u8>,
+    wave_buf: Vec<u8>,
+}
+
+impl Default for CatDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CatDecoder {
+    pub fn new() -> Self {
+        CatDecoder {
+            inbound_audio: false,
+            text_buf: Vec::with_capacity(TEXT_BUF_MAX_SIZE),
+            wave_buf: Vec::with_capacity(WAVE_BUF_MAX_SIZE),
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.inbound_audio = false;
+        self.text_buf.clear();
+        self.wave_buf.clear();
+    }
+
+    pub fn feed(&mut self, bytes: &[u8], mut handler: impl FnMut(CatEvent<'_>)) {
+        for &byte in bytes {
+            // Check if currently receiving audio stream data
+            if self.inbound_audio {
+                // Check if byte is end-of-frame marker
+                if byte == b';' {
+                    handler(CatEvent::Audio { samples: &self.wave_buf, frame_end: true });
+                    self.wave_buf.clear();
+                    self.inbound_audio = false;
+                } else {
+                    self.wave_buf.push(byte);
+                    // Check if wave buffer reached maximum size
+                    if self.wave_buf.len() >= WAVE_BUF_MAX_SIZE {
+                        handler(CatEvent::Audio { samples: &self.wave_buf, frame_end: false });
+                        self.wave_buf.clear();
+                    }
+                }
+                continue;
+            }
+
+            // Check if byte is command terminator
+            if byte == b';' {
+                // Check if there is a reply to parse (stray ';' separators are skipped)
+                if !self.text_buf.is_empty() {
+                    handler(CatEvent::Response(parse_response(&self.text_buf)));
+                }
+                self.text_buf.clear();
+                continue;
+            }
+
+            self.text_buf.push(byte);
+            // Check if received "US" command (start streaming)
+            if self.text_buf.as_slice() == b"US" {
+                self.text_buf.clear();
+                self.inbound_audio = true;
+                handler(CatEvent::StreamStart);
+                continue;
+            }
+            // Check if garbage is piling up without a terminator
+            if self.text_buf.len() >= TEXT_BUF_MAX_SIZE {
+                self.text_buf.clear();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Owned copy of a decoder event, so a whole feed can be compared at once
+    #[derive(Debug, PartialEq, Eq)]
+    enum Event {
+        StreamStart,
+        Audio(Vec<u8>, bool),
+        Response(CatResponse),
+    }
+
+    // Helper function to run a sequence of serial reads through one decoder
+    fn decode(reads: &[&[u8]]) -> Vec<Event> {
+        let mut decoder = CatDecoder::new();
+        let mut events = Vec::new();
+        for read in reads {
+            decoder.feed(read, |event| {
+                events.push(match event {
+                    CatEvent::StreamStart => Event::StreamStart,
+                    CatEvent::Audio { samples, frame_end } => Event::Audio(samples.to_vec(), frame_end),
+                    CatEvent::Response(response) => Event::Response(response),
+                })
+            });
+        }
+        events
+    }
+
+    // Helper function to parse an encoded command the way the radio echoes it back
+    fn echo(command: &CatCommand) -> CatResponse {
+        let encoded = command.encode();
+        assert_eq!(encoded.last(), Some(&b';'));
+        parse_response(&encoded[..encoded.len() - 1])
+    }
+
+    // A TS-480 IF payload: 14.074 MHz, receiving, USB on VFO B with split on
+    const IF_PAYLOAD: &[u8] = b"00014074000     +00000000002101000 ";
+
+    #[test]
+    fn encodes_commands() {
+        let cases: &[(CatCommand, &[u8])] = &[
+            (CatCommand::GetFreqA, b"FA;"),
+            (CatCommand::SetFreqA(14_074_000), b"FA00014074000;"),
+            (CatCommand::GetFreqB, b"FB;"),
+            (CatCommand::SetFreqB(7_030_000), b"FB00007030000;"),
+            (CatCommand::SetMode(Mode::Usb), b"MD2;"),
+            (CatCommand::GetInfo, b"IF;"),
+            (CatCommand::SetRxVfo(Vfo::B), b"FR1;"),
+            (CatCommand::SetTxVfo(Vfo::A), b"FT0;"),
+            (CatCommand::Transmit, b"TX0;"),
+            (CatCommand::Receive, b"RX;"),
+            (CatCommand::SetAudioStream(AudioStream::SpeakerOn), b"UA1;"),
+            (CatCommand::SetAudioStream(AudioStream::SpeakerOff), b"UA2;"),
+        ];
+        for (command, expected) in cases {
+            assert_eq!(command.encode(), *expected, "{:?}", command);
+        }
+    }
+
+    #[test]
+    fn set_commands_round_trip() {
+        assert_eq!(echo(&CatCommand::SetFreqA(14_074_000)), CatResponse::FreqA(14_074_000));
+        assert_eq!(echo(&CatCommand::SetFreqA(99_999_999_999)), CatResponse::FreqA(99_999_999_999));
+        assert_eq!(echo(&CatCommand::SetFreqB(7_030_000)), CatResponse::FreqB(7_030_000));
+        for mode in [Mode::Lsb, Mode::Usb, Mode::Cw, Mode::Fm, Mode::Am] {
+            assert_eq!(echo(&CatCommand::SetMode(mode)), CatResponse::Mode(mode));
+            assert_eq!(Mode::from_name(mode.name()), Some(mode));
+        }
+        for vfo in [Vfo::A, Vfo::B, Vfo::Memory] {
+            assert_eq!(echo(&CatCommand::SetRxVfo(vfo)), CatResponse::RxVfo(vfo));
+            assert_eq!(echo(&CatCommand::SetTxVfo(vfo)), CatResponse::TxVfo(vfo));
+        }
+    }
+
+    #[test]
+    fn parses_responses() {
+        assert_eq!(parse_response(b"FA00014074000"), CatResponse::FreqA(14_074_000));
+        assert_eq!(parse_response(b"FB00007030000"), CatResponse::FreqB(7_030_000));
+        assert_eq!(parse_response(b"MD3"), CatResponse::Mode(Mode::Cw));
+        assert_eq!(parse_response(b"FR1"), CatResponse::RxVfo(Vfo::B));
+        assert_eq!(parse_response(b"FT0"), CatResponse::TxVfo(Vfo::A));
+        assert_eq!(parse_response(b"ID020"), CatResponse::Id(20));
+        assert_eq!(parse_response(b"?"), CatResponse::Error);
+        assert_eq!(parse_response(b"MD9"), CatResponse::Unknown("MD9".to_string()));
+        assert_eq!(parse_response(b"FA"), CatResponse::Unknown("FA".to_string()));
+        assert_eq!(parse_response(b"X"), CatResponse::Unknown("X".to_string()));
+        assert_eq!(parse_response(b"PS1"), CatResponse::Unknown("PS1".to_string()));
+    }
+
+    #[test]
+    fn parses_info() {
+        let mut reply = b"IF".to_vec();
+        reply.extend_from_slice(IF_PAYLOAD);
+        assert_eq!(IF_PAYLOAD.len(), IF_PAYLOAD_LEN);
+        assert_eq!(
+            parse_response(&reply),
+            CatResponse::Info(IfStatus { freq: 14_074_000, tx: false, mode: Some(Mode::Usb), vfo: Some(Vfo::B), split: true })
+        );
+        // Transmitting in CW on VFO A without split
+        reply[2 + 26..2 + 31].copy_from_slice(b"13000");
+        assert_eq!(
+            parse_response(&reply),
+            CatResponse::Info(IfStatus { freq: 14_074_000, tx: true, mode: Some(Mode::Cw), vfo: Some(Vfo::A), split: false })
+        );
+        // Truncated replies are not mistaken for a status
+        assert!(matches!(parse_response(&reply[..20]), CatResponse::Unknown(_)));
+    }
+
+    #[test]
+    fn decodes_replies_split_across_reads() {
+        let mut info = b"IF".to_vec();
+        info.extend_from_slice(IF_PAYLOAD);
+        info.push(b';');
+        let (info_head, info_tail) = info.split_at(17);
+        assert_eq!(
+            decode(&[b"FA000140", b"74000;FB00007", b"030000;;MD", b"2;", info_head, info_tail, b"?;"]),
+            vec![
+                Event::Response(CatResponse::FreqA(14_074_000)),
+                Event::Response(CatResponse::FreqB(7_030_000)),
+                Event::Response(CatResponse::Mode(Mode::Usb)),
+                Event::Response(parse_response(&info[..info.len() - 1])),
+                Event::Response(CatResponse::Error),
+            ]
+        );
+        assert_eq!(
+            decode(&[b"FR", b"1;F", b"T0", b";"]),
+            vec![Event::Response(CatResponse::RxVfo(Vfo::B)), Event::Response(CatResponse::TxVfo(Vfo::A))]
+        );
+    }
+
+    #[test]
+    fn decodes_audio_frames_split_across_reads() {
+        // The "US" prefix itself split, the samples split, and a reply straight after the frame
+        assert_eq!(
+            decode(&[b"U", b"S\x80\x81", b"\x82", b";FA000", b"14074000;"]),
+            vec![
+                Event::StreamStart,
+                Event::Audio(vec![0x80, 0x81, 0x82], true),
+                Event::Response(CatResponse::FreqA(14_074_000)),
+            ]
+        );
+        // Back-to-back frames, the second one empty
+        assert_eq!(
+            decode(&[b"US\x10;US", b";"]),
+            vec![Event::StreamStart, Event::Audio(vec![0x10], true), Event::StreamStart, Event::Audio(vec![], true)]
+        );
+    }
+
+    #[test]
+    fn long_audio_frames_come_out_in_chunks() {
+        let samples: Vec<u8> = (0..WAVE_BUF_MAX_SIZE + 10).map(|i| (i % 251) as u8 + 1).map(|b| if b == b';' { b'<' } else { b }).collect();
+        let mut frame = b"US".to_vec();
+        frame.extend_from_slice(&samples);
+        frame.push(b';');
+        let (head, tail) = frame.split_at(300);
+        assert_eq!(
+            decode(&[head, tail]),
+            vec![
+                Event::StreamStart,
+                Event::Audio(samples[..WAVE_BUF_MAX_SIZE].to_vec(), false),
+                Event::Audio(samples[WAVE_BUF_MAX_SIZE..].to_vec(), true),
+            ]
+        );
+    }
+
+    #[test]
+    fn garbage_without_terminator_is_dropped() {
+        let garbage = vec![b'x'; TEXT_BUF_MAX_SIZE];
+        assert_eq!(
+            decode(&[&garbage, b"MD1;"]),
+            vec![Event::Response(CatResponse::Mode(Mode::Lsb))]
+        );
+    }
+}
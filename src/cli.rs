@@ -1,14 +1,22 @@
 use std::io::Read;
-use std::sync::{Arc, Mutex, atomic::AtomicBool};
+use std::sync::{Arc, Mutex, OnceLock, atomic::AtomicBool};
 use std::thread;
 use std::os::unix::io::AsRawFd;
 
 use serialport;
 
+// Terminal settings from before the ESC handler changed them, restored on any exit path
+static SAVED_TERMINAL: OnceLock<(i32, termios::Termios)> = OnceLock::new();
+
+// TX gain change per key press
+const TX_GAIN_STEP: f32 = 0.1;
+
+// ESC exits; the other keys adjust the TX and RX audio processing
 pub fn spawn_esc_handler(
     shutting_down: Arc<AtomicBool>,
     ser: Arc<Mutex<Box<dyn serialport::SerialPort + Send>>>,
     stop_audio: Arc<Mutex<bool>>,
+    audio_settings: Arc<Mutex<crate::audio::AudioSettings>>,
 ) {
     thread::spawn(move || {
         use termios::*;
@@ -18,7 +26,8 @@ pub fn spawn_esc_handler(
             let fd = tty_file.as_raw_fd();
             // Check if terminal attributes retrieved successfully
             if let Ok(mut term) = Termios::from_fd(fd) {
-                let orig = term.clone();
+                let orig = term;
+                let _ = SAVED_TERMINAL.set((fd, orig));
                 term.c_lflag &= !(ICANON | ECHO);
                 term.c_cc[VMIN] = 1;
                 term.c_cc[VTIME] = 0;
@@ -32,6 +41,28 @@ pub fn spawn_esc_handler(
                             crate::shutdown::shutdown(shutting_down, ser, stop_audio);
                             break;
                         }
+                        let mut settings = audio_settings.lock().unwrap();
+                        match buf[0] {
+                            b'+' | b'=' => {
+                                settings.tx_gain = (settings.tx_gain + TX_GAIN_STEP).min(crate::txdsp::MAX_TX_GAIN);
+                            }
+                            b'-' => settings.tx_gain = (settings.tx_gain - TX_GAIN_STEP).max(0.0),
+                            b'c' => settings.tx_compressor = !settings.tx_compressor,
+                            b'l' => settings.tx_limiter = !settings.tx_limiter,
+                            b'd' => settings.rx_dsp = !settings.rx_dsp,
+                            b'f' => settings.rx_filter = settings.rx_filter.next(),
+                            b'a' => settings.rx_agc = settings.rx_agc.next(),
+                            b'n' => settings.rx_notch = !settings.rx_notch,
+                            b'r' => settings.rx_nr = !settings.rx_nr,
+                            b'v' => settings.vox = !settings.vox,
+                            b'w' => settings.record = !settings.record,
+                            b'k' => settings.keyer_repeat = !settings.keyer_repeat,
+                            b'1'..=b'9' => {
+                                crate::keyer::play((buf[0] - b'0') as usize);
+                            }
+                            b'0' => crate::keyer::stop(),
+                            _ => {}
+                        }
                     }
                 }
                 let _ = tcsetattr(fd, TCSANOW, &orig);
@@ -40,6 +71,14 @@ pub fn spawn_esc_handler(
     });
 }
 
+// The ESC thread may still be blocked in read() when a signal ends the program
+pub fn restore_terminal() {
+    // Check if the ESC handler changed the terminal settings
+    if let Some((fd, orig)) = SAVED_TERMINAL.get() {
+        let _ = termios::tcsetattr(*fd, termios::TCSANOW, orig);
+    }
+}
+
 pub fn print_console_header() {
     
     print!("\x1B[2J\x1B[H");
@@ -49,6 +88,26 @@ pub fn print_console_header() {
     println!("");
     println!("");
     println!("");
+    println!("");
+}
+
+// TX/RX state for the status lines, with what keyed the radio or why it is not transmitting
+pub fn status_text(tx_now: bool, link: crate::reconnect::LinkState) -> String {
+    // Check if the serial link is down so the state shows why nothing moves
+    if link != crate::reconnect::LinkState::Connected {
+        link.as_str().to_string()
+    } else if tx_now && crate::vox::is_keyed() {
+        "TX (VOX)".to_string()
+    } else if let Some((slot, waiting)) = crate::keyer::status() {
+        // Check if the keyer is sending or listening before the next repeat
+        if waiting { format!("RX (MSG {} REPEAT)", slot) } else { format!("TX (MSG {})", slot) }
+    } else if tx_now {
+        "TX".to_string()
+    } else if crate::watchdog::tx_timed_out() {
+        "RX (TX TIMEOUT)".to_string()
+    } else {
+        "RX".to_string()
+    }
 }
 
 pub fn render_levels(
@@ -57,6 +116,8 @@ pub fn render_levels(
     freq_hz: u64,
     mode: &str,
     tx_now: bool,
+    link: crate::reconnect::LinkState,
+    audio: crate::audio::AudioSettings,
 ) {
     fn bar(level: f32) -> String {
         let width = 50usize;
@@ -65,18 +126,48 @@ pub fn render_levels(
         format!("[{}{}]", "#".repeat(filled), "-".repeat(empty))
     }
     
-    print!("\x1B[4F");
+    print!("\x1B[5F");
     print!("\x1B[2K\r");
     println!("INPUT  {} {:5.1}%", bar(input_level), input_level*100.0);
     print!("\x1B[2K\r");
-    println!("OUTPUT {} {:5.1}%", bar(output_level), output_level*100.0);
+    let overload = crate::txdsp::overload_indicator().unwrap_or("");
+    println!("OUTPUT {} {:5.1}% {}", bar(output_level), output_level*100.0, overload);
     print!("\x1B[2K\r");
     let freq_mhz = (freq_hz as f64) / 1_000_000.0f64;
     // Check if RTS line is high or low
     let rts = if crate::trusdx::last_rts_state() { "H" } else { "L" };
-    println!("MODE: {} FREQ: {:.5} MHz STATE: {} RTS:{}", mode, freq_mhz, if tx_now { "TX" } else { "RX" }, rts);
+    let state = status_text(tx_now, link);
+    // Check if the radio clock has been measured yet
+    let clock = match crate::drift::measured_ppm() {
+        Some(ppm) if crate::drift::correction_applied() => format!(" CLOCK: {:+.1} ppm", ppm),
+        Some(ppm) => format!(" CLOCK: {:+.1} ppm (not applied)", ppm),
+        None => String::new(),
+    };
+    // Check if audio is being recorded, and which streams
+    let recording = match (audio.record, audio.record_tx) {
+        (false, _) => String::new(),
+        (true, false) => format!(" REC ({})", audio.record_split.name()),
+        (true, true) => format!(" REC RX+TX ({})", audio.record_split.name()),
+    };
+    println!("MODE: {} FREQ: {:.5} MHz STATE: {} RTS:{}{}{}", mode, freq_mhz, state, rts, clock, recording);
+    print!("\x1B[2K\r");
+    let on_off = |on: bool| if on { "ON" } else { "OFF" };
+    println!(
+        "TX GAIN: {:.2} COMP: {} LIMITER: {} VOX: {}  (+/- gain, c compressor, l limiter, v vox, w record, 1-9 message, 0 stop, k repeat, ESC exit)",
+        audio.tx_gain,
+        on_off(audio.tx_compressor),
+        on_off(audio.tx_limiter),
+        on_off(audio.vox)
+    );
     print!("\x1B[2K\r");
-    println!("Press ESC to exit");
+    println!(
+        "RX DSP: {} FILTER: {} AGC: {} NOTCH: {} NR: {}  (d dsp, f filter, a agc, n notch, r nr)",
+        on_off(audio.rx_dsp),
+        audio.rx_filter.name().to_uppercase(),
+        audio.rx_agc.name().to_uppercase(),
+        on_off(audio.rx_notch),
+        on_off(audio.rx_nr)
+    );
     std::io::Write::flush(&mut std::io::stdout()).ok();
 }
 
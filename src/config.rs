@@ -0,0 +1,197 @@
+// TOML configuration file with named station profiles
+//
+// Top-level keys apply to every profile; a [profiles.<name>] table overrides them,
+// and command-line options override both.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+
+const CONFIG_DIR: &str = "trusdx";
+const CONFIG_FILE: &str = "config.toml";
+
+static RELOAD_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+// Every setting is optional so a layer only overrides what it names
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Settings {
+    pub device: Option<String>,
+    pub usb_id: Option<Vec<String>>,
+    pub usb_serial: Option<String>,
+    pub port: Option<u16>,
+    pub rigctl_bind: Option<Vec<String>>,
+    pub rigctl_port_fallback: Option<u16>,
+    pub rigctl_max_clients: Option<usize>,
+    pub initial_mode: Option<String>,
+    pub initial_freq: Option<u64>,
+    pub audio_backend: Option<String>,
+    pub alsa_rx_device: Option<String>,
+    pub alsa_tx_device: Option<String>,
+    pub device_rate: Option<u32>,
+    pub resample_quality: Option<String>,
+    pub clock_ppm: Option<f64>,
+    pub clock_tracking: Option<bool>,
+    pub sink_name: Option<String>,
+    pub sink_description: Option<String>,
+    pub poll_interval: Option<f64>,
+    pub tx_timeout: Option<f64>,
+    pub tx_gain: Option<f32>,
+    pub min_rms_threshold: Option<f32>,
+    pub tx_quantizer: Option<String>,
+    pub tx_limiter: Option<bool>,
+    pub tx_compressor: Option<bool>,
+    pub tx_compression: Option<f32>,
+    pub rx_dsp: Option<bool>,
+    pub rx_filter: Option<String>,
+    pub rx_agc: Option<String>,
+    pub rx_notch: Option<bool>,
+    pub rx_nr: Option<bool>,
+    pub vox: Option<bool>,
+    pub vox_threshold: Option<f32>,
+    pub vox_attack_ms: Option<u32>,
+    pub vox_hang_ms: Option<u32>,
+    pub vox_anti_trip: Option<f32>,
+    pub record: Option<bool>,
+    pub record_tx: Option<bool>,
+    pub record_split: Option<String>,
+    pub record_dir: Option<String>,
+    pub keyer_messages: Option<Vec<String>>,
+    pub keyer_repeat: Option<bool>,
+    pub keyer_interval: Option<f32>,
+    pub input_level_scale: Option<f32>,
+    pub no_gui: Option<bool>,
+    pub no_tui: Option<bool>,
+}
+
+impl Settings {
+    // Fill every unset value from `base`
+    pub fn or(self, base: Settings) -> Settings {
+        Settings {
+            device: self.device.or(base.device),
+            usb_id: self.usb_id.or(base.usb_id),
+            usb_serial: self.usb_serial.or(base.usb_serial),
+            port: self.port.or(base.port),
+            rigctl_bind: self.rigctl_bind.or(base.rigctl_bind),
+            rigctl_port_fallback: self.rigctl_port_fallback.or(base.rigctl_port_fallback),
+            rigctl_max_clients: self.rigctl_max_clients.or(base.rigctl_max_clients),
+            initial_mode: self.initial_mode.or(base.initial_mode),
+            initial_freq: self.initial_freq.or(base.initial_freq),
+            audio_backend: self.audio_backend.or(base.audio_backend),
+            alsa_rx_device: self.alsa_rx_device.or(base.alsa_rx_device),
+            alsa_tx_device: self.alsa_tx_device.or(base.alsa_tx_device),
+            device_rate: self.device_rate.or(base.device_rate),
+            resample_quality: self.resample_quality.or(base.resample_quality),
+            clock_ppm: self.clock_ppm.or(base.clock_ppm),
+            clock_tracking: self.clock_tracking.or(base.clock_tracking),
+            sink_name: self.sink_name.or(base.sink_name),
+            sink_description: self.sink_description.or(base.sink_description),
+            poll_interval: self.poll_interval.or(base.poll_interval),
+            tx_timeout: self.tx_timeout.or(base.tx_timeout),
+            tx_gain: self.tx_gain.or(base.tx_gain),
+            min_rms_threshold: self.min_rms_threshold.or(base.min_rms_threshold),
+            tx_quantizer: self.tx_quantizer.or(base.tx_quantizer),
+            tx_limiter: self.tx_limiter.or(base.tx_limiter),
+            tx_compressor: self.tx_compressor.or(base.tx_compressor),
+            tx_compression: self.tx_compression.or(base.tx_compression),
+            rx_dsp: self.rx_dsp.or(base.rx_dsp),
+            rx_filter: self.rx_filter.or(base.rx_filter),
+            rx_agc: self.rx_agc.or(base.rx_agc),
+            rx_notch: self.rx_notch.or(base.rx_notch),
+            rx_nr: self.rx_nr.or(base.rx_nr),
+            vox: self.vox.or(base.vox),
+            vox_threshold: self.vox_threshold.or(base.vox_threshold),
+            vox_attack_ms: self.vox_attack_ms.or(base.vox_attack_ms),
+            vox_hang_ms: self.vox_hang_ms.or(base.vox_hang_ms),
+            vox_anti_trip: self.vox_anti_trip.or(base.vox_anti_trip),
+            record: self.record.or(base.record),
+            record_tx: self.record_tx.or(base.record_tx),
+            record_split: self.record_split.or(base.record_split),
+            record_dir: self.record_dir.or(base.record_dir),
+            keyer_messages: self.keyer_messages.or(base.keyer_messages),
+            keyer_repeat: self.keyer_repeat.or(base.keyer_repeat),
+            keyer_interval: self.keyer_interval.or(base.keyer_interval),
+            input_level_scale: self.input_level_scale.or(base.input_level_scale),
+            no_gui: self.no_gui.or(base.no_gui),
+            no_tui: self.no_tui.or(base.no_tui),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ConfigFile {
+    // Profile used when none is given on the command line
+    pub profile: Option<String>,
+    pub base: Settings,
+    pub profiles: HashMap<String, Settings>,
+}
+
+impl ConfigFile {
+    // Base settings overlaid with the named (or default) profile
+    pub fn resolve(mut self, profile: Option<&str>) -> Result<Settings> {
+        let name = profile.map(str::to_string).or(self.profile.take());
+        // Check if a profile was selected at all
+        let Some(name) = name else {
+            return Ok(self.base);
+        };
+        let selected = self.profiles.remove(&name).ok_or_else(|| {
+            let mut known: Vec<&String> = self.profiles.keys().collect();
+            known.sort();
+            let known: Vec<&str> = known.iter().map(|k| k.as_str()).collect();
+            anyhow::anyhow!("Unknown profile '{}' (available: {})", name, if known.is_empty() { "none".to_string() } else { known.join(", ") })
+        })?;
+        Ok(selected.or(self.base))
+    }
+}
+
+// $XDG_CONFIG_HOME/trusdx/config.toml, falling back to ~/.config
+pub fn default_path() -> Option<PathBuf> {
+    let base = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(base.join(CONFIG_DIR).join(CONFIG_FILE))
+}
+
+fn parse(text: &str) -> Result<ConfigFile> {
+    let mut table: toml::Table = toml::from_str(text)?;
+    let profile = match table.remove("profile") {
+        Some(value) => Some(value.try_into::<String>().context("'profile' must be a string")?),
+        None => None,
+    };
+    let profiles = match table.remove("profiles") {
+        Some(value) => value.try_into::<HashMap<String, Settings>>().context("Invalid [profiles] table")?,
+        None => HashMap::new(),
+    };
+    // Whatever is left at the top level is the shared base profile
+    let base = toml::Value::Table(table).try_into::<Settings>()?;
+    Ok(ConfigFile { profile, base, profiles })
+}
+
+// A missing file is only an error when the path was given explicitly
+pub fn load(path: &Path, explicit: bool) -> Result<ConfigFile> {
+    match std::fs::read_to_string(path) {
+        Ok(text) => parse(&text).with_context(|| format!("Invalid config file {}", path.display())),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound && !explicit => Ok(ConfigFile::default()),
+        Err(e) => Err(e).with_context(|| format!("Cannot read config file {}", path.display())),
+    }
+}
+
+extern "C" fn on_sighup(_: libc::c_int) {
+    RELOAD_REQUESTED.store(true, Ordering::Relaxed);
+}
+
+pub fn install_reload_handler() {
+    // SAFETY: the handler only stores to an atomic, which is async-signal-safe
+    unsafe {
+        libc::signal(libc::SIGHUP, on_sighup as extern "C" fn(libc::c_int) as libc::sighandler_t);
+    }
+}
+
+// True once per received SIGHUP
+pub fn take_reload_request() -> bool {
+    RELOAD_REQUESTED.swap(false, Ordering::Relaxed)
+}
@@ -0,0 +1,316 @@
+// Radio sample clock measurement from the RX audio byte count
+//
+// Every RX read adds a (time, samples received) point. The rate is the slope of a least-squares
+// fit pooled over segments of continuous streaming, so TX periods and reconnects only start a
+// new segment instead of throwing the measurement away.
+//
+// The estimate is against the computer's clock. FillTracker adds a slow trim from the RX device
+// buffer level, which also catches a sound card running off its own crystal.
+
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+// A new segment starts after this long without RX audio
+const MAX_GAP: Duration = Duration::from_millis(500);
+// Long segments are cut so old data can age out
+const MAX_SEGMENT: Duration = Duration::from_secs(60);
+const MAX_SEGMENTS: usize = 10;
+// Seconds of streaming needed before the first estimate
+const MIN_MEASURED_SECS: f64 = 30.0;
+// Anything further off means the configured rate is wrong, not the crystal
+const MAX_PPM: f64 = 2000.0;
+// RX buffer level smoothing per update, and how many updates it gets to settle after streaming
+// (re)starts before the level to hold is taken
+const FILL_SMOOTHING: f64 = 0.2;
+const FILL_SETTLE_UPDATES: u32 = 10;
+// Trim per millisecond of buffer level error; at one update a second this pulls the level back
+// with a time constant of about 100 s
+const FILL_GAIN_PPM_PER_MS: f64 = 10.0;
+const MAX_FILL_TRIM_PPM: f64 = 200.0;
+
+// f64 bits of the last estimate; NaN until there is one
+static MEASURED_PPM: AtomicU64 = AtomicU64::new(0x7ff8_0000_0000_0000);
+
+// Cleared when the audio path has no resampler to apply the correction with
+static CORRECTION_APPLIED: AtomicBool = AtomicBool::new(true);
+
+pub fn measured_ppm() -> Option<f64> {
+    let ppm = f64::from_bits(MEASURED_PPM.load(Ordering::Relaxed));
+    Some(ppm).filter(|ppm| !ppm.is_nan())
+}
+
+pub fn set_correction_applied(applied: bool) {
+    CORRECTION_APPLIED.store(applied, Ordering::Relaxed);
+}
+
+pub fn correction_applied() -> bool {
+    CORRECTION_APPLIED.load(Ordering::Relaxed)
+}
+
+// Least-squares sums of one segment, relative to its first point
+#[derive(Default)]
+struct Segment {
+    n: f64,
+    t: f64,
+    s: f64,
+    tt: f64,
+    ts: f64,
+    // Seconds covered
+    span: f64,
+}
+
+impl Segment {
+    fn add(&mut self, t: f64, s: f64) {
+        self.n += 1.0;
+        self.t += t;
+        self.s += s;
+        self.tt += t * t;
+        self.ts += t * s;
+        self.span = t;
+    }
+
+    // (covariance, variance) sums around the segment's own means
+    fn centred(&self) -> (f64, f64) {
+        // Check if the segment has enough points for a slope
+        if self.n < 2.0 {
+            return (0.0, 0.0);
+        }
+        (self.ts - self.t * self.s / self.n, self.tt - self.t * self.t / self.n)
+    }
+}
+
+pub struct DriftEstimator {
+    nominal_rate: f64,
+    segments: VecDeque<Segment>,
+    // Start of the current segment, and samples received since
+    segment_start: Option<Instant>,
+    segment_samples: u64,
+    last_audio: Option<Instant>,
+}
+
+impl DriftEstimator {
+    pub fn new(nominal_rate: u32) -> DriftEstimator {
+        DriftEstimator {
+            nominal_rate: nominal_rate as f64,
+            segments: VecDeque::new(),
+            segment_start: None,
+            segment_samples: 0,
+            last_audio: None,
+        }
+    }
+
+    // Streaming stopped (TX, reconnect); the next audio starts a new segment
+    pub fn pause(&mut self) {
+        self.segment_start = None;
+    }
+
+    // Count `samples` received at `now`
+    pub fn feed(&mut self, samples: usize, now: Instant) {
+        // Check if streaming was interrupted since the last audio
+        if self.last_audio.is_some_and(|last| now.duration_since(last) > MAX_GAP) {
+            self.segment_start = None;
+        }
+        self.last_audio = Some(now);
+        // Check if this audio starts a new segment
+        let start = match self.segment_start {
+            Some(start) if now.duration_since(start) < MAX_SEGMENT => start,
+            _ => {
+                self.segment_start = Some(now);
+                self.segment_samples = 0;
+                self.segments.push_back(Segment::default());
+                // Check if the oldest segment has aged out
+                if self.segments.len() > MAX_SEGMENTS {
+                    self.segments.pop_front();
+                }
+                now
+            }
+        };
+        self.segment_samples += samples as u64;
+        let t = now.duration_since(start).as_secs_f64();
+        if let Some(segment) = self.segments.back_mut() {
+            segment.add(t, self.segment_samples as f64);
+        }
+    }
+
+    // Offset of the radio clock in ppm, once enough streaming has been seen
+    pub fn estimate(&self) -> Option<f64> {
+        let measured: f64 = self.segments.iter().map(|segment| segment.span).sum();
+        // Check if there is enough data for a meaningful slope
+        if measured < MIN_MEASURED_SECS {
+            return None;
+        }
+        let (cov, var) = self
+            .segments
+            .iter()
+            .map(Segment::centred)
+            .fold((0.0, 0.0), |(cov, var), (c, v)| (cov + c, var + v));
+        // Check if the points were spread out in time at all
+        if var <= 0.0 {
+            return None;
+        }
+        let ppm = (cov / var / self.nominal_rate - 1.0) * 1e6;
+        Some(ppm).filter(|ppm| ppm.abs() <= MAX_PPM)
+    }
+
+    // Estimate, also published for the status display
+    pub fn update(&self) -> Option<f64> {
+        let ppm = self.estimate()?;
+        MEASURED_PPM.store(ppm.to_bits(), Ordering::Relaxed);
+        Some(ppm)
+    }
+}
+
+// Holds the RX device buffer at the level it settled at, by trimming the clock correction
+pub struct FillTracker {
+    // Smoothed RX delay in seconds, and the level to hold
+    level: Option<f64>,
+    target: Option<f64>,
+    updates: u32,
+}
+
+impl Default for FillTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FillTracker {
+    pub fn new() -> FillTracker {
+        FillTracker { level: None, target: None, updates: 0 }
+    }
+
+    // Streaming stopped; the buffer runs dry meanwhile, so the level to hold is taken afresh
+    pub fn pause(&mut self) {
+        *self = FillTracker::new();
+    }
+
+    // Take one RX delay reading; returns the trim in ppm to add to the clock estimate
+    pub fn update(&mut self, delay: Duration) -> f64 {
+        let delay = delay.as_secs_f64();
+        let level = match self.level {
+            Some(level) => level + (delay - level) * FILL_SMOOTHING,
+            None => delay,
+        };
+        self.level = Some(level);
+        // Check if the buffer is still settling after streaming started
+        if self.updates < FILL_SETTLE_UPDATES {
+            self.updates += 1;
+            return 0.0;
+        }
+        let target = *self.target.get_or_insert(level);
+        // A filling buffer means the radio delivers faster than the device plays
+        ((level - target) * 1000.0 * FILL_GAIN_PPM_PER_MS).clamp(-MAX_FILL_TRIM_PPM, MAX_FILL_TRIM_PPM)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn estimates_radio_clock_offset() {
+        let rate = 7812.0 * (1.0 + 25e-6);
+        let mut drift = DriftEstimator::new(7812);
+        let start = Instant::now();
+        // 45 s of 512-sample reads, with a TX gap in the middle
+        for read in 0..700 {
+            let t = read as f64 * 512.0 / rate + if read >= 350 { 5.0 } else { 0.0 };
+            // Check if this read is the first after the gap
+            if read == 350 {
+                drift.pause();
+            }
+            drift.feed(512, start + Duration::from_secs_f64(t));
+        }
+        let ppm = drift.estimate().expect("enough data for an estimate");
+        assert!((ppm - 25.0).abs() < 0.5, "estimated {} ppm", ppm);
+    }
+
+    #[test]
+    fn no_estimate_before_enough_audio() {
+        let mut drift = DriftEstimator::new(7812);
+        let start = Instant::now();
+        for read in 0..100 {
+            drift.feed(512, start + Duration::from_secs_f64(read as f64 * 512.0 / 7812.0));
+        }
+        assert_eq!(drift.estimate(), None);
+    }
+
+    #[test]
+    fn fill_trim_holds_the_buffer_level() {
+        // Sound card 150 ppm slower than the radio, one update a second
+        let mismatch_ppm = 150.0;
+        let mut fill = FillTracker::new();
+        let mut delay = 0.1f64;
+        let mut trim = 0.0;
+        let mut levels = Vec::new();
+        for _ in 0..2000 {
+            trim = fill.update(Duration::from_secs_f64(delay));
+            delay += (mismatch_ppm - trim) * 1e-6;
+            levels.push(delay);
+        }
+        assert!((trim - mismatch_ppm).abs() < 1.0, "trim {} ppm", trim);
+        // Without the trim the buffer would have grown by 300 ms
+        let spread = levels.iter().fold(0.0f64, |max, level| max.max(level - 0.1));
+        assert!(spread < 0.02, "buffer level moved {} s", spread);
+        // Streaming restarts: the level is taken afresh and the trim starts from zero
+        fill.pause();
+        assert_eq!(fill.update(Duration::from_millis(300)), 0.0);
+    }
+
+    #[test]
+    fn fill_trim_is_limited() {
+        let mut fill = FillTracker::new();
+        for _ in 0..=FILL_SETTLE_UPDATES {
+            fill.update(Duration::from_millis(50));
+        }
+        assert_eq!(fill.update(Duration::from_secs(5)), MAX_FILL_TRIM_PPM);
+    }
+
+    // Helper function to run both estimators against a simulated radio and sound card, both
+    // offset from the computer clock; returns the last trim and the buffer level each second
+    fn simulate(radio_ppm: f64, card_ppm: f64, secs: usize) -> (f64, Vec<f64>) {
+        let rate = 7812.0 * (1.0 + radio_ppm * 1e-6);
+        let mut drift = DriftEstimator::new(7812);
+        let mut fill = FillTracker::new();
+        let start = Instant::now();
+        let mut received = 0.0;
+        let mut delay = 0.1f64;
+        let mut trim = 0.0;
+        let mut levels = Vec::new();
+        for second in 1..=secs {
+            // 512-sample reads as they arrive during this second
+            while (received + 512.0) / rate <= second as f64 {
+                received += 512.0;
+                drift.feed(512, start + Duration::from_secs_f64(received / rate));
+            }
+            trim = fill.update(Duration::from_secs_f64(delay));
+            let correction = drift.estimate().unwrap_or(0.0) + trim;
+            // The resampler's output runs at radio - correction, the card plays at its own speed
+            delay += (radio_ppm - correction - card_ppm) * 1e-6;
+            levels.push(delay);
+        }
+        (trim, levels)
+    }
+
+    #[test]
+    fn drift_and_fill_hold_the_buffer_together() {
+        // Radio 40 ppm fast, sound card 120 ppm slow against the computer clock
+        let (trim, levels) = simulate(40.0, -120.0, 1500);
+        assert!((trim - 120.0).abs() < 2.0, "trim {} ppm", trim);
+        let spread = levels.iter().fold(0.0f64, |max, level| max.max((level - 0.1).abs()));
+        assert!(spread < 0.02, "buffer level moved {} s", spread);
+        let last = &levels[levels.len() - 100..];
+        assert!(last.iter().all(|level| (level - last[0]).abs() < 0.001));
+    }
+
+    #[test]
+    fn buffer_drifts_by_what_the_trim_cannot_cover() {
+        // Sound card 350 ppm slow: the trim stops at its limit and the buffer creeps at 150 ppm
+        let (trim, levels) = simulate(40.0, -350.0, 1500);
+        assert_eq!(trim, MAX_FILL_TRIM_PPM);
+        let n = levels.len();
+        let slope_ppm = (levels[n - 1] - levels[n - 501]) / 500.0 * 1e6;
+        assert!((slope_ppm - 150.0).abs() < 5.0, "buffer grows {} ppm", slope_ppm);
+    }
+}
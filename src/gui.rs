@@ -3,7 +3,11 @@ use std::thread;
 use std::time::Duration;
 
 use gtk::prelude::*;
-use gtk::{Builder, Label, ProgressBar, Window};
+use gtk::{Builder, Button, CheckButton, ComboBoxText, Frame, Label, ProgressBar, Scale, Window};
+
+use crate::audio::AudioSettings;
+use crate::record::RecordSplit;
+use crate::rxdsp::{RxAgc, RxFilter};
 
 const GLADE_UI: &str = include_str!("gui.glade");
 
@@ -18,8 +22,10 @@ pub fn setup_gui(
     ser: Arc<Mutex<Box<dyn serialport::SerialPort + Send>>>,
     stop_audio: Arc<Mutex<bool>>,
     freq_state: Arc<Mutex<u64>>,
-    mode_state: Arc<Mutex<String>>,
+    vfo_state: Arc<Mutex<crate::trusdx::VfoState>>,
+    mode_state: Arc<Mutex<crate::cat::Mode>>,
     tx_state: Arc<Mutex<bool>>,
+    audio_settings: Arc<Mutex<AudioSettings>>,
 ) -> Result<(), String> {
     let display = std::env::var("DISPLAY").ok();
     let wayland = std::env::var("WAYLAND_DISPLAY").ok();
@@ -57,6 +63,191 @@ pub fn setup_gui(
         .object("lblState")
         .ok_or("Could not find lblState label in glade file")?;
     
+    let scale_tx_gain: Scale = builder
+        .object("scaleTxGain")
+        .ok_or("Could not find scaleTxGain scale in glade file")?;
+
+    let chk_compressor: CheckButton = builder
+        .object("chkCompressor")
+        .ok_or("Could not find chkCompressor check button in glade file")?;
+
+    let scale_compression: Scale = builder
+        .object("scaleCompression")
+        .ok_or("Could not find scaleCompression scale in glade file")?;
+
+    let chk_limiter: CheckButton = builder
+        .object("chkLimiter")
+        .ok_or("Could not find chkLimiter check button in glade file")?;
+
+    sync_tx_controls(&audio_settings.lock().unwrap(), &scale_tx_gain, &chk_compressor, &scale_compression, &chk_limiter);
+    let settings_for_gain = audio_settings.clone();
+    scale_tx_gain.connect_value_changed(move |scale| {
+        settings_for_gain.lock().unwrap().tx_gain = scale.value() as f32;
+    });
+    let settings_for_compressor = audio_settings.clone();
+    chk_compressor.connect_toggled(move |check| {
+        settings_for_compressor.lock().unwrap().tx_compressor = check.is_active();
+    });
+    let settings_for_compression = audio_settings.clone();
+    scale_compression.connect_value_changed(move |scale| {
+        settings_for_compression.lock().unwrap().tx_compression = scale.value() as f32;
+    });
+    let settings_for_limiter = audio_settings.clone();
+    chk_limiter.connect_toggled(move |check| {
+        settings_for_limiter.lock().unwrap().tx_limiter = check.is_active();
+    });
+
+    let chk_rx_dsp: CheckButton = builder
+        .object("chkRxDsp")
+        .ok_or("Could not find chkRxDsp check button in glade file")?;
+
+    let combo_rx_filter: ComboBoxText = builder
+        .object("comboRxFilter")
+        .ok_or("Could not find comboRxFilter combo box in glade file")?;
+
+    let combo_rx_agc: ComboBoxText = builder
+        .object("comboRxAgc")
+        .ok_or("Could not find comboRxAgc combo box in glade file")?;
+
+    let chk_rx_notch: CheckButton = builder
+        .object("chkRxNotch")
+        .ok_or("Could not find chkRxNotch check button in glade file")?;
+
+    let chk_rx_nr: CheckButton = builder
+        .object("chkRxNr")
+        .ok_or("Could not find chkRxNr check button in glade file")?;
+
+    sync_rx_controls(&audio_settings.lock().unwrap(), &chk_rx_dsp, &combo_rx_filter, &combo_rx_agc, &chk_rx_notch, &chk_rx_nr);
+    let settings_for_rx_dsp = audio_settings.clone();
+    chk_rx_dsp.connect_toggled(move |check| {
+        settings_for_rx_dsp.lock().unwrap().rx_dsp = check.is_active();
+    });
+    let settings_for_rx_filter = audio_settings.clone();
+    combo_rx_filter.connect_changed(move |combo| {
+        // Check if the selected entry names a filter
+        if let Some(filter) = combo.active_id().and_then(|id| RxFilter::from_name(&id)) {
+            settings_for_rx_filter.lock().unwrap().rx_filter = filter;
+        }
+    });
+    let settings_for_rx_agc = audio_settings.clone();
+    combo_rx_agc.connect_changed(move |combo| {
+        // Check if the selected entry names an AGC speed
+        if let Some(agc) = combo.active_id().and_then(|id| RxAgc::from_name(&id)) {
+            settings_for_rx_agc.lock().unwrap().rx_agc = agc;
+        }
+    });
+    let settings_for_rx_notch = audio_settings.clone();
+    chk_rx_notch.connect_toggled(move |check| {
+        settings_for_rx_notch.lock().unwrap().rx_notch = check.is_active();
+    });
+    let settings_for_rx_nr = audio_settings.clone();
+    chk_rx_nr.connect_toggled(move |check| {
+        settings_for_rx_nr.lock().unwrap().rx_nr = check.is_active();
+    });
+
+    let chk_vox: CheckButton = builder
+        .object("chkVox")
+        .ok_or("Could not find chkVox check button in glade file")?;
+
+    let scale_vox_threshold: Scale = builder
+        .object("scaleVoxThreshold")
+        .ok_or("Could not find scaleVoxThreshold scale in glade file")?;
+
+    let scale_vox_hang: Scale = builder
+        .object("scaleVoxHang")
+        .ok_or("Could not find scaleVoxHang scale in glade file")?;
+
+    sync_vox_controls(&audio_settings.lock().unwrap(), &chk_vox, &scale_vox_threshold, &scale_vox_hang);
+    let settings_for_vox = audio_settings.clone();
+    chk_vox.connect_toggled(move |check| {
+        settings_for_vox.lock().unwrap().vox = check.is_active();
+    });
+    let settings_for_vox_threshold = audio_settings.clone();
+    scale_vox_threshold.connect_value_changed(move |scale| {
+        settings_for_vox_threshold.lock().unwrap().vox_threshold = scale.value() as f32;
+    });
+    let settings_for_vox_hang = audio_settings.clone();
+    scale_vox_hang.connect_value_changed(move |scale| {
+        settings_for_vox_hang.lock().unwrap().vox_hang_ms = scale.value().round() as u32;
+    });
+
+    let chk_record: CheckButton = builder
+        .object("chkRecord")
+        .ok_or("Could not find chkRecord check button in glade file")?;
+
+    let chk_record_tx: CheckButton = builder
+        .object("chkRecordTx")
+        .ok_or("Could not find chkRecordTx check button in glade file")?;
+
+    let combo_record_split: ComboBoxText = builder
+        .object("comboRecordSplit")
+        .ok_or("Could not find comboRecordSplit combo box in glade file")?;
+
+    sync_record_controls(&audio_settings.lock().unwrap(), &chk_record, &chk_record_tx, &combo_record_split);
+    let settings_for_record = audio_settings.clone();
+    chk_record.connect_toggled(move |check| {
+        settings_for_record.lock().unwrap().record = check.is_active();
+    });
+    let settings_for_record_tx = audio_settings.clone();
+    chk_record_tx.connect_toggled(move |check| {
+        settings_for_record_tx.lock().unwrap().record_tx = check.is_active();
+    });
+    let settings_for_record_split = audio_settings.clone();
+    combo_record_split.connect_changed(move |combo| {
+        // Check if the selected entry names a split
+        if let Some(split) = combo.active_id().and_then(|id| RecordSplit::from_name(&id)) {
+            settings_for_record_split.lock().unwrap().record_split = split;
+        }
+    });
+
+    let frame_keyer: Frame = builder
+        .object("frameKeyer")
+        .ok_or("Could not find frameKeyer frame in glade file")?;
+
+    let combo_keyer_slot: ComboBoxText = builder
+        .object("comboKeyerSlot")
+        .ok_or("Could not find comboKeyerSlot combo box in glade file")?;
+
+    let btn_keyer_play: Button = builder
+        .object("btnKeyerPlay")
+        .ok_or("Could not find btnKeyerPlay button in glade file")?;
+
+    let btn_keyer_stop: Button = builder
+        .object("btnKeyerStop")
+        .ok_or("Could not find btnKeyerStop button in glade file")?;
+
+    let chk_keyer_repeat: CheckButton = builder
+        .object("chkKeyerRepeat")
+        .ok_or("Could not find chkKeyerRepeat check button in glade file")?;
+
+    let scale_keyer_interval: Scale = builder
+        .object("scaleKeyerInterval")
+        .ok_or("Could not find scaleKeyerInterval scale in glade file")?;
+
+    let message_names = crate::keyer::message_names();
+    for (i, name) in message_names.iter().enumerate() {
+        combo_keyer_slot.append_text(&format!("{}: {}", i + 1, name));
+    }
+    combo_keyer_slot.set_active(Some(0));
+    sync_keyer_controls(&audio_settings.lock().unwrap(), &chk_keyer_repeat, &scale_keyer_interval);
+    btn_keyer_play.connect_clicked(move |_| {
+        // Check if a message is selected
+        if let Some(index) = combo_keyer_slot.active() {
+            crate::keyer::play(index as usize + 1);
+        }
+    });
+    btn_keyer_stop.connect_clicked(|_| {
+        crate::keyer::stop();
+    });
+    let settings_for_keyer_repeat = audio_settings.clone();
+    chk_keyer_repeat.connect_toggled(move |check| {
+        settings_for_keyer_repeat.lock().unwrap().keyer_repeat = check.is_active();
+    });
+    let settings_for_keyer_interval = audio_settings.clone();
+    scale_keyer_interval.connect_value_changed(move |scale| {
+        settings_for_keyer_interval.lock().unwrap().keyer_interval = scale.value() as f32;
+    });
+
     prog_tx_level.set_show_text(true);
     prog_rx_level.set_show_text(true);
     
@@ -76,11 +267,16 @@ pub fn setup_gui(
     });
     
     window.show_all();
+    // Check if any messages were loaded; without them the keyer has nothing to offer
+    if message_names.is_empty() {
+        frame_keyer.hide();
+    }
     
     let shutting_down_for_timeout = shutting_down.clone();
     let input_level_for_timeout = input_level.clone();
     let output_level_for_timeout = output_level.clone();
     let freq_state_for_timeout = freq_state.clone();
+    let vfo_state_for_timeout = vfo_state.clone();
     let mode_state_for_timeout = mode_state.clone();
     let tx_state_for_timeout = tx_state.clone();
     let prog_tx_for_timeout = prog_tx_level.clone();
@@ -103,18 +299,38 @@ pub fn setup_gui(
         prog_rx_for_timeout.set_text(Some(&rx_text));
         
         prog_tx_for_timeout.set_fraction(out_lvl.clamp(0.0, 1.0) as f64);
-        let tx_text = format!("{:.1}%", out_lvl * 100.0);
+        // Check if TX audio recently went over full scale
+        let tx_text = match crate::txdsp::overload_indicator() {
+            Some(overload) => format!("{:.1}% {}", out_lvl * 100.0, overload),
+            None => format!("{:.1}%", out_lvl * 100.0),
+        };
         prog_tx_for_timeout.set_text(Some(&tx_text));
+
+        // Follow changes made from the console, rigctl or a config reload
+        let settings = *audio_settings.lock().unwrap();
+        sync_tx_controls(&settings, &scale_tx_gain, &chk_compressor, &scale_compression, &chk_limiter);
+        sync_rx_controls(&settings, &chk_rx_dsp, &combo_rx_filter, &combo_rx_agc, &chk_rx_notch, &chk_rx_nr);
+        sync_vox_controls(&settings, &chk_vox, &scale_vox_threshold, &scale_vox_hang);
+        sync_record_controls(&settings, &chk_record, &chk_record_tx, &combo_record_split);
+        sync_keyer_controls(&settings, &chk_keyer_repeat, &scale_keyer_interval);
         
-        let freq = *freq_state_for_timeout.lock().unwrap();
-        let mode = mode_state_for_timeout.lock().unwrap().clone();
+        let freq_a = *freq_state_for_timeout.lock().unwrap();
+        let vfo = *vfo_state_for_timeout.lock().unwrap();
+        let freq = vfo.rx_freq(freq_a);
+        let mode = *mode_state_for_timeout.lock().unwrap();
         let tx_now = *tx_state_for_timeout.lock().unwrap();
         let freq_mhz = (freq as f64) / 1_000_000.0f64;
         let freq_text = format!("{:.5} MHz", freq_mhz);
         lbl_freq_for_timeout.set_text(&freq_text);
-        lbl_mode_for_timeout.set_text(&mode);
-        let state_text = if tx_now { "TX" } else { "RX" };
-        lbl_state_for_timeout.set_text(state_text);
+        lbl_mode_for_timeout.set_text(mode.name());
+        let state_text = crate::cli::status_text(tx_now, crate::reconnect::link_state());
+        // Check if split is active to show the TX frequency alongside
+        if vfo.split() {
+            let tx_mhz = (vfo.freq_of(vfo.tx_vfo, freq_a) as f64) / 1_000_000.0f64;
+            lbl_state_for_timeout.set_text(&format!("{} SPLIT {:.5}", state_text, tx_mhz));
+        } else {
+            lbl_state_for_timeout.set_text(&state_text);
+        }
         
         glib::ControlFlow::Continue
     });
@@ -124,6 +340,86 @@ pub fn setup_gui(
     Ok(())
 }
 
+// Helper function to show the TX chain settings without echoing unchanged values back
+fn sync_tx_controls(settings: &AudioSettings, gain: &Scale, compressor: &CheckButton, compression: &Scale, limiter: &CheckButton) {
+    // Check if each control is out of date before touching it; gains above the slider stay untouched
+    let tx_gain = settings.tx_gain.min(crate::txdsp::MAX_TX_GAIN) as f64;
+    if (gain.value() - tx_gain).abs() > 1e-3 {
+        gain.set_value(tx_gain);
+    }
+    if compressor.is_active() != settings.tx_compressor {
+        compressor.set_active(settings.tx_compressor);
+    }
+    if (compression.value() - settings.tx_compression as f64).abs() > 1e-3 {
+        compression.set_value(settings.tx_compression as f64);
+    }
+    if limiter.is_active() != settings.tx_limiter {
+        limiter.set_active(settings.tx_limiter);
+    }
+}
+
+// Helper function to show the RX processing settings, like sync_tx_controls
+fn sync_rx_controls(settings: &AudioSettings, dsp: &CheckButton, filter: &ComboBoxText, agc: &ComboBoxText, notch: &CheckButton, nr: &CheckButton) {
+    // Check if each control is out of date before touching it
+    if dsp.is_active() != settings.rx_dsp {
+        dsp.set_active(settings.rx_dsp);
+    }
+    if filter.active_id().as_deref() != Some(settings.rx_filter.name()) {
+        filter.set_active_id(Some(settings.rx_filter.name()));
+    }
+    if agc.active_id().as_deref() != Some(settings.rx_agc.name()) {
+        agc.set_active_id(Some(settings.rx_agc.name()));
+    }
+    if notch.is_active() != settings.rx_notch {
+        notch.set_active(settings.rx_notch);
+    }
+    if nr.is_active() != settings.rx_nr {
+        nr.set_active(settings.rx_nr);
+    }
+}
+
+// Helper function to show the VOX settings; values beyond the sliders stay untouched
+fn sync_vox_controls(settings: &AudioSettings, vox: &CheckButton, threshold: &Scale, hang: &Scale) {
+    // Check if each control is out of date before touching it
+    if vox.is_active() != settings.vox {
+        vox.set_active(settings.vox);
+    }
+    let vox_threshold = (settings.vox_threshold as f64).min(threshold.adjustment().upper());
+    if (threshold.value() - vox_threshold).abs() > 1e-4 {
+        threshold.set_value(vox_threshold);
+    }
+    let vox_hang = (settings.vox_hang_ms as f64).min(hang.adjustment().upper());
+    if (hang.value() - vox_hang).abs() >= 1.0 {
+        hang.set_value(vox_hang);
+    }
+}
+
+// Helper function to show the recording settings
+fn sync_record_controls(settings: &AudioSettings, record: &CheckButton, record_tx: &CheckButton, split: &ComboBoxText) {
+    // Check if each control is out of date before touching it
+    if record.is_active() != settings.record {
+        record.set_active(settings.record);
+    }
+    if record_tx.is_active() != settings.record_tx {
+        record_tx.set_active(settings.record_tx);
+    }
+    if split.active_id().as_deref() != Some(settings.record_split.name()) {
+        split.set_active_id(Some(settings.record_split.name()));
+    }
+}
+
+// Helper function to show the keyer settings; intervals beyond the slider stay untouched
+fn sync_keyer_controls(settings: &AudioSettings, repeat: &CheckButton, interval: &Scale) {
+    // Check if each control is out of date before touching it
+    if repeat.is_active() != settings.keyer_repeat {
+        repeat.set_active(settings.keyer_repeat);
+    }
+    let keyer_interval = (settings.keyer_interval as f64).min(interval.adjustment().upper());
+    if (interval.value() - keyer_interval).abs() > 0.05 {
+        interval.set_value(keyer_interval);
+    }
+}
+
 pub fn spawn_gui(
     input_level: Arc<Mutex<f32>>,
     output_level: Arc<Mutex<f32>>,
@@ -131,8 +427,10 @@ pub fn spawn_gui(
     ser: Arc<Mutex<Box<dyn serialport::SerialPort + Send>>>,
     stop_audio: Arc<Mutex<bool>>,
     freq_state: Arc<Mutex<u64>>,
-    mode_state: Arc<Mutex<String>>,
+    vfo_state: Arc<Mutex<crate::trusdx::VfoState>>,
+    mode_state: Arc<Mutex<crate::cat::Mode>>,
     tx_state: Arc<Mutex<bool>>,
+    audio_settings: Arc<Mutex<AudioSettings>>,
 ) {
     thread::spawn(move || {
         // Check if GUI setup failed
@@ -143,8 +441,10 @@ pub fn spawn_gui(
             ser,
             stop_audio,
             freq_state,
+            vfo_state,
             mode_state,
             tx_state,
+            audio_settings,
         ) {
             eprintln!("GUI error: {}", e);
         }
@@ -0,0 +1,303 @@
+// hamlib rigctld wire protocol: command table, line parser and response formatting
+
+use crate::cat::{Mode, Vfo};
+
+// hamlib error codes as sent in "RPRT n"
+pub const RIG_OK: i32 = 0;
+pub const RIG_EINVAL: i32 = -1;
+pub const RIG_ENIMPL: i32 = -4;
+pub const RIG_EIO: i32 = -6;
+pub const RIG_ERJCTED: i32 = -9;
+pub const RIG_ENAVAIL: i32 = -11;
+pub const RIG_EBUSBUSY: i32 = -14;
+
+pub const HAMLIB_MODES: &[&str] = &["LSB", "USB", "CW", "CWR", "FM", "AM", "PKTUSB", "PKTLSB"];
+pub const HAMLIB_VFOS: &[&str] = &["VFOA", "VFOB", "currVFO"];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RigCmd {
+    SetFreq,
+    GetFreq,
+    SetMode,
+    GetMode,
+    SetVfo,
+    GetVfo,
+    SetPtt,
+    GetPtt,
+    SetSplitVfo,
+    GetSplitVfo,
+    SetSplitFreq,
+    GetSplitFreq,
+    SetSplitMode,
+    GetSplitMode,
+    SetRit,
+    GetRit,
+    SetXit,
+    GetXit,
+    SetLevel,
+    GetLevel,
+    SetFunc,
+    GetFunc,
+    SetParm,
+    GetParm,
+    VfoOp,
+    SendMorse,
+    GetInfo,
+    GetVfoInfo,
+    DumpCaps,
+    DumpState,
+    ChkVfo,
+    GetPowerstat,
+    SetPowerstat,
+    GetLockMode,
+    SetLockMode,
+    SendVoiceMem,
+    StopVoiceMem,
+    // Driver extensions with no hamlib equivalent
+    SetRecording,
+    GetRecording,
+    Quit,
+}
+
+// Commands in the table that the trUSDX has no way to honour
+pub fn is_supported(cmd: RigCmd) -> bool {
+    !matches!(
+        cmd,
+        RigCmd::SetRit
+            | RigCmd::GetRit
+            | RigCmd::SetXit
+            | RigCmd::GetXit
+            | RigCmd::SetParm
+            | RigCmd::GetParm
+            | RigCmd::VfoOp
+            | RigCmd::SendMorse
+    )
+}
+
+pub struct CmdDef {
+    pub cmd: RigCmd,
+    pub short: Option<char>,
+    pub long: &'static str,
+    // Labels used for each result value in extended response mode
+    pub results: &'static [&'static str],
+}
+
+const fn def(cmd: RigCmd, short: Option<char>, long: &'static str, results: &'static [&'static str]) -> CmdDef {
+    CmdDef { cmd, short, long, results }
+}
+
+pub const COMMANDS: &[CmdDef] = &[
+    def(RigCmd::SetFreq, Some('F'), "set_freq", &[]),
+    def(RigCmd::GetFreq, Some('f'), "get_freq", &["Frequency"]),
+    def(RigCmd::SetMode, Some('M'), "set_mode", &[]),
+    def(RigCmd::GetMode, Some('m'), "get_mode", &["Mode", "Passband"]),
+    def(RigCmd::SetVfo, Some('V'), "set_vfo", &[]),
+    def(RigCmd::GetVfo, Some('v'), "get_vfo", &["VFO"]),
+    def(RigCmd::SetPtt, Some('T'), "set_ptt", &[]),
+    def(RigCmd::GetPtt, Some('t'), "get_ptt", &["PTT"]),
+    def(RigCmd::SetSplitVfo, Some('S'), "set_split_vfo", &[]),
+    def(RigCmd::GetSplitVfo, Some('s'), "get_split_vfo", &["Split", "TX VFO"]),
+    def(RigCmd::SetSplitFreq, Some('I'), "set_split_freq", &[]),
+    def(RigCmd::GetSplitFreq, Some('i'), "get_split_freq", &["TX Frequency"]),
+    def(RigCmd::SetSplitMode, Some('X'), "set_split_mode", &[]),
+    def(RigCmd::GetSplitMode, Some('x'), "get_split_mode", &["TX Mode", "TX Passband"]),
+    def(RigCmd::SetRit, Some('J'), "set_rit", &[]),
+    def(RigCmd::GetRit, Some('j'), "get_rit", &["RIT"]),
+    def(RigCmd::SetXit, Some('Z'), "set_xit", &[]),
+    def(RigCmd::GetXit, Some('z'), "get_xit", &["XIT"]),
+    def(RigCmd::SetLevel, Some('L'), "set_level", &[]),
+    def(RigCmd::GetLevel, Some('l'), "get_level", &["Level Value"]),
+    def(RigCmd::SetFunc, Some('U'), "set_func", &[]),
+    def(RigCmd::GetFunc, Some('u'), "get_func", &["Func Status"]),
+    def(RigCmd::SetParm, Some('P'), "set_parm", &[]),
+    def(RigCmd::GetParm, Some('p'), "get_parm", &["Parm Value"]),
+    def(RigCmd::VfoOp, Some('G'), "vfo_op", &[]),
+    def(RigCmd::SendMorse, Some('b'), "send_morse", &[]),
+    def(RigCmd::GetInfo, Some('_'), "get_info", &["Info"]),
+    def(RigCmd::GetVfoInfo, None, "get_vfo_info", &["Freq", "Mode", "Width", "Split", "SatMode"]),
+    def(RigCmd::DumpCaps, Some('1'), "dump_caps", &[]),
+    def(RigCmd::DumpState, None, "dump_state", &[]),
+    def(RigCmd::ChkVfo, None, "chk_vfo", &["ChkVFO"]),
+    def(RigCmd::GetPowerstat, None, "get_powerstat", &["Power Status"]),
+    def(RigCmd::SetPowerstat, None, "set_powerstat", &[]),
+    def(RigCmd::GetLockMode, None, "get_lock_mode", &["Locked"]),
+    def(RigCmd::SetLockMode, None, "set_lock_mode", &[]),
+    def(RigCmd::SendVoiceMem, None, "send_voice_mem", &[]),
+    def(RigCmd::StopVoiceMem, None, "stop_voice_mem", &[]),
+    def(RigCmd::SetRecording, None, "set_recording", &[]),
+    def(RigCmd::GetRecording, None, "get_recording", &["Recording"]),
+    def(RigCmd::Quit, Some('q'), "quit", &[]),
+];
+
+pub struct Request<'a> {
+    pub def: &'static CmdDef,
+    pub args: Vec<&'a str>,
+    // Separator for extended response mode ('+' means newline), None for the plain protocol
+    pub ext_sep: Option<char>,
+}
+
+pub enum Reply {
+    // Set command succeeded
+    Ok,
+    // Get command values, one per CmdDef::results label
+    Values(Vec<String>),
+    // Unlabelled multi-line block (dump_state, dump_caps)
+    Lines(Vec<String>),
+    Error(i32),
+}
+
+fn lookup_short(c: char) -> Option<&'static CmdDef> {
+    // 'Q' is an alias for quit, like in rigctld
+    let c = if c == 'Q' { 'q' } else { c };
+    COMMANDS.iter().find(|d| d.short == Some(c))
+}
+
+fn lookup_long(name: &str) -> Option<&'static CmdDef> {
+    COMMANDS.iter().find(|d| d.long == name)
+}
+
+// Parse one protocol line; Err carries the unrecognised command for logging
+pub fn parse_line(line: &str) -> Result<Request<'_>, String> {
+    let mut rest = line.trim();
+    let mut ext_sep = None;
+    // Check if line starts with an extended response separator
+    if let Some(c) = rest.chars().next().filter(|c| matches!(c, '+' | ';' | '|' | ',')) {
+        ext_sep = Some(if c == '+' { '\n' } else { c });
+        rest = &rest[1..];
+    }
+
+    let (def, args_str) = if let Some(long) = rest.strip_prefix('\\') {
+        let (name, args) = long.split_once(char::is_whitespace).unwrap_or((long, ""));
+        (lookup_long(name).ok_or_else(|| name.to_string())?, args)
+    } else {
+        let c = rest.chars().next().ok_or_else(String::new)?;
+        (lookup_short(c).ok_or_else(|| c.to_string())?, &rest[c.len_utf8()..])
+    };
+
+    // Morse text keeps its spaces; everything else is whitespace separated
+    let args = if def.cmd == RigCmd::SendMorse {
+        let text = args_str.trim();
+        if text.is_empty() { Vec::new() } else { vec![text] }
+    } else {
+        args_str.split_whitespace().collect()
+    };
+    Ok(Request { def, args, ext_sep })
+}
+
+pub fn format_reply(req: &Request<'_>, reply: &Reply) -> String {
+    let mut out = String::new();
+    match req.ext_sep {
+        None => match reply {
+            Reply::Ok => out.push_str("RPRT 0\n"),
+            Reply::Error(code) => out.push_str(&format!("RPRT {}\n", code)),
+            Reply::Values(values) | Reply::Lines(values) => {
+                for value in values {
+                    out.push_str(value);
+                    out.push('\n');
+                }
+            }
+        },
+        Some(sep) => {
+            out.push_str(req.def.long);
+            out.push(':');
+            for arg in &req.args {
+                out.push(' ');
+                out.push_str(arg);
+            }
+            out.push(sep);
+            match reply {
+                Reply::Values(values) => {
+                    for (index, value) in values.iter().enumerate() {
+                        // Check if this value has a label in the command table
+                        if let Some(label) = req.def.results.get(index) {
+                            out.push_str(label);
+                            out.push_str(": ");
+                        }
+                        out.push_str(value);
+                        out.push(sep);
+                    }
+                }
+                Reply::Lines(lines) => {
+                    for line in lines {
+                        out.push_str(line);
+                        out.push(sep);
+                    }
+                }
+                Reply::Ok | Reply::Error(_) => {}
+            }
+            let code = match reply {
+                Reply::Error(code) => *code,
+                _ => RIG_OK,
+            };
+            out.push_str(&format!("RPRT {}\n", code));
+        }
+    }
+    out
+}
+
+// Map a hamlib mode name to the radio mode, plus the name to echo back for data variants
+pub fn parse_mode(name: &str) -> Option<(Mode, Option<&'static str>)> {
+    match name {
+        "LSB" => Some((Mode::Lsb, None)),
+        "USB" => Some((Mode::Usb, None)),
+        // The trUSDX has a single CW mode with no reverse sideband option
+        "CW" => Some((Mode::Cw, None)),
+        "CWR" => Some((Mode::Cw, Some("CWR"))),
+        "FM" => Some((Mode::Fm, None)),
+        "AM" => Some((Mode::Am, None)),
+        "PKTUSB" => Some((Mode::Usb, Some("PKTUSB"))),
+        "PKTLSB" => Some((Mode::Lsb, Some("PKTLSB"))),
+        _ => None,
+    }
+}
+
+pub fn default_passband(mode: Mode) -> u32 {
+    match mode {
+        Mode::Lsb | Mode::Usb => 2400,
+        Mode::Cw => 500,
+        Mode::Am => 6000,
+        Mode::Fm => 12000,
+    }
+}
+
+pub fn parse_hz(arg: &str) -> Option<u64> {
+    // Check if frequency is a plain integer, otherwise accept hamlib's float form
+    if let Ok(hz_int) = arg.parse::<u64>() {
+        Some(hz_int)
+    } else if let Ok(hz_f) = arg.parse::<f64>() {
+        // Check if the float is a usable frequency
+        if hz_f.is_finite() && hz_f >= 0.0 { Some(hz_f.round() as u64) } else { None }
+    } else {
+        None
+    }
+}
+
+pub fn vfo_name(vfo: Vfo) -> &'static str {
+    match vfo {
+        Vfo::B => "VFOB",
+        Vfo::A => "VFOA",
+        Vfo::Memory => "MEM",
+    }
+}
+
+pub fn parse_vfo(arg: &str, current: Vfo) -> Option<Vfo> {
+    match arg {
+        "VFOA" | "Main" | "MainA" => Some(Vfo::A),
+        "VFOB" | "Sub" | "MainB" => Some(Vfo::B),
+        "currVFO" | "VFO" => Some(current),
+        _ => None,
+    }
+}
+
+pub fn parse_bool(arg: &str) -> Option<bool> {
+    arg.parse::<i32>().ok().map(|v| v != 0)
+}
+
+// Level values go out the way rigctld prints floats
+pub fn format_level(value: f32) -> String {
+    format!("{:.6}", value)
+}
+
+pub fn parse_level(arg: &str) -> Option<f32> {
+    arg.parse::<f32>().ok().filter(|v| v.is_finite())
+}
@@ -0,0 +1,145 @@
+// JACK backend: one client with an RX output port and a TX input port.
+// It runs at the JACK server's rate; audio::setup_audio puts a resampler in front of it.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Context};
+
+use crate::audio::{AudioBackend, VirtualDevices};
+
+// Most audio kept queued in either direction before the oldest is dropped
+const MAX_QUEUE_MS: usize = 500;
+const TX_READ_TIMEOUT_MS: u64 = 200;
+
+struct Queues {
+    rx: Mutex<VecDeque<f32>>,
+    tx: Mutex<VecDeque<f32>>,
+    tx_ready: Condvar,
+    max_len: usize,
+}
+
+struct Process {
+    rx_port: jack::Port<jack::AudioOut>,
+    tx_port: jack::Port<jack::AudioIn>,
+    queues: Arc<Queues>,
+}
+
+impl jack::ProcessHandler for Process {
+    fn process(&mut self, _: &jack::Client, ps: &jack::ProcessScope) -> jack::Control {
+        let out = self.rx_port.as_mut_slice(ps);
+        // Check if the RX queue is free; the realtime thread must never wait for it
+        match self.queues.rx.try_lock() {
+            Ok(mut rx) => {
+                for sample in out.iter_mut() {
+                    *sample = rx.pop_front().unwrap_or(0.0);
+                }
+            }
+            Err(_) => out.fill(0.0),
+        }
+        // Check if the TX queue is free, otherwise this period is lost
+        if let Ok(mut tx) = self.queues.tx.try_lock() {
+            tx.extend(self.tx_port.as_slice(ps).iter().copied());
+            let excess = tx.len().saturating_sub(self.queues.max_len);
+            tx.drain(..excess);
+            self.queues.tx_ready.notify_all();
+        }
+        jack::Control::Continue
+    }
+}
+
+pub struct JackBackend {
+    queues: Arc<Queues>,
+    client: Mutex<Option<jack::AsyncClient<(), Process>>>,
+    sample_rate: u32,
+}
+
+impl JackBackend {
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+}
+
+// Registers `<description>:<rx_source>` and `<description>:<tx_sink>`; connecting them is left to the user
+pub fn setup_jack(devices: &VirtualDevices, description: &str) -> anyhow::Result<JackBackend> {
+    let (client, _) = jack::Client::new(description, jack::ClientOptions::NO_START_SERVER)
+        .map_err(|e| anyhow!("Cannot connect to the JACK server: {}", e))?;
+    let rate = client.sample_rate() as u32;
+    let rx_port = client
+        .register_port(&devices.rx_source, jack::AudioOut)
+        .with_context(|| format!("Cannot register JACK port {}", devices.rx_source))?;
+    let tx_port = client
+        .register_port(&devices.tx_sink, jack::AudioIn)
+        .with_context(|| format!("Cannot register JACK port {}", devices.tx_sink))?;
+
+    let queues = Arc::new(Queues {
+        rx: Mutex::new(VecDeque::new()),
+        tx: Mutex::new(VecDeque::new()),
+        tx_ready: Condvar::new(),
+        max_len: rate as usize * MAX_QUEUE_MS / 1000,
+    });
+    let process = Process {
+        rx_port,
+        tx_port,
+        queues: queues.clone(),
+    };
+    let client = client.activate_async((), process).context("Cannot activate the JACK client")?;
+
+    Ok(JackBackend {
+        queues,
+        client: Mutex::new(Some(client)),
+        sample_rate: rate,
+    })
+}
+
+impl AudioBackend for JackBackend {
+    fn name(&self) -> &'static str {
+        "jack"
+    }
+
+    fn write_rx(&self, samples: &[f32]) {
+        let mut rx = self.queues.rx.lock().unwrap();
+        rx.extend(samples.iter().copied());
+        let excess = rx.len().saturating_sub(self.queues.max_len);
+        rx.drain(..excess);
+    }
+
+    fn rx_delay(&self) -> Option<Duration> {
+        let queued = self.queues.rx.lock().unwrap().len();
+        Some(Duration::from_secs_f64(queued as f64 / self.sample_rate as f64))
+    }
+
+    fn read_tx(&self, samples: &mut [i16]) -> anyhow::Result<()> {
+        let deadline = Instant::now() + Duration::from_millis(TX_READ_TIMEOUT_MS);
+        let mut tx = self.queues.tx.lock().unwrap();
+        while tx.len() < samples.len() {
+            let now = Instant::now();
+            // Check if the JACK graph stopped delivering audio
+            if now >= deadline {
+                anyhow::bail!("no TX audio from JACK");
+            }
+            tx = self.queues.tx_ready.wait_timeout(tx, deadline - now).unwrap().0;
+        }
+        let count = samples.len();
+        for (out, sample) in samples.iter_mut().zip(tx.drain(..count)) {
+            *out = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+        }
+        Ok(())
+    }
+
+    fn tx_ready(&self, samples: usize) -> bool {
+        self.queues.tx.lock().unwrap().len() >= samples
+    }
+
+    fn drain_tx(&self) {
+        self.queues.tx.lock().unwrap().clear();
+    }
+
+    fn close(&self) {
+        // Check if the client is still active
+        if let Some(client) = self.client.lock().unwrap().take() {
+            let _ = client.deactivate();
+        }
+    }
+}
@@ -0,0 +1,400 @@
+// Message keyer: transmits prerecorded WAV files from numbered slots, for contest exchanges,
+// CQ loops and beacons
+//
+// The messages are loaded and resampled to the TX rate at startup. The bridge plays them through
+// the same TX processing as application audio, paced to the TX rate, and un-keys at the end.
+// Any other keying (rigctl, VOX, the TX timeout) aborts the message.
+
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Context, Result};
+
+use crate::audio::AudioSettings;
+use crate::resample::{ResampleQuality, Resampler};
+
+// How far sending may run ahead of the TX clock; the rest queues in the serial buffers
+const MAX_LEAD: Duration = Duration::from_millis(40);
+
+struct Message {
+    name: String,
+    samples: Vec<f32>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Request {
+    Play(usize),
+    // Stop the message and un-key
+    Stop,
+    // Someone else keyed the radio; stop sending but leave the TX state alone
+    Abort,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeyerAction {
+    None,
+    Key,
+    Unkey,
+}
+
+static MESSAGES: OnceLock<Vec<Message>> = OnceLock::new();
+static REQUEST: Mutex<Option<Request>> = Mutex::new(None);
+// Slot being sent, or waited on between repeats, and whether it is waiting
+static STATUS: Mutex<Option<(usize, bool)>> = Mutex::new(None);
+
+// Load the message files into slots 1, 2, ... at the TX rate
+pub fn load(paths: &[PathBuf], tx_rate: u32) -> Result<()> {
+    let mut messages = Vec::with_capacity(paths.len());
+    for path in paths {
+        let (rate, samples) = read_wav(path).with_context(|| format!("Cannot load keyer message {}", path.display()))?;
+        let name = path.file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default();
+        messages.push(Message { name, samples: resample(&samples, rate, tx_rate) });
+    }
+    let _ = MESSAGES.set(messages);
+    Ok(())
+}
+
+// File names of the loaded messages, slot 1 first
+pub fn message_names() -> Vec<String> {
+    MESSAGES.get().map(|messages| messages.iter().map(|message| message.name.clone()).collect()).unwrap_or_default()
+}
+
+// Ask the bridge to send the message in `slot` (from 1); false if there is no such slot
+pub fn play(slot: usize) -> bool {
+    // Check if the slot holds a message
+    if slot == 0 || slot > message_names().len() {
+        return false;
+    }
+    *REQUEST.lock().unwrap() = Some(Request::Play(slot));
+    true
+}
+
+pub fn stop() {
+    *REQUEST.lock().unwrap() = Some(Request::Stop);
+}
+
+// Another keyer took over the transmission
+pub fn abort() {
+    // Check if there is anything to abort
+    if status().is_some() {
+        *REQUEST.lock().unwrap() = Some(Request::Abort);
+    }
+}
+
+// (slot, waiting to repeat) while a message is active
+pub fn status() -> Option<(usize, bool)> {
+    *STATUS.lock().unwrap()
+}
+
+enum State {
+    Idle,
+    Sending { slot: usize, pos: usize, started: Instant },
+    Waiting { slot: usize, until: Instant },
+}
+
+// The bridge's side: follows requests and feeds the TX path
+pub struct Keyer {
+    rate: u32,
+    state: State,
+}
+
+impl Keyer {
+    pub fn new(tx_rate: u32) -> Keyer {
+        Keyer { rate: tx_rate, state: State::Idle }
+    }
+
+    // Helper function to change state and publish it
+    fn set_state(&mut self, state: State) {
+        *STATUS.lock().unwrap() = match state {
+            State::Idle => None,
+            State::Sending { slot, .. } => Some((slot, false)),
+            State::Waiting { slot, .. } => Some((slot, true)),
+        };
+        self.state = state;
+    }
+
+    // Helper function to start sending `slot` from the top
+    fn start(&mut self, slot: usize) -> KeyerAction {
+        self.set_state(State::Sending { slot, pos: 0, started: Instant::now() });
+        KeyerAction::Key
+    }
+
+    // Called on every bridge pass with the current TX state
+    pub fn poll(&mut self, transmitting: bool) -> KeyerAction {
+        let request = REQUEST.lock().unwrap().take();
+        let sending = matches!(self.state, State::Sending { .. });
+        match request {
+            Some(Request::Play(slot)) => {
+                // Check if someone else is transmitting; a message must not cut in
+                if transmitting && !sending {
+                    eprintln!("Keyer: radio is already transmitting, message {} not sent", slot);
+                    return KeyerAction::None;
+                }
+                return self.start(slot);
+            }
+            Some(Request::Stop) => {
+                self.set_state(State::Idle);
+                return if sending { KeyerAction::Unkey } else { KeyerAction::None };
+            }
+            Some(Request::Abort) => {
+                self.set_state(State::Idle);
+                return KeyerAction::None;
+            }
+            None => {}
+        }
+        match self.state {
+            // Check if the transmission was ended by the TX timeout or a rigctl "T 0"
+            State::Sending { .. } if !transmitting => {
+                self.set_state(State::Idle);
+                KeyerAction::None
+            }
+            // Check if someone else keyed the radio between repeats
+            State::Waiting { .. } if transmitting => {
+                self.set_state(State::Idle);
+                KeyerAction::None
+            }
+            State::Waiting { slot, until } if Instant::now() >= until => self.start(slot),
+            _ => KeyerAction::None,
+        }
+    }
+
+    pub fn is_sending(&self) -> bool {
+        matches!(self.state, State::Sending { .. })
+    }
+
+    // Fill `out` with the next block, paced to the TX rate. Returns false once the message has
+    // been sent and played out; the caller then un-keys.
+    pub fn next_block(&mut self, settings: &AudioSettings, out: &mut [i16]) -> bool {
+        let State::Sending { slot, pos, started } = self.state else {
+            return false;
+        };
+        let samples = MESSAGES.get().and_then(|messages| messages.get(slot - 1)).map(|message| &message.samples[..]).unwrap_or(&[]);
+        let sent = Duration::from_secs_f64(pos as f64 / self.rate as f64);
+        let elapsed = started.elapsed();
+        // Check if the whole message is out; wait for it to leave the buffers before un-keying
+        if pos >= samples.len() {
+            thread::sleep(sent.saturating_sub(elapsed));
+            // Check if the message should come round again
+            if settings.keyer_repeat {
+                let until = Instant::now() + Duration::from_secs_f32(settings.keyer_interval);
+                self.set_state(State::Waiting { slot, until });
+            } else {
+                self.set_state(State::Idle);
+            }
+            return false;
+        }
+        // Check if sending is too far ahead of the radio
+        if sent > elapsed + MAX_LEAD {
+            thread::sleep(sent - elapsed - MAX_LEAD);
+        }
+        for (i, out) in out.iter_mut().enumerate() {
+            let sample = samples.get(pos + i).copied().unwrap_or(0.0);
+            *out = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+        }
+        self.state = State::Sending { slot, pos: pos + out.len(), started };
+        true
+    }
+}
+
+// Helper function to bring a message to the TX rate, including the resampler's tail
+fn resample(samples: &[f32], from_rate: u32, to_rate: u32) -> Vec<f32> {
+    // Check if the file is already at the TX rate
+    if from_rate == to_rate {
+        return samples.to_vec();
+    }
+    let mut resampler = Resampler::new(from_rate, to_rate, ResampleQuality::High);
+    let mut output = Vec::new();
+    resampler.process(samples, &mut output);
+    resampler.process(&vec![0.0; from_rate as usize / 10], &mut output);
+    output.truncate((samples.len() as u64 * to_rate as u64).div_ceil(from_rate as u64) as usize);
+    output
+}
+
+// Helper function to read a PCM or float WAV file as mono samples at its own rate
+fn read_wav(path: &Path) -> Result<(u32, Vec<f32>)> {
+    parse_wav(&std::fs::read(path)?)
+}
+
+// Helper function for the mono samples and rate of a WAV file's contents
+fn parse_wav(bytes: &[u8]) -> Result<(u32, Vec<f32>)> {
+    // Check if this is a RIFF WAVE file at all
+    if bytes.len() < 12 || &bytes[0..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+        bail!("not a WAV file");
+    }
+    let mut format: Option<(u16, u16, u32, u16)> = None;
+    let mut offset = 12;
+    while offset + 8 <= bytes.len() {
+        let id = &bytes[offset..offset + 4];
+        let len = u32::from_le_bytes(bytes[offset + 4..offset + 8].try_into()?) as usize;
+        let body = &bytes[offset + 8..(offset + 8 + len).min(bytes.len())];
+        match id {
+            b"fmt " if body.len() >= 16 => {
+                let mut tag = u16::from_le_bytes([body[0], body[1]]);
+                // Check if the format is WAVE_FORMAT_EXTENSIBLE; the real tag starts the sub-format GUID
+                if tag == 0xfffe && body.len() >= 26 {
+                    tag = u16::from_le_bytes([body[24], body[25]]);
+                }
+                let channels = u16::from_le_bytes([body[2], body[3]]);
+                let rate = u32::from_le_bytes(body[4..8].try_into()?);
+                let bits = u16::from_le_bytes([body[14], body[15]]);
+                format = Some((tag, channels, rate, bits));
+            }
+            b"data" => {
+                let Some((tag, channels, rate, bits)) = format else {
+                    bail!("data before the format chunk");
+                };
+                // Check if the sample format is one we can convert
+                let decode: fn(&[u8]) -> f32 = match (tag, bits) {
+                    (1, 8) => |b| (b[0] as f32 - 128.0) / 128.0,
+                    (1, 16) => |b| i16::from_le_bytes([b[0], b[1]]) as f32 / 32768.0,
+                    (1, 24) => |b| i32::from_le_bytes([0, b[0], b[1], b[2]]) as f32 / 2_147_483_648.0,
+                    (1, 32) => |b| i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f32 / 2_147_483_648.0,
+                    (3, 32) => |b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+                    _ => bail!("unsupported sample format (format {}, {} bits)", tag, bits),
+                };
+                // Check if the format describes usable audio
+                if channels == 0 || rate == 0 {
+                    bail!("invalid format chunk");
+                }
+                let width = bits as usize / 8;
+                let frame = width * channels as usize;
+                // Mix all channels down to mono
+                let samples = body
+                    .chunks_exact(frame)
+                    .map(|frame| frame.chunks_exact(width).map(decode).sum::<f32>() / channels as f32)
+                    .collect();
+                return Ok((rate, samples));
+            }
+            _ => {}
+        }
+        // Chunks are padded to an even length
+        offset += 8 + len + (len & 1);
+    }
+    bail!("no audio data found")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Helper function for a 16-byte PCM/float format chunk body
+    fn fmt(tag: u16, channels: u16, rate: u32, bits: u16) -> Vec<u8> {
+        let align = channels * bits / 8;
+        let mut body = Vec::new();
+        body.extend_from_slice(&tag.to_le_bytes());
+        body.extend_from_slice(&channels.to_le_bytes());
+        body.extend_from_slice(&rate.to_le_bytes());
+        body.extend_from_slice(&(rate * align as u32).to_le_bytes());
+        body.extend_from_slice(&align.to_le_bytes());
+        body.extend_from_slice(&bits.to_le_bytes());
+        body
+    }
+
+    // Helper function for a WAVE_FORMAT_EXTENSIBLE format chunk body with the given sub-format
+    fn fmt_extensible(sub_format: u16, channels: u16, rate: u32, bits: u16) -> Vec<u8> {
+        let mut body = fmt(0xfffe, channels, rate, bits);
+        body.extend_from_slice(&22u16.to_le_bytes());
+        body.extend_from_slice(&bits.to_le_bytes());
+        body.extend_from_slice(&0u32.to_le_bytes());
+        body.extend_from_slice(&sub_format.to_le_bytes());
+        body.extend_from_slice(b"\x00\x00\x00\x00\x10\x00\x80\x00\x00\xaa\x00\x38\x9b\x71");
+        body
+    }
+
+    // Helper function for a RIFF WAVE file holding `chunks`, each padded to an even length
+    fn wav(chunks: &[(&[u8; 4], Vec<u8>)]) -> Vec<u8> {
+        let mut body = b"WAVE".to_vec();
+        for (id, data) in chunks {
+            body.extend_from_slice(*id);
+            body.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            body.extend_from_slice(data);
+            // Check if the chunk needs its pad byte
+            if data.len() % 2 == 1 {
+                body.push(0);
+            }
+        }
+        let mut bytes = b"RIFF".to_vec();
+        bytes.extend_from_slice(&(body.len() as u32).to_le_bytes());
+        bytes.extend(body);
+        bytes
+    }
+
+    #[test]
+    fn reads_pcm_and_float_samples() {
+        let half = [0.5f32, -0.5];
+        let cases: [(Vec<u8>, Vec<u8>); 4] = [
+            (fmt(1, 1, 8000, 8), vec![192, 64]),
+            (fmt(1, 1, 8000, 16), [16384i16, -16384].iter().flat_map(|s| s.to_le_bytes()).collect()),
+            (fmt(1, 1, 8000, 24), vec![0x00, 0x00, 0x40, 0x00, 0x00, 0xc0]),
+            (fmt(3, 1, 8000, 32), half.iter().flat_map(|s| s.to_le_bytes()).collect()),
+        ];
+        for (format, data) in cases {
+            let (rate, samples) = parse_wav(&wav(&[(b"fmt ", format.clone()), (b"data", data)])).unwrap();
+            assert_eq!(rate, 8000);
+            assert_eq!(samples, half, "format {:?}", &format[..2]);
+        }
+    }
+
+    #[test]
+    fn reads_extensible_format() {
+        let data: Vec<u8> = [0.25f32, -0.75].iter().flat_map(|s| s.to_le_bytes()).collect();
+        let (rate, samples) = parse_wav(&wav(&[(b"fmt ", fmt_extensible(3, 1, 48000, 32)), (b"data", data)])).unwrap();
+        assert_eq!((rate, samples), (48000, vec![0.25, -0.75]));
+        let data: Vec<u8> = [1000i16, -1000].iter().flat_map(|s| s.to_le_bytes()).collect();
+        let (_, samples) = parse_wav(&wav(&[(b"fmt ", fmt_extensible(1, 1, 48000, 16)), (b"data", data)])).unwrap();
+        assert_eq!(samples, vec![1000.0 / 32768.0, -1000.0 / 32768.0]);
+    }
+
+    #[test]
+    fn mixes_stereo_to_mono() {
+        // Left and right frames: (0.5, 0.25), (-1.0, 1.0)
+        let data: Vec<u8> = [16384i16, 8192, -32768, 32767].iter().flat_map(|s| s.to_le_bytes()).collect();
+        let (_, samples) = parse_wav(&wav(&[(b"fmt ", fmt(1, 2, 22050, 16)), (b"data", data)])).unwrap();
+        assert_eq!(samples.len(), 2);
+        assert_eq!(samples[0], 0.375);
+        assert!(samples[1].abs() < 1e-4);
+    }
+
+    #[test]
+    fn skips_odd_length_chunks_with_their_padding() {
+        let bytes = wav(&[
+            (b"LIST", b"abc".to_vec()),
+            (b"fmt ", fmt(1, 1, 8000, 8)),
+            (b"junk", vec![1; 5]),
+            (b"data", vec![128, 255, 0]),
+        ]);
+        let (rate, samples) = parse_wav(&bytes).unwrap();
+        assert_eq!((rate, samples), (8000, vec![0.0, 127.0 / 128.0, -1.0]));
+    }
+
+    #[test]
+    fn rejects_broken_files() {
+        let data = vec![0u8; 4];
+        let cases = [
+            (wav(&[(b"LIST", vec![0; 4]), (b"data", data.clone())]), "data before the format chunk"),
+            (wav(&[(b"LIST", vec![0; 4])]), "no audio data found"),
+            (wav(&[(b"data", data.clone()), (b"fmt ", fmt(1, 1, 8000, 16))]), "data before the format chunk"),
+            (wav(&[(b"fmt ", fmt(2, 1, 8000, 4)), (b"data", data.clone())]), "unsupported sample format"),
+            (wav(&[(b"fmt ", fmt(1, 1, 8000, 12)), (b"data", data.clone())]), "unsupported sample format"),
+            (b"RIFF\x04\x00\x00\x00AVI ".to_vec(), "not a WAV file"),
+        ];
+        for (bytes, message) in cases {
+            let error = parse_wav(&bytes).unwrap_err().to_string();
+            assert!(error.contains(message), "expected {:?}, got {:?}", message, error);
+        }
+    }
+
+    #[test]
+    fn resampled_length_matches_the_rate_change() {
+        for (from, to) in [(48000, 11520), (44100, 11520), (8000, 11520), (7812, 11520)] {
+            for len in [1, 2, 999, 4801, 44101] {
+                let samples = vec![0.1; len];
+                let expected = (len as u64 * to as u64).div_ceil(from as u64) as usize;
+                assert_eq!(resample(&samples, from, to).len(), expected, "{} samples {} -> {}", len, from, to);
+            }
+        }
+        assert_eq!(resample(&[0.5, -0.5], 11520, 11520), vec![0.5, -0.5]);
+    }
+}
@@ -4,28 +4,93 @@ use std::time::Duration;
 use anyhow::Result;
 use serialport;
 
+#[cfg(feature = "alsa")]
+mod alsa_backend;
+mod args;
 mod audio;
+#[cfg(feature = "jack")]
+mod jack_backend;
+#[cfg(feature = "pipewire")]
+mod pipewire_backend;
+mod pulse_backend;
+mod caps;
+mod cat;
+mod config;
+mod drift;
+mod hamlib;
+mod keyer;
+mod pulsectl;
+mod quantize;
+mod record;
+mod resample;
 mod rigctl;
+mod rxdsp;
 mod trusdx;
+mod txdsp;
+mod vox;
 mod cli;
 mod gui;
 mod shutdown;
+mod reconnect;
+mod watchdog;
 
 fn main() -> Result<()> {
-    audio::cleanup_trusdx_audio_interfaces();
+    let mut options = match args::parse_args()? {
+        Some(options) => options,
+        None => return Ok(()),
+    };
 
-    let _mid = audio::create_trusdx_audio_interface(11520);
+    // Check if only a device listing was requested
+    if options.list_devices {
+        trusdx::print_candidate_devices(&options.selector);
+        return Ok(());
+    }
+
+    keyer::load(&options.keyer_messages, audio::RADIO_TX_RATE)?;
 
-    let mut port = trusdx::open_trusdx_serial()?;
+    let devices = audio::VirtualDevices::new(&options.sink_name);
+    let audio = audio::setup_audio(
+        &options.audio_backend,
+        &devices,
+        &options.sink_description,
+        audio::RADIO_RX_RATE,
+        audio::RADIO_TX_RATE,
+    )?;
+    let audio_backend = audio.backend.clone();
+    // Check if there is a resampler to apply the clock correction with
+    if !audio_backend.corrects_clock() {
+        drift::set_correction_applied(false);
+        eprintln!("Note: without resampling (--device-rate 0) the radio clock offset is measured but not corrected");
+    }
+    // With tracking on this only holds until the first measurement
+    audio_backend.set_clock_correction(options.clock_ppm);
+
+    let (device_path, mut port) = match trusdx::open_trusdx_serial(&options.selector) {
+        Ok(opened) => opened,
+        Err(e) => {
+            audio_backend.close();
+            return Err(e);
+        }
+    };
     
     let _ = trusdx::control_rts(&mut *port, false);
     let _ = trusdx::control_dtr(&mut *port, true);
     let ser: Arc<Mutex<Box<dyn serialport::SerialPort + Send>>> = Arc::new(Mutex::new(port));
+    let tx_state = Arc::new(Mutex::new(false));
+    let stop_audio = Arc::new(Mutex::new(false));
+    let shutting_down = Arc::new(AtomicBool::new(false));
+
+    shutdown::install_signal_handlers(shutting_down.clone(), ser.clone(), stop_audio.clone(), options.no_tui)?;
+    shutdown::install_panic_hook(ser.clone(), tx_state.clone());
 
     {
         let mut s = ser.lock().unwrap();
         let _ = trusdx::enable_streaming_speaker_off(&mut **s);
-        let _ = trusdx::set_mode(&mut **s, 2);
+        let _ = trusdx::set_mode(&mut **s, options.initial_mode);
+        // Check if a startup frequency was requested
+        if let Some(hz) = options.initial_freq {
+            let _ = trusdx::set_vfo_a(&mut **s, hz);
+        }
     }
     let streaming_started = Arc::new(AtomicBool::new(false));
     {
@@ -47,76 +112,154 @@ fn main() -> Result<()> {
         }
     }
 
-    let freq_state = Arc::new(Mutex::new(0u64));
-    let tx_state = Arc::new(Mutex::new(false));
-    let mode_state = Arc::new(Mutex::new(String::from("USB")));
-    let audio = audio::setup_pulseaudio(7812, 11520)?;
-    let stop_audio = Arc::new(Mutex::new(false));
+    let freq_state = Arc::new(Mutex::new(options.initial_freq.unwrap_or(0)));
+    let vfo_state = Arc::new(Mutex::new(trusdx::VfoState::default()));
+    let mode_state = Arc::new(Mutex::new(options.initial_mode));
+    let audio_settings = Arc::new(Mutex::new(options.audio));
     let input_level = Arc::new(Mutex::new(0.0f32));
     let output_level = Arc::new(Mutex::new(0.0f32));
-    let cat_queue: Arc<Mutex<Vec<Vec<u8>>>> = Arc::new(Mutex::new(Vec::new()));
+    let cat_queue: Arc<Mutex<Vec<cat::CatCommand>>> = Arc::new(Mutex::new(Vec::new()));
     audio::run_audio_bridge(
         ser.clone(),
         audio,
+        audio_settings.clone(),
         stop_audio.clone(),
         input_level.clone(),
         output_level.clone(),
         freq_state.clone(),
+        vfo_state.clone(),
         mode_state.clone(),
         tx_state.clone(),
         cat_queue.clone(),
         streaming_started.clone(),
+        options.record_dir.clone(),
     );
 
-    rigctl::spawn_rigctl_server(ser.clone(), freq_state.clone(), tx_state.clone(), cat_queue.clone());
-
-    let shutting_down = Arc::new(AtomicBool::new(false));
-    cli::spawn_esc_handler(shutting_down.clone(), ser.clone(), stop_audio.clone());
-
-    gui::spawn_gui(
-        input_level.clone(),
-        output_level.clone(),
-        shutting_down.clone(),
+    // Check if the rigctl listeners came up; otherwise the radio and audio devices are put back before exiting
+    if let Err(e) = rigctl::spawn_rigctl_server(
         ser.clone(),
-        stop_audio.clone(),
         freq_state.clone(),
+        vfo_state.clone(),
         mode_state.clone(),
         tx_state.clone(),
+        cat_queue.clone(),
+        audio_settings.clone(),
+        &options.rigctl,
+    ) {
+        shutdown::shutdown(shutting_down.clone(), ser.clone(), stop_audio.clone());
+        audio_backend.close();
+        return Err(e);
+    }
+
+    let tx_timeout = Arc::new(Mutex::new(options.tx_timeout));
+    watchdog::spawn_tx_watchdog(ser.clone(), tx_state.clone(), tx_timeout.clone(), shutting_down.clone());
+
+    reconnect::spawn_reconnect_supervisor(
+        options.selector.clone(),
+        device_path.clone(),
+        ser.clone(),
+        tx_state.clone(),
+        streaming_started.clone(),
+        shutting_down.clone(),
     );
+    // Check if the console UI is wanted (needs a terminal)
+    if !options.no_tui {
+        cli::spawn_esc_handler(shutting_down.clone(), ser.clone(), stop_audio.clone(), audio_settings.clone());
+    }
 
-    cli::print_console_header();
+    // Check if the GTK window is wanted
+    if !options.no_gui {
+        gui::spawn_gui(
+            input_level.clone(),
+            output_level.clone(),
+            shutting_down.clone(),
+            ser.clone(),
+            stop_audio.clone(),
+            freq_state.clone(),
+            vfo_state.clone(),
+            mode_state.clone(),
+            tx_state.clone(),
+            audio_settings.clone(),
+        );
+    }
+
+    // Check if the console UI is wanted
+    if options.no_tui {
+        println!("trUSDX driver running on {}", device_path);
+    } else {
+        cli::print_console_header();
+    }
     let mut last_poll = std::time::Instant::now();
     let mut prev_tx_state = false;
     let mut last_tx_end = std::time::Instant::now() - std::time::Duration::from_secs(5);
+    let mut logged_ppm: Option<(f64, std::time::Instant)> = None;
     
     loop {
         // Check if shutdown flag is set
         if shutting_down.load(Ordering::Relaxed) { break; }
+        // Check if SIGHUP asked for the config file to be read again
+        if config::take_reload_request() {
+            match args::reload(&options) {
+                Ok(reloaded) => {
+                    // Only levels, the poll interval, the TX timeout and the clock correction are swapped live;
+                    // the rest needs a restart
+                    *audio_settings.lock().unwrap() = reloaded.audio;
+                    // Check if the manual correction is in charge rather than the measured one
+                    if !reloaded.audio.clock_tracking {
+                        audio_backend.set_clock_correction(reloaded.clock_ppm);
+                    }
+                    options.clock_ppm = reloaded.clock_ppm;
+                    *tx_timeout.lock().unwrap() = reloaded.tx_timeout;
+                    options.poll_interval = reloaded.poll_interval;
+                    options.tx_timeout = reloaded.tx_timeout;
+                    options.audio = reloaded.audio;
+                    eprintln!("Configuration reloaded");
+                }
+                Err(e) => eprintln!("Configuration reload failed, keeping current settings: {:#}", e),
+            }
+        }
         let in_lvl = *input_level.lock().unwrap();
         let out_lvl = *output_level.lock().unwrap();
-        let freq = *freq_state.lock().unwrap();
-        let mode = mode_state.lock().unwrap().clone();
+        let freq = vfo_state.lock().unwrap().rx_freq(*freq_state.lock().unwrap());
+        let mode = *mode_state.lock().unwrap();
         let tx_now = *tx_state.lock().unwrap();
         // Check if TX just ended (transition from TX to RX)
         if prev_tx_state && !tx_now { last_tx_end = std::time::Instant::now(); }
         prev_tx_state = tx_now;
-        cli::render_levels(in_lvl, out_lvl, freq, &mode, tx_now);
+        // Check if the console meters should be drawn
+        if !options.no_tui {
+            let audio_now = *audio_settings.lock().unwrap();
+            cli::render_levels(in_lvl, out_lvl, freq, mode.name(), tx_now, reconnect::link_state(), audio_now);
+        } else if let Some(ppm) = drift::measured_ppm() {
+            // Check if the clock offset moved enough, and long enough ago, to be worth a log line
+            let due = logged_ppm.is_none_or(|(last, at)| (ppm - last).abs() >= 1.0 && at.elapsed() >= Duration::from_secs(60));
+            if due {
+                // Check if the offset is only reported
+                let note = if drift::correction_applied() { "" } else { " (not applied, no resampling)" };
+                eprintln!("Radio clock offset: {:+.1} ppm{}", ppm, note);
+                logged_ppm = Some((ppm, std::time::Instant::now()));
+            }
+        }
         thread::sleep(Duration::from_millis(10));
+        let poll_due = options.poll_interval.is_some_and(|interval| last_poll.elapsed() >= interval);
         // Check if in RX mode and poll interval elapsed
-        if !tx_now && last_poll.elapsed() >= Duration::from_secs(2) {
+        if !tx_now && reconnect::is_connected() && poll_due {
             // Check if enough time passed since last TX ended
             if std::time::Instant::now().duration_since(last_tx_end) >= Duration::from_millis(500) {
                 // Check if serial port lock acquired successfully
-                if let Ok(mut s) = ser.lock() { let _ = trusdx::query_vfo_a(&mut **s); }
+                if let Ok(mut s) = ser.lock() { let _ = trusdx::query_vfos(&mut **s); }
             }
             last_poll = std::time::Instant::now();
         }
     }
 
-    
-    print!("\x1B[2J\x1B[H");
-    std::io::Write::flush(&mut std::io::stdout()).ok();
-    audio::cleanup_trusdx_audio_interfaces();
+    // Check if the console UI was drawn and needs clearing
+    if !options.no_tui {
+        cli::restore_terminal();
+        print!("\x1B[2J\x1B[H");
+        std::io::Write::flush(&mut std::io::stdout()).ok();
+    }
+    audio_backend.close();
     Ok(())
 }
 
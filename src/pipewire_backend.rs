@@ -0,0 +1,283 @@
+// Native PipeWire backend: the RX source and TX sink are the nodes of our own two streams,
+// so no null sink or monitor is involved and PipeWire resamples from the radio rates itself
+
+use std::collections::VecDeque;
+use std::sync::{mpsc, Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::anyhow;
+use pipewire as pw;
+use pw::properties::properties;
+use pw::spa;
+use spa::pod::Pod;
+
+use crate::audio::{AudioBackend, VirtualDevices};
+
+// Node latency hint given to the graph
+const LATENCY_MS: u32 = 20;
+// Most audio kept queued in either direction before the oldest is dropped
+const MAX_QUEUE_MS: u32 = 500;
+const TX_READ_TIMEOUT_MS: u64 = 200;
+const STARTUP_TIMEOUT_MS: u64 = 5000;
+
+struct Queues {
+    rx: Mutex<VecDeque<f32>>,
+    tx: Mutex<VecDeque<i16>>,
+    tx_ready: Condvar,
+    rx_max: usize,
+    tx_max: usize,
+    rx_rate: u32,
+}
+
+pub struct PipeWireBackend {
+    queues: Arc<Queues>,
+    quit: Mutex<Option<pw::channel::Sender<()>>>,
+    thread: Mutex<Option<thread::JoinHandle<()>>>,
+}
+
+// Starts the PipeWire loop thread and waits until both nodes exist
+pub fn setup_pipewire(
+    devices: &VirtualDevices,
+    description: &str,
+    audio_rx_rate: u32,
+    audio_tx_rate: u32,
+) -> anyhow::Result<PipeWireBackend> {
+    let queues = Arc::new(Queues {
+        rx: Mutex::new(VecDeque::new()),
+        tx: Mutex::new(VecDeque::new()),
+        tx_ready: Condvar::new(),
+        rx_max: (audio_rx_rate * MAX_QUEUE_MS / 1000) as usize,
+        tx_max: (audio_tx_rate * MAX_QUEUE_MS / 1000) as usize,
+        rx_rate: audio_rx_rate,
+    });
+    let (quit_tx, quit_rx) = pw::channel::channel::<()>();
+    let (ready_tx, ready_rx) = mpsc::channel::<Result<(), String>>();
+
+    let devices = devices.clone();
+    let description = description.to_string();
+    let loop_queues = queues.clone();
+    let handle = thread::spawn(move || {
+        // Check if the loop could be set up; errors go back to the caller
+        if let Err(e) = run_loop(&devices, &description, audio_rx_rate, audio_tx_rate, loop_queues, quit_rx, &ready_tx) {
+            let _ = ready_tx.send(Err(format!("{:#}", e)));
+        }
+    });
+
+    match ready_rx.recv_timeout(Duration::from_millis(STARTUP_TIMEOUT_MS)) {
+        Ok(Ok(())) => Ok(PipeWireBackend {
+            queues,
+            quit: Mutex::new(Some(quit_tx)),
+            thread: Mutex::new(Some(handle)),
+        }),
+        Ok(Err(e)) => Err(anyhow!("PipeWire: {}", e)),
+        Err(_) => {
+            let _ = quit_tx.send(());
+            Err(anyhow!("PipeWire: timed out creating the audio nodes"))
+        }
+    }
+}
+
+fn format_param(format: spa::param::audio::AudioFormat, rate: u32) -> anyhow::Result<Vec<u8>> {
+    let mut info = spa::param::audio::AudioInfoRaw::new();
+    info.set_format(format);
+    info.set_rate(rate);
+    info.set_channels(1);
+    let object = spa::pod::Object {
+        type_: spa::utils::SpaTypes::ObjectParamFormat.as_raw(),
+        id: spa::param::ParamType::EnumFormat.as_raw(),
+        properties: info.into(),
+    };
+    let (cursor, _) = spa::pod::serialize::PodSerializer::serialize(
+        std::io::Cursor::new(Vec::new()),
+        &spa::pod::Value::Object(object),
+    )
+    .map_err(|_| anyhow!("cannot build the stream format"))?;
+    Ok(cursor.into_inner())
+}
+
+fn run_loop(
+    devices: &VirtualDevices,
+    description: &str,
+    audio_rx_rate: u32,
+    audio_tx_rate: u32,
+    queues: Arc<Queues>,
+    quit_rx: pw::channel::Receiver<()>,
+    ready: &mpsc::Sender<Result<(), String>>,
+) -> anyhow::Result<()> {
+    let mainloop = pw::main_loop::MainLoop::new(None)?;
+    let context = pw::context::Context::new(&mainloop)?;
+    let core = context.connect(None)?;
+
+    let _quit = quit_rx.attach(mainloop.loop_(), {
+        let mainloop = mainloop.clone();
+        move |_| mainloop.quit()
+    });
+
+    // RX: a source node applications record from
+    let rx_stream = pw::stream::Stream::new(
+        &core,
+        &devices.rx_source,
+        properties! {
+            *pw::keys::MEDIA_TYPE => "Audio",
+            *pw::keys::MEDIA_CLASS => "Audio/Source",
+            *pw::keys::NODE_NAME => devices.rx_source.as_str(),
+            *pw::keys::NODE_DESCRIPTION => format!("{} RX", description),
+            *pw::keys::NODE_LATENCY => format!("{}/{}", audio_rx_rate * LATENCY_MS / 1000, audio_rx_rate),
+        },
+    )?;
+    let rx_queues = queues.clone();
+    let _rx_listener = rx_stream
+        .add_local_listener_with_user_data(())
+        .process(move |stream, _| {
+            // Check if the graph handed us a buffer to fill
+            let Some(mut buffer) = stream.dequeue_buffer() else {
+                return;
+            };
+            let requested = buffer.requested() as usize;
+            let datas = buffer.datas_mut();
+            let Some(data) = datas.first_mut() else {
+                return;
+            };
+            let stride = std::mem::size_of::<f32>();
+            let frames = match data.data() {
+                Some(slice) => {
+                    let mut frames = slice.len() / stride;
+                    // Check if the graph asked for a specific quantum
+                    if requested > 0 {
+                        frames = frames.min(requested);
+                    }
+                    let mut rx = rx_queues.rx.lock().unwrap();
+                    for out in slice.chunks_exact_mut(stride).take(frames) {
+                        // Underruns are filled with silence
+                        out.copy_from_slice(&rx.pop_front().unwrap_or(0.0).to_le_bytes());
+                    }
+                    frames
+                }
+                None => 0,
+            };
+            let chunk = data.chunk_mut();
+            *chunk.offset_mut() = 0;
+            *chunk.stride_mut() = stride as i32;
+            *chunk.size_mut() = (frames * stride) as u32;
+        })
+        .register()?;
+
+    // TX: a sink node applications play into
+    let tx_stream = pw::stream::Stream::new(
+        &core,
+        &devices.tx_sink,
+        properties! {
+            *pw::keys::MEDIA_TYPE => "Audio",
+            *pw::keys::MEDIA_CLASS => "Audio/Sink",
+            *pw::keys::NODE_NAME => devices.tx_sink.as_str(),
+            *pw::keys::NODE_DESCRIPTION => format!("{} TX", description),
+            *pw::keys::NODE_LATENCY => format!("{}/{}", audio_tx_rate * LATENCY_MS / 1000, audio_tx_rate),
+        },
+    )?;
+    let tx_queues = queues;
+    let _tx_listener = tx_stream
+        .add_local_listener_with_user_data(())
+        .process(move |stream, _| {
+            // Check if the graph delivered a buffer of TX audio
+            let Some(mut buffer) = stream.dequeue_buffer() else {
+                return;
+            };
+            let datas = buffer.datas_mut();
+            let Some(data) = datas.first_mut() else {
+                return;
+            };
+            let offset = data.chunk().offset() as usize;
+            let size = data.chunk().size() as usize;
+            // Check if the buffer is mapped
+            if let Some(slice) = data.data() {
+                let end = (offset + size).min(slice.len());
+                let mut tx = tx_queues.tx.lock().unwrap();
+                for pair in slice[offset.min(end)..end].chunks_exact(2) {
+                    tx.push_back(i16::from_le_bytes([pair[0], pair[1]]));
+                }
+                let excess = tx.len().saturating_sub(tx_queues.tx_max);
+                tx.drain(..excess);
+                tx_queues.tx_ready.notify_all();
+            }
+        })
+        .register()?;
+
+    let rx_format = format_param(spa::param::audio::AudioFormat::F32LE, audio_rx_rate)?;
+    let tx_format = format_param(spa::param::audio::AudioFormat::S16LE, audio_tx_rate)?;
+    let rx_pod = Pod::from_bytes(&rx_format).ok_or_else(|| anyhow!("invalid RX stream format"))?;
+    let tx_pod = Pod::from_bytes(&tx_format).ok_or_else(|| anyhow!("invalid TX stream format"))?;
+
+    // No AUTOCONNECT: these are devices other streams link to, not streams looking for one
+    rx_stream.connect(
+        spa::utils::Direction::Output,
+        None,
+        pw::stream::StreamFlags::MAP_BUFFERS,
+        &mut [rx_pod],
+    )?;
+    tx_stream.connect(
+        spa::utils::Direction::Input,
+        None,
+        pw::stream::StreamFlags::MAP_BUFFERS,
+        &mut [tx_pod],
+    )?;
+
+    let _ = ready.send(Ok(()));
+    mainloop.run();
+    Ok(())
+}
+
+impl AudioBackend for PipeWireBackend {
+    fn name(&self) -> &'static str {
+        "pipewire"
+    }
+
+    fn write_rx(&self, samples: &[f32]) {
+        let mut rx = self.queues.rx.lock().unwrap();
+        rx.extend(samples.iter().copied());
+        let excess = rx.len().saturating_sub(self.queues.rx_max);
+        rx.drain(..excess);
+    }
+
+    fn rx_delay(&self) -> Option<Duration> {
+        let queued = self.queues.rx.lock().unwrap().len();
+        Some(Duration::from_secs_f64(queued as f64 / self.queues.rx_rate as f64))
+    }
+
+    fn read_tx(&self, samples: &mut [i16]) -> anyhow::Result<()> {
+        let deadline = Instant::now() + Duration::from_millis(TX_READ_TIMEOUT_MS);
+        let mut tx = self.queues.tx.lock().unwrap();
+        while tx.len() < samples.len() {
+            let now = Instant::now();
+            // Check if nothing is playing into the TX sink
+            if now >= deadline {
+                anyhow::bail!("no TX audio from PipeWire");
+            }
+            tx = self.queues.tx_ready.wait_timeout(tx, deadline - now).unwrap().0;
+        }
+        let count = samples.len();
+        for (out, sample) in samples.iter_mut().zip(tx.drain(..count)) {
+            *out = sample;
+        }
+        Ok(())
+    }
+
+    fn tx_ready(&self, samples: usize) -> bool {
+        self.queues.tx.lock().unwrap().len() >= samples
+    }
+
+    fn drain_tx(&self) {
+        self.queues.tx.lock().unwrap().clear();
+    }
+
+    fn close(&self) {
+        // Check if the loop is still running and ask it to quit
+        if let Some(quit) = self.quit.lock().unwrap().take() {
+            let _ = quit.send(());
+        }
+        // Check if the loop thread is still to be joined
+        if let Some(handle) = self.thread.lock().unwrap().take() {
+            let _ = handle.join();
+        }
+    }
+}
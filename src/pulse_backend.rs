@@ -0,0 +1,243 @@
+// PulseAudio backend: null sinks plus a remap source, driven through libpulse-simple
+
+use std::sync::Mutex;
+use std::time::Duration;
+
+use libpulse_binding as pulse;
+use libpulse_binding::def::BufferAttr;
+use libpulse_simple_binding as psimple;
+
+use crate::audio::{AudioBackend, VirtualDevices};
+use crate::pulsectl::{module_arg, PulseControl};
+
+const DRAIN_ATTEMPTS: usize = 10;
+const DRAIN_CHUNK_BYTES: usize = 1024;
+const NULL_SINK_MODULE: &str = "module-null-sink";
+const REMAP_SOURCE_MODULE: &str = "module-remap-source";
+pub const PULSE_CLIENT_NAME: &str = "trusdxAudio";
+
+// Quotes and backslashes would need two levels of escaping in a module argument
+fn properties(description: &str) -> String {
+    let description: String = description.chars().filter(|c| !matches!(c, '\'' | '"' | '\\')).collect();
+    format!("'device.description=\"{}\"'", description)
+}
+
+// Unload our sinks and source left behind by a run that did not exit cleanly
+fn cleanup_trusdx_audio_interfaces(devices: &VirtualDevices) -> anyhow::Result<()> {
+    let mut pulse = PulseControl::connect(PULSE_CLIENT_NAME)?;
+    let mut stale: Vec<(bool, u32)> = Vec::new();
+    for module in pulse.list_modules()? {
+        // Check if this is one of our modules rather than anything mentioning the name
+        if module.name == REMAP_SOURCE_MODULE
+            && module_arg(&module.argument, "source_name").as_deref() == Some(devices.rx_source.as_str())
+        {
+            stale.push((false, module.index));
+        } else if module.name == NULL_SINK_MODULE {
+            let sink = module_arg(&module.argument, "sink_name");
+            // Check if the sink carries one of our names
+            if [&devices.rx_sink, &devices.tx_sink, &devices.legacy_sink].iter().any(|name| sink.as_deref() == Some(name.as_str())) {
+                stale.push((true, module.index));
+            }
+        }
+    }
+    // The remap source goes before the sink it reads from
+    stale.sort();
+    for (_, index) in stale {
+        pulse.unload_module(index)?;
+    }
+    Ok(())
+}
+
+// Returns the indices of the modules we loaded, to be passed to remove_trusdx_audio_interface
+fn create_trusdx_audio_interface(devices: &VirtualDevices, description: &str) -> anyhow::Result<Vec<u32>> {
+    let mut pulse = PulseControl::connect(PULSE_CLIENT_NAME)?;
+    let loads = [
+        (
+            NULL_SINK_MODULE,
+            format!("sink_name={} sink_properties={}", devices.rx_sink, properties(&format!("{} RX (internal)", description))),
+        ),
+        (
+            REMAP_SOURCE_MODULE,
+            format!(
+                "source_name={} master={}.monitor source_properties={}",
+                devices.rx_source,
+                devices.rx_sink,
+                properties(&format!("{} RX", description))
+            ),
+        ),
+        (
+            NULL_SINK_MODULE,
+            format!("sink_name={} sink_properties={}", devices.tx_sink, properties(&format!("{} TX", description))),
+        ),
+    ];
+    let mut modules = Vec::new();
+    for (name, argument) in &loads {
+        match pulse.load_module(name, argument) {
+            Ok(index) => modules.push(index),
+            Err(e) => {
+                // Check if anything was loaded before the failure and take it down again
+                for index in modules.iter().rev() {
+                    let _ = pulse.unload_module(*index);
+                }
+                return Err(e);
+            }
+        }
+    }
+    Ok(modules)
+}
+
+// Unloads in reverse order so the remap source goes before its master sink
+fn remove_trusdx_audio_interface(modules: &[u32]) -> anyhow::Result<()> {
+    let mut pulse = PulseControl::connect(PULSE_CLIENT_NAME)?;
+    for index in modules.iter().rev() {
+        pulse.unload_module(*index)?;
+    }
+    Ok(())
+}
+
+fn create_buffer_attr(rate: u32, sample_size: usize) -> BufferAttr {
+    let sample_size_u32 = sample_size as u32;
+    BufferAttr {
+        maxlength: (rate / 4) * sample_size_u32,
+        tlength: (rate / 50) * sample_size_u32,
+        prebuf: (rate / 100) * sample_size_u32,
+        minreq: (rate / 200) * sample_size_u32,
+        fragsize: (rate / 100) * sample_size_u32,
+    }
+}
+
+pub struct PulseBackend {
+    playback: psimple::Simple,
+    record: psimple::Simple,
+    // Modules we loaded, taken on close so they are unloaded once
+    modules: Mutex<Vec<u32>>,
+}
+
+// Creates the RX/TX devices and opens the playback and record streams on them
+pub fn setup_pulseaudio(
+    devices: &VirtualDevices,
+    description: &str,
+    audio_rx_rate: u32,
+    audio_tx_rate: u32,
+) -> anyhow::Result<PulseBackend> {
+    // Check if a previous run left its devices behind
+    if let Err(e) = cleanup_trusdx_audio_interfaces(devices) {
+        eprintln!("Could not check for stale {} devices: {:#}", devices.tx_sink, e);
+    }
+    let modules = create_trusdx_audio_interface(devices, description)?;
+    match open_streams(devices, audio_rx_rate, audio_tx_rate) {
+        Ok((playback, record)) => Ok(PulseBackend { playback, record, modules: Mutex::new(modules) }),
+        Err(e) => {
+            let _ = remove_trusdx_audio_interface(&modules);
+            Err(e)
+        }
+    }
+}
+
+fn open_streams(
+    devices: &VirtualDevices,
+    audio_rx_rate: u32,
+    audio_tx_rate: u32,
+) -> anyhow::Result<(psimple::Simple, psimple::Simple)> {
+    let monitor_name = format!("{}.monitor", devices.tx_sink);
+    let spec_rx = pulse::sample::Spec {
+        format: pulse::sample::Format::F32le,
+        channels: 1,
+        rate: audio_rx_rate,
+    };
+    let spec_tx = pulse::sample::Spec {
+        format: pulse::sample::Format::S16le,
+        channels: 1,
+        rate: audio_tx_rate,
+    };
+
+    let pb_attr = create_buffer_attr(audio_rx_rate, std::mem::size_of::<f32>());
+    let rec_attr = create_buffer_attr(audio_tx_rate, std::mem::size_of::<i16>());
+
+    let pa_playback = psimple::Simple::new(
+        None,
+        PULSE_CLIENT_NAME,
+        pulse::stream::Direction::Playback,
+        Some(&devices.rx_sink),
+        "Radio RX Audio",
+        &spec_rx,
+        None,
+        Some(&pb_attr),
+    )?;
+
+    let pa_record = psimple::Simple::new(
+        None,
+        PULSE_CLIENT_NAME,
+        pulse::stream::Direction::Record,
+        Some(&monitor_name),
+        "Radio TX Audio",
+        &spec_tx,
+        None,
+        Some(&rec_attr),
+    )?;
+
+    Ok((pa_playback, pa_record))
+}
+
+impl AudioBackend for PulseBackend {
+    fn name(&self) -> &'static str {
+        "pulseaudio"
+    }
+
+    fn write_rx(&self, samples: &[f32]) {
+        write_audio_buffer(&self.playback, samples);
+    }
+
+    fn rx_delay(&self) -> Option<Duration> {
+        self.playback.get_latency().ok().map(|latency| Duration::from_micros(latency.0))
+    }
+
+    fn read_tx(&self, samples: &mut [i16]) -> anyhow::Result<()> {
+        let mut bytes = vec![0u8; samples.len() * 2];
+        self.record.read(&mut bytes)?;
+        bytes_to_i16_le(&bytes, samples);
+        Ok(())
+    }
+
+    fn drain_tx(&self) {
+        let mut drain_buf = vec![0u8; DRAIN_CHUNK_BYTES];
+        for _ in 0..DRAIN_ATTEMPTS {
+            // Check if audio read failed (buffer empty)
+            if self.record.read(&mut drain_buf).is_err() {
+                break;
+            }
+        }
+    }
+
+    fn close(&self) {
+        let modules = std::mem::take(&mut *self.modules.lock().unwrap());
+        // Check if our devices could be unloaded
+        if let Err(e) = remove_trusdx_audio_interface(&modules) {
+            eprintln!("Could not remove the PulseAudio devices: {:#}", e);
+        }
+    }
+}
+
+// Helper function to write audio buffer to pulseaudio (safe conversion)
+fn write_audio_buffer(audio: &psimple::Simple, f32_buf: &[f32]) {
+    // Safe conversion: f32 to bytes using bytemuck-style approach
+    // Since we're writing F32le format, we can safely reinterpret
+    let bytes = unsafe {
+        std::slice::from_raw_parts(
+            f32_buf.as_ptr() as *const u8,
+            std::mem::size_of_val(f32_buf),
+        )
+    };
+    let _ = audio.write(bytes);
+}
+
+// Helper function to convert bytes to i16 samples (little-endian)
+fn bytes_to_i16_le(bytes: &[u8], output: &mut [i16]) {
+    for (index, chunk) in bytes.chunks_exact(2).enumerate() {
+        // Check if output buffer has space for more samples
+        if index >= output.len() {
+            break;
+        }
+        output[index] = i16::from_le_bytes([chunk[0], chunk[1]]);
+    }
+}
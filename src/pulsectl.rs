@@ -0,0 +1,128 @@
+// Minimal synchronous client for PulseAudio module management over the libpulse context API
+
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+
+use anyhow::{anyhow, bail, Result};
+use libpulse_binding as pulse;
+use pulse::callbacks::ListResult;
+use pulse::context::{Context, FlagSet, State};
+use pulse::mainloop::standard::{IterateResult, Mainloop};
+use pulse::operation::{Operation, State as OperationState};
+
+pub struct ModuleEntry {
+    pub index: u32,
+    pub name: String,
+    pub argument: String,
+}
+
+pub struct PulseControl {
+    mainloop: Mainloop,
+    context: Context,
+}
+
+impl PulseControl {
+    pub fn connect(client_name: &str) -> Result<PulseControl> {
+        let mainloop = Mainloop::new().ok_or_else(|| anyhow!("Cannot create PulseAudio mainloop"))?;
+        let mut context = Context::new(&mainloop, client_name).ok_or_else(|| anyhow!("Cannot create PulseAudio context"))?;
+        context.connect(None, FlagSet::NOFLAGS, None)?;
+        let mut control = PulseControl { mainloop, context };
+        loop {
+            control.iterate()?;
+            match control.context.get_state() {
+                State::Ready => return Ok(control),
+                State::Failed | State::Terminated => bail!("Cannot connect to PulseAudio: {}", control.context.errno()),
+                _ => {}
+            }
+        }
+    }
+
+    fn iterate(&mut self) -> Result<()> {
+        match self.mainloop.iterate(true) {
+            IterateResult::Success(_) => Ok(()),
+            IterateResult::Quit(_) => bail!("PulseAudio mainloop quit"),
+            IterateResult::Err(e) => Err(e.into()),
+        }
+    }
+
+    // Run the mainloop until the operation has delivered its callback
+    fn wait<T: ?Sized>(&mut self, op: Operation<T>) -> Result<()> {
+        while op.get_state() == OperationState::Running {
+            self.iterate()?;
+        }
+        Ok(())
+    }
+
+    // Returns the index of the new module
+    pub fn load_module(&mut self, name: &str, argument: &str) -> Result<u32> {
+        let index = Rc::new(Cell::new(pulse::def::INVALID_INDEX));
+        let result = index.clone();
+        let op = self.context.introspect().load_module(name, argument, move |i| result.set(i));
+        self.wait(op)?;
+        // Check if the server refused the module or its arguments
+        if index.get() == pulse::def::INVALID_INDEX {
+            bail!("Cannot load {} ({}): {}", name, argument, self.context.errno());
+        }
+        Ok(index.get())
+    }
+
+    pub fn unload_module(&mut self, index: u32) -> Result<()> {
+        let ok = Rc::new(Cell::new(false));
+        let result = ok.clone();
+        let op = self.context.introspect().unload_module(index, move |success| result.set(success));
+        self.wait(op)?;
+        // Check if the server accepted the unload
+        if !ok.get() {
+            bail!("Cannot unload module {}: {}", index, self.context.errno());
+        }
+        Ok(())
+    }
+
+    pub fn list_modules(&mut self) -> Result<Vec<ModuleEntry>> {
+        let modules = Rc::new(RefCell::new(Vec::new()));
+        let result = modules.clone();
+        let op = self.context.introspect().get_module_info_list(move |item| {
+            // Check if this is a module entry rather than the end of the list
+            if let ListResult::Item(info) = item {
+                result.borrow_mut().push(ModuleEntry {
+                    index: info.index,
+                    name: info.name.as_deref().unwrap_or_default().to_string(),
+                    argument: info.argument.as_deref().unwrap_or_default().to_string(),
+                });
+            }
+        });
+        self.wait(op)?;
+        Ok(modules.take())
+    }
+}
+
+impl Drop for PulseControl {
+    fn drop(&mut self) {
+        self.context.disconnect();
+    }
+}
+
+// Value of key=value in a module argument string, honouring '...' and "..." quoting
+pub fn module_arg(argument: &str, key: &str) -> Option<String> {
+    let mut rest = argument.trim_start();
+    while !rest.is_empty() {
+        let (name, after) = rest.split_once('=')?;
+        let (value, after) = match after.chars().next() {
+            Some(quote @ ('\'' | '"')) => {
+                let body = &after[1..];
+                let end = body.find(quote).unwrap_or(body.len());
+                (&body[..end], body.get(end + 1..).unwrap_or(""))
+            }
+            _ => {
+                let end = after.find(char::is_whitespace).unwrap_or(after.len());
+                (&after[..end], &after[end..])
+            }
+        };
+        // Check if this is the key we are looking for
+        if name.trim() == key {
+            return Some(value.to_string());
+        }
+        rest = after.trim_start();
+    }
+    None
+}
@@ -0,0 +1,248 @@
+// 16-bit TX audio to the radio's 8-bit unsigned stream
+//
+// Plain truncation leaves quantization error that follows the signal and turns into spurious
+// products on the air. TPDF dither makes the error a steady noise floor instead, and noise shaping
+// moves most of that noise above the SSB passband where the radio's filter removes it.
+
+// Code the radio reads as the end of a CAT command, never sent as audio
+const RESERVED: u8 = b';';
+// Error feedback coefficients for a noise transfer function of 1 - c1 z^-1 - c2 z^-2, chosen
+// for the least noise between 200 and 2800 Hz at the 11520 Hz TX rate
+const SHAPING: [f32; 2] = [1.08, -0.72];
+// Largest error fed back, so a clipped peak cannot set the shaper ringing. Dither and rounding
+// reach 1.5 LSB and the detour around `;` adds up to 1 LSB, so only clipped samples get limited
+const MAX_FEEDBACK: f32 = 2.5;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TxQuantizer {
+    // Original conversion: truncate, then send `;` as `:`
+    Truncate,
+    // Rounding with triangular dither
+    Tpdf,
+    // Dither plus second-order noise shaping
+    Shaped,
+}
+
+impl TxQuantizer {
+    pub fn from_name(name: &str) -> Option<TxQuantizer> {
+        match name {
+            "truncate" => Some(TxQuantizer::Truncate),
+            "tpdf" => Some(TxQuantizer::Tpdf),
+            "shaped" => Some(TxQuantizer::Shaped),
+            _ => None,
+        }
+    }
+}
+
+pub struct Quantizer {
+    // xorshift32 state for the dither
+    seed: u32,
+    // Last two quantization errors, newest first
+    error: [f32; 2],
+}
+
+impl Quantizer {
+    pub fn new() -> Quantizer {
+        Quantizer {
+            seed: 0x2545_f491,
+            error: [0.0; 2],
+        }
+    }
+
+    // Forget the shaper history, e.g. at the start of a transmission
+    pub fn reset(&mut self) {
+        self.error = [0.0; 2];
+    }
+
+    // Helper function for uniform noise in [-0.5, 0.5) LSB
+    fn uniform(&mut self) -> f32 {
+        self.seed ^= self.seed << 13;
+        self.seed ^= self.seed >> 17;
+        self.seed ^= self.seed << 5;
+        (self.seed >> 8) as f32 / (1u32 << 24) as f32 - 0.5
+    }
+
+    // Convert `input` (full scale = 1.0) into `output`, which must be the same length
+    pub fn convert(&mut self, kind: TxQuantizer, input: &[f32], output: &mut [u8]) {
+        for (out, &sample) in output.iter_mut().zip(input) {
+            let scaled = (sample * 32768.0).clamp(-32768.0, 32767.0);
+            *out = match kind {
+                TxQuantizer::Truncate => {
+                    let byte = (128i16 + (scaled as i16 / 256)).clamp(0, 255) as u8;
+                    // Check if byte is semicolon (needs escaping)
+                    if byte == RESERVED {
+                        b':'
+                    } else {
+                        byte
+                    }
+                }
+                TxQuantizer::Tpdf => self.quantize(128.0 + scaled / 256.0, false),
+                TxQuantizer::Shaped => self.quantize(128.0 + scaled / 256.0, true),
+            };
+        }
+    }
+
+    // Helper function to quantize one sample given in 8-bit units
+    fn quantize(&mut self, value: f32, shaped: bool) -> u8 {
+        let target = if shaped {
+            value - SHAPING[0] * self.error[0] - SHAPING[1] * self.error[1]
+        } else {
+            value
+        };
+        let dithered = target + self.uniform() + self.uniform();
+        let mut code = dithered.round().clamp(0.0, 255.0) as u8;
+        // Check if the reserved code came up; take whichever neighbour is closer
+        if code == RESERVED {
+            code = if dithered < RESERVED as f32 { RESERVED - 1 } else { RESERVED + 1 };
+        }
+        // Check if the error should be shaped by the next samples
+        if shaped {
+            self.error[1] = self.error[0];
+            self.error[0] = (code as f32 - target).clamp(-MAX_FEEDBACK, MAX_FEEDBACK);
+        }
+        code
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::PI;
+
+    const RATE: usize = 11520;
+    const KINDS: [TxQuantizer; 3] = [TxQuantizer::Truncate, TxQuantizer::Tpdf, TxQuantizer::Shaped];
+
+    // Helper function for one second of a sum of tones, each `level` dBFS, at the TX rate
+    fn tone(tones: &[f64], level: f64) -> Vec<f32> {
+        let amplitude = 10f64.powf(level / 20.0);
+        (0..RATE)
+            .map(|n| {
+                let t = n as f64 / RATE as f64;
+                tones.iter().map(|f| amplitude * (2.0 * PI * f * t).sin()).sum::<f64>() as f32
+            })
+            .collect()
+    }
+
+    // Helper function to quantize `input` with a fresh quantizer
+    fn run(kind: TxQuantizer, input: &[f32]) -> Vec<u8> {
+        let mut output = vec![0u8; input.len()];
+        Quantizer::new().convert(kind, input, &mut output);
+        output
+    }
+
+    // Helper function for the power in each 1 Hz bin from 200 to 2800 Hz, indexed by frequency
+    fn spectrum(output: &[u8]) -> Vec<f64> {
+        let samples: Vec<f64> = output.iter().map(|&b| (b as f64 - 128.0) / 128.0).collect();
+        let (cos, sin): (Vec<f64>, Vec<f64>) = (0..RATE)
+            .map(|n| {
+                let phase = 2.0 * PI * n as f64 / RATE as f64;
+                (phase.cos(), phase.sin())
+            })
+            .unzip();
+        (0..=2800)
+            .map(|k| {
+                if k < 200 {
+                    return 0.0;
+                }
+                let (mut re, mut im) = (0.0, 0.0);
+                for (n, &x) in samples.iter().enumerate() {
+                    let i = k * n % RATE;
+                    re += x * cos[i];
+                    im += x * sin[i];
+                }
+                re * re + im * im
+            })
+            .collect()
+    }
+
+    // Helper function for the SNR in dB and the strongest of `products` in dBc
+    fn measure(power: &[f64], tones: &[usize], products: &[usize]) -> (f64, f64) {
+        let signal: f64 = tones.iter().map(|&k| power[k]).sum();
+        let noise = power.iter().sum::<f64>() - signal;
+        let product = products.iter().map(|&k| power[k]).fold(0.0, f64::max);
+        (
+            10.0 * (signal / noise).log10(),
+            10.0 * (product * tones.len() as f64 / signal).log10(),
+        )
+    }
+
+    #[test]
+    fn parses_names() {
+        assert_eq!(TxQuantizer::from_name("shaped"), Some(TxQuantizer::Shaped));
+        assert_eq!(TxQuantizer::from_name("tpdf"), Some(TxQuantizer::Tpdf));
+        assert_eq!(TxQuantizer::from_name("truncate"), Some(TxQuantizer::Truncate));
+        assert_eq!(TxQuantizer::from_name("round"), None);
+    }
+
+    #[test]
+    fn single_tone_snr() {
+        // Minimum SNR from 200 to 2800 Hz for a 1 kHz tone at -20 and -40 dBFS
+        let cases = [
+            (TxQuantizer::Truncate, [30.0, 11.0]),
+            (TxQuantizer::Tpdf, [27.0, 7.0]),
+            (TxQuantizer::Shaped, [32.0, 12.0]),
+        ];
+        for (kind, minimum) in cases {
+            for (level, minimum) in [-20.0, -40.0].into_iter().zip(minimum) {
+                let power = spectrum(&run(kind, &tone(&[1000.0], level)));
+                let (snr, _) = measure(&power, &[1000], &[]);
+                assert!(snr >= minimum, "{:?} at {} dBFS: SNR {:.1} dB", kind, level, snr);
+            }
+        }
+    }
+
+    #[test]
+    fn two_tone_snr_and_imd() {
+        // Minimum SNR and maximum IMD3 (700 and 1600 Hz) for 1000 + 1300 Hz at -20 and -40 dBFS
+        let cases = [
+            (TxQuantizer::Truncate, [(31.0, -38.0), (10.0, -14.0)]),
+            (TxQuantizer::Tpdf, [(30.0, -55.0), (10.0, -40.0)]),
+            (TxQuantizer::Shaped, [(35.0, -60.0), (15.0, -40.0)]),
+        ];
+        for (kind, limits) in cases {
+            for (level, (min_snr, max_imd)) in [-20.0, -40.0].into_iter().zip(limits) {
+                let power = spectrum(&run(kind, &tone(&[1000.0, 1300.0], level)));
+                let (snr, imd) = measure(&power, &[1000, 1300], &[700, 1600]);
+                assert!(snr >= min_snr, "{:?} at {} dBFS: SNR {:.1} dB", kind, level, snr);
+                assert!(imd <= max_imd, "{:?} at {} dBFS: IMD3 {:.1} dBc", kind, level, imd);
+            }
+        }
+    }
+
+    #[test]
+    fn never_sends_reserved_code() {
+        // A full-scale tone, an overdriven one and a slow ramp all cross the code for `;`
+        let ramp: Vec<f32> = (0..RATE).map(|n| n as f32 / RATE as f32 * 2.0 - 1.0).collect();
+        for kind in KINDS {
+            for input in [tone(&[1000.0], 0.0), tone(&[1000.0, 1300.0], 0.0), ramp.clone()] {
+                assert!(!run(kind, &input).contains(&RESERVED), "{:?}", kind);
+            }
+        }
+    }
+
+    #[test]
+    fn feeds_back_whole_error_when_not_clipping() {
+        // A full-scale tone never clips, so the error the shaper keeps must be the real one
+        let mut quantizer = Quantizer::new();
+        for sample in tone(&[1000.0], -0.5) {
+            let value = 128.0 + sample * 128.0;
+            let target = value - SHAPING[0] * quantizer.error[0] - SHAPING[1] * quantizer.error[1];
+            let mut out = [0u8];
+            quantizer.convert(TxQuantizer::Shaped, &[sample], &mut out);
+            assert!((quantizer.error[0] - (out[0] as f32 - target)).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn shaper_settles_after_clipping() {
+        // Two overdriven tones hit both rails, then silence must come out near the midpoint
+        let mut quantizer = Quantizer::new();
+        let mut clipped = vec![0u8; RATE];
+        quantizer.convert(TxQuantizer::Shaped, &tone(&[1000.0, 1300.0], 6.0), &mut clipped);
+        assert!(clipped.contains(&0) && clipped.contains(&255));
+
+        let mut silence = vec![0u8; RATE];
+        quantizer.convert(TxQuantizer::Shaped, &vec![0.0; RATE], &mut silence);
+        assert!(silence[100..].iter().all(|&b| (124..=132).contains(&b)), "{:?}", &silence[100..120]);
+    }
+}
@@ -0,0 +1,114 @@
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::trusdx;
+
+const SUPERVISOR_INTERVAL_MS: u64 = 500;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LinkState {
+    Connected,
+    Disconnected,
+    Reconnecting,
+}
+
+impl LinkState {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LinkState::Connected => "CONNECTED",
+            LinkState::Disconnected => "DISCONNECTED",
+            LinkState::Reconnecting => "RECONNECTING",
+        }
+    }
+
+    fn to_u8(self) -> u8 {
+        match self {
+            LinkState::Connected => 0,
+            LinkState::Disconnected => 1,
+            LinkState::Reconnecting => 2,
+        }
+    }
+
+    fn from_u8(v: u8) -> LinkState {
+        match v {
+            0 => LinkState::Connected,
+            2 => LinkState::Reconnecting,
+            _ => LinkState::Disconnected,
+        }
+    }
+}
+
+static LINK_STATE: AtomicU8 = AtomicU8::new(0);
+
+pub fn link_state() -> LinkState { LinkState::from_u8(LINK_STATE.load(Ordering::Relaxed)) }
+pub fn is_connected() -> bool { link_state() == LinkState::Connected }
+fn set_link_state(state: LinkState) { LINK_STATE.store(state.to_u8(), Ordering::Relaxed); }
+
+// Timeouts are normal on the 10 ms serial reads; anything else means the port is gone
+pub fn is_link_error(e: &std::io::Error) -> bool {
+    !matches!(
+        e.kind(),
+        std::io::ErrorKind::TimedOut | std::io::ErrorKind::WouldBlock | std::io::ErrorKind::Interrupted
+    )
+}
+
+pub fn note_io_error(e: &std::io::Error) {
+    // Check if error indicates the device went away while we thought it was up
+    if is_link_error(e) && is_connected() {
+        set_link_state(LinkState::Disconnected);
+    }
+}
+
+fn reopen(
+    selector: &trusdx::DeviceSelector,
+    ser: &Arc<Mutex<Box<dyn serialport::SerialPort + Send>>>,
+) -> anyhow::Result<String> {
+    let path = trusdx::find_trusdx_device(selector)?;
+    let mut port = trusdx::open_serial_path(&path)?;
+    let _ = trusdx::control_rts(&mut *port, false);
+    let _ = trusdx::control_dtr(&mut *port, true);
+    trusdx::enable_streaming_speaker_off(&mut *port)?;
+    *ser.lock().unwrap() = port;
+    Ok(path)
+}
+
+pub fn spawn_reconnect_supervisor(
+    selector: trusdx::DeviceSelector,
+    device_path: String,
+    ser: Arc<Mutex<Box<dyn serialport::SerialPort + Send>>>,
+    tx_state: Arc<Mutex<bool>>,
+    streaming_started: Arc<AtomicBool>,
+    shutting_down: Arc<AtomicBool>,
+) {
+    thread::spawn(move || {
+        let mut device_path = device_path;
+        loop {
+            // Check if shutdown flag is set
+            if shutting_down.load(Ordering::Relaxed) { break; }
+            thread::sleep(Duration::from_millis(SUPERVISOR_INTERVAL_MS));
+
+            match link_state() {
+                LinkState::Connected => {
+                    // Check if the device node vanished without any I/O noticing yet
+                    if !Path::new(&device_path).exists() {
+                        set_link_state(LinkState::Disconnected);
+                    }
+                }
+                LinkState::Disconnected | LinkState::Reconnecting => {
+                    set_link_state(LinkState::Reconnecting);
+                    // The radio comes back in RX, so drop any stale TX request first
+                    *tx_state.lock().unwrap() = false;
+                    streaming_started.store(false, Ordering::Relaxed);
+                    // Check if the device is back and could be opened
+                    if let Ok(path) = reopen(&selector, &ser) {
+                        device_path = path;
+                        set_link_state(LinkState::Connected);
+                    }
+                }
+            }
+        }
+    });
+}
@@ -0,0 +1,262 @@
+// Recording of the radio audio to WAV files, one file per stream
+//
+// Files are named <UTC start>_<frequency>_<mode>_<rx|tx>.wav, with _2, _3 and so on added when a
+// split reuses the same second, and hold 16-bit mono audio at the radio rates. The header is
+// brought up to date every second, so files stay playable even if the driver is killed.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+
+use crate::audio::AudioSettings;
+use crate::cat::Mode;
+
+const RECORD_DIR: &str = "trusdx/recordings";
+const HEADER_UPDATE_INTERVAL: Duration = Duration::from_secs(1);
+const WAV_HEADER_LEN: u32 = 44;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RecordSplit {
+    // One file per stream for as long as recording is on
+    Off,
+    // New files whenever the radio switches between RX and TX
+    Over,
+    // New files at the start of every UTC minute
+    Minute,
+}
+
+impl RecordSplit {
+    pub fn from_name(name: &str) -> Option<RecordSplit> {
+        match name {
+            "off" => Some(RecordSplit::Off),
+            "over" => Some(RecordSplit::Over),
+            "minute" => Some(RecordSplit::Minute),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            RecordSplit::Off => "off",
+            RecordSplit::Over => "over",
+            RecordSplit::Minute => "minute",
+        }
+    }
+}
+
+// $XDG_DATA_HOME/trusdx/recordings, falling back to ~/.local/share
+pub fn default_dir() -> PathBuf {
+    let base = std::env::var_os("XDG_DATA_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/share")))
+        .unwrap_or_default();
+    base.join(RECORD_DIR)
+}
+
+struct WavFile {
+    writer: BufWriter<File>,
+    samples: u32,
+    last_header: Instant,
+}
+
+impl WavFile {
+    // Fails with AlreadyExists rather than overwrite an earlier recording
+    fn create(path: &Path, rate: u32) -> io::Result<WavFile> {
+        let file = OpenOptions::new().write(true).create_new(true).open(path)?;
+        let mut wav = WavFile {
+            writer: BufWriter::new(file),
+            samples: 0,
+            last_header: Instant::now(),
+        };
+        wav.write_header(rate)?;
+        Ok(wav)
+    }
+
+    // Helper function for the RIFF header; the sizes cover the samples written so far
+    fn write_header(&mut self, rate: u32) -> io::Result<()> {
+        let data_len = self.samples * 2;
+        let mut header = Vec::with_capacity(WAV_HEADER_LEN as usize);
+        header.extend_from_slice(b"RIFF");
+        header.extend_from_slice(&(WAV_HEADER_LEN - 8 + data_len).to_le_bytes());
+        header.extend_from_slice(b"WAVEfmt ");
+        header.extend_from_slice(&16u32.to_le_bytes());
+        // PCM, mono
+        header.extend_from_slice(&1u16.to_le_bytes());
+        header.extend_from_slice(&1u16.to_le_bytes());
+        header.extend_from_slice(&rate.to_le_bytes());
+        header.extend_from_slice(&(rate * 2).to_le_bytes());
+        header.extend_from_slice(&2u16.to_le_bytes());
+        header.extend_from_slice(&16u16.to_le_bytes());
+        header.extend_from_slice(b"data");
+        header.extend_from_slice(&data_len.to_le_bytes());
+
+        self.writer.flush()?;
+        let file = self.writer.get_mut();
+        file.seek(SeekFrom::Start(0))?;
+        file.write_all(&header)?;
+        file.seek(SeekFrom::End(0))?;
+        self.last_header = Instant::now();
+        Ok(())
+    }
+
+    fn write(&mut self, samples: &[f32], rate: u32) -> io::Result<()> {
+        for &sample in samples {
+            let value = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+            self.writer.write_all(&value.to_le_bytes())?;
+        }
+        self.samples = self.samples.saturating_add(samples.len() as u32);
+        // Check if the header is due to catch up with the data
+        if self.last_header.elapsed() >= HEADER_UPDATE_INTERVAL {
+            self.write_header(rate)?;
+        }
+        Ok(())
+    }
+}
+
+// One recorded stream: its rate, open file and file name suffix
+struct Stream {
+    rate: u32,
+    suffix: &'static str,
+    file: Option<WavFile>,
+}
+
+impl Stream {
+    fn close(&mut self) {
+        // Check if a file is open and needs its final sizes
+        if let Some(mut wav) = self.file.take() {
+            let _ = wav.write_header(self.rate);
+        }
+    }
+}
+
+pub struct Recorder {
+    dir: PathBuf,
+    rx: Stream,
+    tx: Stream,
+    // UTC minute and direction of the last audio, to know when a new segment starts
+    minute: i64,
+    transmitting: bool,
+}
+
+impl Recorder {
+    pub fn new(dir: PathBuf, rx_rate: u32, tx_rate: u32) -> Recorder {
+        Recorder {
+            dir,
+            rx: Stream { rate: rx_rate, suffix: "rx", file: None },
+            tx: Stream { rate: tx_rate, suffix: "tx", file: None },
+            minute: 0,
+            transmitting: false,
+        }
+    }
+
+    // Finish any open files, e.g. when recording is switched off
+    pub fn stop(&mut self) {
+        self.rx.close();
+        self.tx.close();
+    }
+
+    // Helper function to end the current segment where the split setting asks for it
+    fn roll(&mut self, settings: &AudioSettings, transmitting: bool) {
+        let minute = chrono::Utc::now().timestamp().div_euclid(60);
+        // Check if this audio belongs in a new segment
+        let new_segment = match settings.record_split {
+            RecordSplit::Off => false,
+            RecordSplit::Over => transmitting != self.transmitting,
+            RecordSplit::Minute => minute != self.minute,
+        };
+        if new_segment {
+            self.stop();
+        }
+        self.minute = minute;
+        self.transmitting = transmitting;
+    }
+
+    // Received audio as converted from the radio's 8-bit stream
+    pub fn rx(&mut self, settings: &AudioSettings, samples: &[f32], freq_hz: u64, mode: Mode) -> anyhow::Result<()> {
+        self.roll(settings, false);
+        write_stream(&self.dir, &mut self.rx, samples, freq_hz, mode)
+    }
+
+    // Transmitted audio as it goes to the 8-bit conversion
+    pub fn tx(&mut self, settings: &AudioSettings, samples: &[f32], freq_hz: u64, mode: Mode) -> anyhow::Result<()> {
+        self.roll(settings, true);
+        // Check if TX audio is wanted at all
+        if !settings.record_tx {
+            self.tx.close();
+            return Ok(());
+        }
+        write_stream(&self.dir, &mut self.tx, samples, freq_hz, mode)
+    }
+}
+
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+// Helper function to append to a stream, opening a new file named after the moment it starts
+fn write_stream(dir: &Path, stream: &mut Stream, samples: &[f32], freq_hz: u64, mode: Mode) -> anyhow::Result<()> {
+    let wav = match &mut stream.file {
+        Some(wav) => wav,
+        None => {
+            std::fs::create_dir_all(dir).with_context(|| format!("Cannot create {}", dir.display()))?;
+            let stem = format!(
+                "{}_{}Hz_{}_{}",
+                chrono::Utc::now().format("%Y%m%dT%H%M%SZ"),
+                freq_hz,
+                mode.name(),
+                stream.suffix
+            );
+            let wav = create_unique(dir, &stem, stream.rate)?;
+            stream.file.insert(wav)
+        }
+    };
+    wav.write(samples, stream.rate).context("Cannot write the recording")
+}
+
+// Helper function to create <stem>.wav, or <stem>_2.wav and so on when a file started in the
+// same second already has the name
+fn create_unique(dir: &Path, stem: &str, rate: u32) -> anyhow::Result<WavFile> {
+    for n in 1.. {
+        let path = match n {
+            1 => dir.join(format!("{}.wav", stem)),
+            _ => dir.join(format!("{}_{}.wav", stem, n)),
+        };
+        match WavFile::create(&path, rate) {
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            result => return result.with_context(|| format!("Cannot create {}", path.display())),
+        }
+    }
+    unreachable!()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_in_the_same_second_get_their_own_files() {
+        let dir = std::env::temp_dir().join(format!("trusdx-record-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let stem = "20261017T143000Z_14074000Hz_USB_rx";
+        for len in [4, 8, 12] {
+            let mut wav = create_unique(&dir, stem, 7812).unwrap();
+            wav.write(&vec![0.5; len], 7812).unwrap();
+            wav.write_header(7812).unwrap();
+        }
+
+        // Check if every file kept its own samples
+        for (name, len) in [("", 4), ("_2", 8), ("_3", 12)] {
+            let path = dir.join(format!("{}{}.wav", stem, name));
+            let size = std::fs::metadata(&path).unwrap().len();
+            assert_eq!(size, WAV_HEADER_LEN as u64 + len * 2, "{}", path.display());
+        }
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
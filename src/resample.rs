@@ -0,0 +1,377 @@
+// Windowed-sinc resampler between the radio's stream rates and a standard device rate
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::audio::AudioBackend;
+
+// Kernel offsets tabulated per input sample; in between them the taps are interpolated
+const PHASES: usize = 256;
+const I16_SCALE: f32 = 32768.0;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ResampleQuality {
+    Fast,
+    Medium,
+    High,
+}
+
+impl ResampleQuality {
+    pub fn from_name(name: &str) -> Option<ResampleQuality> {
+        match name {
+            "fast" => Some(ResampleQuality::Fast),
+            "medium" => Some(ResampleQuality::Medium),
+            "high" => Some(ResampleQuality::High),
+            _ => None,
+        }
+    }
+
+    // (kernel half-length in samples of the lower rate, Kaiser beta, passband as a fraction of the lower Nyquist frequency)
+    fn params(self) -> (usize, f64, f64) {
+        match self {
+            ResampleQuality::Fast => (8, 6.0, 0.85),
+            ResampleQuality::Medium => (16, 8.0, 0.90),
+            ResampleQuality::High => (32, 10.0, 0.94),
+        }
+    }
+}
+
+// Helper function for the zeroth-order modified Bessel function (Kaiser window)
+fn bessel_i0(x: f64) -> f64 {
+    let mut sum = 1.0;
+    let mut term = 1.0;
+    let half = x / 2.0;
+    for k in 1..50 {
+        term *= half / k as f64;
+        sum += term * term;
+        // Check if the series has converged
+        if term * term < sum * 1e-12 {
+            break;
+        }
+    }
+    sum
+}
+
+fn sinc(x: f64) -> f64 {
+    // Check if at the removable singularity
+    if x.abs() < 1e-9 {
+        1.0
+    } else {
+        (std::f64::consts::PI * x).sin() / (std::f64::consts::PI * x)
+    }
+}
+
+// Streaming converter with an arbitrary, adjustable ratio
+pub struct Resampler {
+    half_len: usize,
+    // PHASES + 1 rows of 2 * half_len taps
+    table: Vec<f32>,
+    nominal_step: f64,
+    // Input samples per output sample
+    step: f64,
+    // Position of the next output sample within `history`
+    pos: f64,
+    history: Vec<f32>,
+}
+
+impl Resampler {
+    pub fn new(from_rate: u32, to_rate: u32, quality: ResampleQuality) -> Resampler {
+        let (span, beta, passband) = quality.params();
+        let scale = (to_rate as f64 / from_rate as f64).min(1.0);
+        // Cutoff relative to the input Nyquist frequency, low enough to stop aliasing when decimating
+        let cutoff = passband * scale;
+        // A lower cutoff needs a proportionally longer kernel for the same transition band
+        let half_len = (span as f64 / scale).ceil() as usize;
+        let taps = 2 * half_len;
+        let mut table = Vec::with_capacity((PHASES + 1) * taps);
+        for phase in 0..=PHASES {
+            let frac = phase as f64 / PHASES as f64;
+            let start = table.len();
+            for tap in 0..taps {
+                let t = tap as f64 - (half_len - 1) as f64 - frac;
+                let x = t / half_len as f64;
+                // Check if the tap falls outside the window, so adjacent rows line up exactly
+                if x.abs() >= 1.0 {
+                    table.push(0.0);
+                    continue;
+                }
+                let window = bessel_i0(beta * (1.0 - x * x).sqrt()) / bessel_i0(beta);
+                table.push((cutoff * sinc(cutoff * t) * window) as f32);
+            }
+            // Unity gain at DC for every phase
+            let sum: f32 = table[start..].iter().sum();
+            for tap in &mut table[start..] {
+                *tap /= sum;
+            }
+        }
+        let step = from_rate as f64 / to_rate as f64;
+        let mut resampler = Resampler {
+            half_len,
+            table,
+            nominal_step: step,
+            step,
+            pos: 0.0,
+            history: Vec::new(),
+        };
+        resampler.reset();
+        resampler
+    }
+
+    // Scale the ratio by `factor` (input clock over its nominal rate, relative to the output clock)
+    pub fn set_correction(&mut self, factor: f64) {
+        self.step = self.nominal_step * factor;
+    }
+
+    // Forget buffered input, e.g. after a gap in the stream
+    pub fn reset(&mut self) {
+        self.history.clear();
+        self.history.resize(self.half_len - 1, 0.0);
+        self.pos = (self.half_len - 1) as f64;
+    }
+
+    // Append every output sample `input` makes available; the rest waits for the next call
+    pub fn process(&mut self, input: &[f32], output: &mut Vec<f32>) {
+        self.history.extend_from_slice(input);
+        let taps = 2 * self.half_len;
+        loop {
+            let centre = self.pos as usize;
+            // Check if the kernel still fits in the input received so far
+            if centre + self.half_len >= self.history.len() {
+                break;
+            }
+            let scaled = (self.pos - centre as f64) * PHASES as f64;
+            let phase = (scaled as usize).min(PHASES - 1);
+            let mix = (scaled - phase as f64) as f32;
+            let window = &self.history[centre + 1 - self.half_len..centre + 1 + self.half_len];
+            let lower = &self.table[phase * taps..(phase + 1) * taps];
+            let upper = &self.table[(phase + 1) * taps..(phase + 2) * taps];
+            let mut sum = 0.0f32;
+            for ((sample, a), b) in window.iter().zip(lower).zip(upper) {
+                sum += sample * (a + (b - a) * mix);
+            }
+            output.push(sum);
+            self.pos += self.step;
+        }
+        // Drop input that no later output sample reaches
+        let consumed = (self.pos as usize + 1 - self.half_len).min(self.history.len());
+        self.history.drain(..consumed);
+        self.pos -= consumed as f64;
+    }
+}
+
+struct TxState {
+    resampler: Resampler,
+    // Radio-rate samples converted but not yet handed to the bridge
+    pending: VecDeque<f32>,
+    device_buf: Vec<i16>,
+    // device_buf as floats, the resampler's input
+    input: Vec<f32>,
+    converted: Vec<f32>,
+}
+
+// Runs another backend at the device rate and converts to and from the radio rates in between
+pub struct ResampledBackend {
+    inner: Arc<dyn AudioBackend>,
+    rx: Mutex<(Resampler, Vec<f32>)>,
+    tx: Mutex<TxState>,
+    device_rate: u32,
+    tx_rate: u32,
+}
+
+impl ResampledBackend {
+    pub fn new(inner: Arc<dyn AudioBackend>, rx_rate: u32, tx_rate: u32, device_rate: u32, quality: ResampleQuality) -> ResampledBackend {
+        ResampledBackend {
+            inner,
+            rx: Mutex::new((Resampler::new(rx_rate, device_rate, quality), Vec::new())),
+            tx: Mutex::new(TxState {
+                resampler: Resampler::new(device_rate, tx_rate, quality),
+                pending: VecDeque::new(),
+                device_buf: Vec::new(),
+                input: Vec::new(),
+                converted: Vec::new(),
+            }),
+            device_rate,
+            tx_rate,
+        }
+    }
+}
+
+impl AudioBackend for ResampledBackend {
+    fn name(&self) -> &'static str {
+        self.inner.name()
+    }
+
+    fn write_rx(&self, samples: &[f32]) {
+        let mut rx = self.rx.lock().unwrap();
+        let (resampler, out) = &mut *rx;
+        out.clear();
+        resampler.process(samples, out);
+        self.inner.write_rx(out);
+    }
+
+    fn read_tx(&self, samples: &mut [i16]) -> anyhow::Result<()> {
+        let mut tx = self.tx.lock().unwrap();
+        let tx = &mut *tx;
+        while tx.pending.len() < samples.len() {
+            let missing = (samples.len() - tx.pending.len()) as u64;
+            let chunk = (missing * self.device_rate as u64).div_ceil(self.tx_rate as u64) as usize;
+            tx.device_buf.resize(chunk, 0);
+            self.inner.read_tx(&mut tx.device_buf)?;
+            tx.input.clear();
+            tx.input.extend(tx.device_buf.iter().map(|&s| s as f32 / I16_SCALE));
+            tx.converted.clear();
+            tx.resampler.process(&tx.input, &mut tx.converted);
+            tx.pending.extend(tx.converted.iter().copied());
+        }
+        let count = samples.len();
+        for (out, sample) in samples.iter_mut().zip(tx.pending.drain(..count)) {
+            *out = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+        }
+        Ok(())
+    }
+
+    fn tx_ready(&self, samples: usize) -> bool {
+        let pending = self.tx.lock().unwrap().pending.len();
+        // Check if converted audio already covers the request, else ask for the device-rate remainder
+        pending >= samples
+            || self.inner.tx_ready(((samples - pending) as u64 * self.device_rate as u64).div_ceil(self.tx_rate as u64) as usize)
+    }
+
+    fn drain_tx(&self) {
+        self.inner.drain_tx();
+        let mut tx = self.tx.lock().unwrap();
+        tx.pending.clear();
+        tx.resampler.reset();
+    }
+
+    fn set_clock_correction(&self, ppm: f64) {
+        let factor = 1.0 + ppm * 1e-6;
+        // RX input and TX output both run at the radio clock
+        self.rx.lock().unwrap().0.set_correction(factor);
+        self.tx.lock().unwrap().resampler.set_correction(1.0 / factor);
+    }
+
+    fn corrects_clock(&self) -> bool {
+        true
+    }
+
+    fn rx_delay(&self) -> Option<Duration> {
+        self.inner.rx_delay()
+    }
+
+    fn close(&self) {
+        self.inner.close();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::PI;
+
+    const QUALITIES: [ResampleQuality; 3] = [ResampleQuality::Fast, ResampleQuality::Medium, ResampleQuality::High];
+
+    fn tone(freq: f64, rate: u32, len: usize) -> Vec<f32> {
+        (0..len).map(|n| (2.0 * PI * freq * n as f64 / rate as f64).sin() as f32).collect()
+    }
+
+    // Helper function for the amplitude of `freq` in `samples`, in dB relative to full scale
+    fn level_db(samples: &[f32], freq: f64, rate: u32) -> f64 {
+        let (mut re, mut im) = (0.0, 0.0);
+        for (n, &x) in samples.iter().enumerate() {
+            let phase = 2.0 * PI * freq * n as f64 / rate as f64;
+            re += x as f64 * phase.cos();
+            im += x as f64 * phase.sin();
+        }
+        20.0 * (2.0 * (re * re + im * im).sqrt() / samples.len() as f64).log10()
+    }
+
+    // Helper function to convert `input` fed in blocks of the given sizes, cycling through them
+    fn run(resampler: &mut Resampler, input: &[f32], blocks: &[usize]) -> Vec<f32> {
+        let mut output = Vec::new();
+        let mut rest = input;
+        for &block in blocks.iter().cycle() {
+            // Check if all input has been fed
+            if rest.is_empty() {
+                break;
+            }
+            let (chunk, tail) = rest.split_at(block.min(rest.len()));
+            resampler.process(chunk, &mut output);
+            rest = tail;
+        }
+        output
+    }
+
+    #[test]
+    fn unity_gain_at_dc() {
+        for quality in QUALITIES {
+            for (from, to) in [(7812, 48000), (48000, 11520), (44100, 7812)] {
+                let mut resampler = Resampler::new(from, to, quality);
+                let output = run(&mut resampler, &vec![0.5; from as usize], &[4096]);
+                let settled = &output[output.len() / 2..];
+                assert!(settled.iter().all(|&s| (s - 0.5).abs() < 1e-3), "{:?} {} -> {}", quality, from, to);
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_tones_above_the_lower_nyquist() {
+        // (quality, dB below a full-scale 7 kHz tone going 48000 -> 11520, and below a 3 kHz
+        // tone's image at 4812 Hz going 7812 -> 48000)
+        let cases = [
+            (ResampleQuality::Fast, 60.0, 65.0),
+            (ResampleQuality::Medium, 85.0, 72.0),
+            (ResampleQuality::High, 100.0, 80.0),
+        ];
+        for (quality, down, up) in cases {
+            let mut resampler = Resampler::new(48000, 11520, quality);
+            let output = run(&mut resampler, &tone(7000.0, 48000, 48000), &[4096]);
+            let settled = &output[200..];
+            let power = settled.iter().map(|&s| (s as f64).powi(2)).sum::<f64>() / settled.len() as f64;
+            let leaked = 10.0 * (2.0 * power).log10();
+            assert!(leaked < -down, "{:?}: 7 kHz leaks through at {:.1} dB", quality, leaked);
+
+            let mut resampler = Resampler::new(7812, 48000, quality);
+            let output = run(&mut resampler, &tone(3000.0, 7812, 7812), &[4096]);
+            let image = level_db(&output[2000..], 7812.0 - 3000.0, 48000);
+            assert!(image < -up, "{:?}: image at {:.1} dB", quality, image);
+        }
+    }
+
+    #[test]
+    fn odd_blocks_give_the_same_stream() {
+        let input = tone(1000.0, 7812, 200_003);
+        for (from, to) in [(7812, 48000), (48000, 11520)] {
+            let mut whole = Resampler::new(from, to, ResampleQuality::Medium);
+            let expected = run(&mut whole, &input, &[input.len()]);
+            let mut split = Resampler::new(from, to, ResampleQuality::Medium);
+            let output = run(&mut split, &input, &[1, 7, 127, 509, 3, 1021]);
+            // Positions are kept relative to the buffered input, so rounding may differ slightly
+            assert_eq!(output.len(), expected.len(), "{} -> {}", from, to);
+            let error = output.iter().zip(&expected).fold(0.0f32, |max, (a, b)| max.max((a - b).abs()));
+            assert!(error < 1e-4, "{} -> {}: blocks change the output by {}", from, to, error);
+
+            // Output count is len * to / from, less what is still held back for the kernel
+            let ideal = input.len() as f64 * to as f64 / from as f64;
+            let held = whole.half_len as f64 * (to as f64 / from as f64).max(1.0);
+            let count = output.len() as f64;
+            assert!(count <= ideal + 1.0 && count >= ideal - held - 1.0, "{} -> {}: {} samples", from, to, count);
+        }
+    }
+
+    #[test]
+    fn correction_changes_the_output_rate() {
+        let input = vec![0.0; 100_000];
+        let mut nominal = Resampler::new(7812, 48000, ResampleQuality::Fast);
+        let expected = run(&mut nominal, &input, &[1000]).len() as f64;
+        for ppm in [500.0, -500.0] {
+            let mut corrected = Resampler::new(7812, 48000, ResampleQuality::Fast);
+            corrected.set_correction(1.0 + ppm * 1e-6);
+            let count = run(&mut corrected, &input, &[1000]).len() as f64;
+            // A faster input clock means fewer output samples for the same input
+            let measured = (expected / count - 1.0) * 1e6;
+            assert!((measured - ppm).abs() < 10.0, "asked {} ppm, got {:.1} ppm", ppm, measured);
+        }
+    }
+}
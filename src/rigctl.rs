@@ -1,19 +1,377 @@
 use std::io::{BufRead, BufReader, Write};
-use std::net::{TcpListener, TcpStream};
+use std::net::{IpAddr, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
+use std::thread;
+use crate::audio::AudioSettings;
 use crate::trusdx;
+use crate::cat::{CatCommand, Mode, Vfo};
+use crate::caps;
+use crate::hamlib::{self, Reply, Request, RigCmd};
 use serialport;
 
+pub const DEFAULT_PORT: u16 = 4532;
+pub const DEFAULT_BIND: &str = "127.0.0.1";
+pub const DEFAULT_MAX_CLIENTS: usize = 8;
 
-fn handle_rigctl_client(
-    mut stream: TcpStream,
-    _ser: Arc<Mutex<Box<dyn serialport::SerialPort + Send>>>,
+#[derive(Clone, Debug)]
+pub struct RigctlOptions {
+    pub listen: Vec<SocketAddr>,
+    // How many ports above the requested one to try when it is busy (0 = fail)
+    pub port_fallback: u16,
+    pub max_clients: usize,
+}
+
+fn set_vfo_freq(freq_state: &Arc<Mutex<u64>>, vfo_state: &Arc<Mutex<trusdx::VfoState>>, vfo: Vfo, hz: u64) {
+    match vfo {
+        Vfo::B => vfo_state.lock().unwrap().freq_b = hz,
+        _ => *freq_state.lock().unwrap() = hz,
+    }
+}
+
+#[derive(Clone)]
+struct RigctlShared {
+    ser: Arc<Mutex<Box<dyn serialport::SerialPort + Send>>>,
     freq_state: Arc<Mutex<u64>>,
+    vfo_state: Arc<Mutex<trusdx::VfoState>>,
+    mode_state: Arc<Mutex<Mode>>,
     tx_state: Arc<Mutex<bool>>,
-    cat_queue: Arc<Mutex<Vec<Vec<u8>>>>,
-) {
+    cat_queue: Arc<Mutex<Vec<CatCommand>>>,
+    // The TX/RX audio processing settings the levels and functions map to
+    audio_settings: Arc<Mutex<AudioSettings>>,
+    // Client id that keyed the radio; only that client may un-key it
+    ptt_owner: Arc<Mutex<Option<u64>>>,
+}
+
+// Decrements the live connection count when a client handler exits
+struct ConnectionSlot(Arc<AtomicUsize>);
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+// Per-connection protocol state
+struct ClientSession {
+    id: u64,
+    // Data mode name this client last set, so "m" echoes PKTUSB rather than USB
+    pkt_alias: Option<(Mode, &'static str)>,
+}
+
+fn get_mode_values(shared: &RigctlShared, session: &ClientSession) -> Vec<String> {
+    let mode = *shared.mode_state.lock().unwrap();
+    // Check if this client asked for a data variant the radio reports as plain SSB
+    let name = match session.pkt_alias {
+        Some((alias_mode, alias)) if alias_mode == mode => alias,
+        _ => mode.name(),
+    };
+    vec![name.to_string(), hamlib::default_passband(mode).to_string()]
+}
+
+fn set_mode(args: &[&str], shared: &RigctlShared, session: &mut ClientSession) -> Reply {
+    let passband_ok = args.get(1).is_none_or(|arg| arg.parse::<i64>().is_ok());
+    match args.first().map(|name| hamlib::parse_mode(name)) {
+        Some(Some((mode, alias))) if passband_ok => {
+            session.pkt_alias = alias.map(|alias| (mode, alias));
+            *shared.mode_state.lock().unwrap() = mode;
+            shared.cat_queue.lock().unwrap().push(CatCommand::SetMode(mode));
+            Reply::Ok
+        }
+        // Check if the mode name is one the trUSDX cannot do
+        Some(None) => Reply::Error(hamlib::RIG_ENAVAIL),
+        _ => Reply::Error(hamlib::RIG_EINVAL),
+    }
+}
+
+fn set_freq(args: &[&str], split: bool, shared: &RigctlShared) -> Reply {
+    // Check if command has frequency parameter
+    if let Some(hz) = args.first().and_then(|arg| hamlib::parse_hz(arg)) {
+        let vfo = {
+            let state = shared.vfo_state.lock().unwrap();
+            // Check if this is the split (TX) frequency
+            if split { state.split_vfo() } else { state.rx_vfo }
+        };
+        set_vfo_freq(&shared.freq_state, &shared.vfo_state, vfo, hz);
+        shared.cat_queue.lock().unwrap().push(trusdx::set_freq_command(vfo, hz));
+        Reply::Ok
+    } else {
+        Reply::Error(hamlib::RIG_EINVAL)
+    }
+}
+
+fn set_vfo(args: &[&str], shared: &RigctlShared) -> Reply {
+    let mut state = shared.vfo_state.lock().unwrap();
+    let requested = args.first().and_then(|arg| hamlib::parse_vfo(arg, state.rx_vfo));
+    // Check if VFO name was recognised
+    if let Some(vfo) = requested {
+        let was_split = state.split();
+        state.rx_vfo = vfo;
+        // FR resets TX to the same VFO on the radio, so restore split afterwards
+        state.tx_vfo = vfo;
+        if was_split { state.tx_vfo = state.split_vfo(); }
+        let mut q = shared.cat_queue.lock().unwrap();
+        q.push(CatCommand::SetRxVfo(state.rx_vfo));
+        q.push(CatCommand::SetTxVfo(state.tx_vfo));
+        Reply::Ok
+    } else {
+        Reply::Error(hamlib::RIG_EINVAL)
+    }
+}
+
+fn set_split_vfo(args: &[&str], shared: &RigctlShared) -> Reply {
+    // Check if split on/off parameter parsed
+    if let Some(enable) = args.first().and_then(|arg| hamlib::parse_bool(arg)) {
+        let mut state = shared.vfo_state.lock().unwrap();
+        let requested = args.get(1).and_then(|arg| hamlib::parse_vfo(arg, state.rx_vfo));
+        state.tx_vfo = match (enable, requested) {
+            (false, _) => state.rx_vfo,
+            (true, Some(vfo)) if vfo != state.rx_vfo => vfo,
+            (true, _) => state.split_vfo(),
+        };
+        shared.cat_queue.lock().unwrap().push(CatCommand::SetTxVfo(state.tx_vfo));
+        Reply::Ok
+    } else {
+        Reply::Error(hamlib::RIG_EINVAL)
+    }
+}
+
+fn set_ptt(args: &[&str], shared: &RigctlShared, session: &ClientSession) -> Reply {
+    // Check if command has TX state parameter
+    let on = match args.first().and_then(|arg| hamlib::parse_bool(arg)) {
+        Some(on) => on,
+        None => return Reply::Error(hamlib::RIG_EINVAL),
+    };
+    let mut owner = shared.ptt_owner.lock().unwrap();
+    // Check if ownership is stale (radio was un-keyed behind our back)
+    if !*shared.tx_state.lock().unwrap() {
+        *owner = None;
+    }
+    match *owner {
+        // Already keyed by another client: nothing to do for T 1
+        Some(other) if other != session.id && on => Reply::Ok,
+        // Keyed by another client: refuse to un-key their transmission
+        Some(other) if other != session.id => Reply::Error(hamlib::RIG_ERJCTED),
+        _ => {
+            // Check if the serial link is down; keying would go nowhere
+            if !crate::reconnect::is_connected() {
+                return Reply::Error(hamlib::RIG_EIO);
+            }
+            // Any keying from a client ends a keyer message
+            crate::keyer::abort();
+            // Check if serial port lock acquired successfully
+            if let Ok(mut s) = shared.ser.lock() {
+                // Check if TX should be enabled
+                if on {
+                    // A client keying over a VOX transmission owns it from now on
+                    crate::vox::hand_over();
+                    let _ = trusdx::start_transmit_baseband(&mut **s);
+                } else {
+                    let _ = trusdx::enable_streaming_speaker_off(&mut **s);
+                }
+            }
+            *shared.tx_state.lock().unwrap() = on;
+            *owner = if on { Some(session.id) } else { None };
+            Reply::Ok
+        }
+    }
+}
+
+// VOXGAIN 0..1 spans VOX thresholds from 0 dBFS down to this many dB
+const VOX_GAIN_RANGE_DB: f32 = 60.0;
+
+// Levels are hamlib's 0..1 scale, except VOXDELAY in tenths of a second; MICGAIN covers the
+// TX gain up to txdsp::MAX_TX_GAIN
+fn set_level(args: &[&str], shared: &RigctlShared) -> Reply {
+    let value = match args.get(1).and_then(|arg| hamlib::parse_level(arg)) {
+        Some(value) => value,
+        None => return Reply::Error(hamlib::RIG_EINVAL),
+    };
+    let scaled = value.clamp(0.0, 1.0);
+    let mut settings = shared.audio_settings.lock().unwrap();
+    match args.first().copied() {
+        Some("MICGAIN") => settings.tx_gain = scaled * crate::txdsp::MAX_TX_GAIN,
+        Some("COMP") => settings.tx_compression = scaled,
+        Some("VOXGAIN") => settings.vox_threshold = 10f32.powf(-scaled * VOX_GAIN_RANGE_DB / 20.0),
+        Some("ANTIVOX") => settings.vox_anti_trip = scaled,
+        Some("VOXDELAY") => settings.vox_hang_ms = (value.max(0.0).round() as u32).saturating_mul(100),
+        _ => return Reply::Error(hamlib::RIG_ENAVAIL),
+    }
+    Reply::Ok
+}
+
+fn get_level(args: &[&str], shared: &RigctlShared) -> Reply {
+    let settings = *shared.audio_settings.lock().unwrap();
+    let value = match args.first().copied() {
+        Some("MICGAIN") => (settings.tx_gain / crate::txdsp::MAX_TX_GAIN).min(1.0),
+        Some("COMP") => settings.tx_compression,
+        Some("VOXGAIN") => (-20.0 * settings.vox_threshold.max(1e-6).log10() / VOX_GAIN_RANGE_DB).clamp(0.0, 1.0),
+        Some("ANTIVOX") => settings.vox_anti_trip,
+        Some("VOXDELAY") => return Reply::Values(vec![((settings.vox_hang_ms + 50) / 100).to_string()]),
+        _ => return Reply::Error(hamlib::RIG_ENAVAIL),
+    };
+    Reply::Values(vec![hamlib::format_level(value)])
+}
+
+fn set_func(args: &[&str], shared: &RigctlShared) -> Reply {
+    let Some(on) = args.get(1).and_then(|arg| hamlib::parse_bool(arg)) else {
+        return Reply::Error(hamlib::RIG_EINVAL);
+    };
+    let mut settings = shared.audio_settings.lock().unwrap();
+    match args.first().copied() {
+        Some("COMP") => settings.tx_compressor = on,
+        Some("VOX") => settings.vox = on,
+        _ => return Reply::Error(hamlib::RIG_ENAVAIL),
+    }
+    Reply::Ok
+}
+
+fn get_func(args: &[&str], shared: &RigctlShared) -> Reply {
+    let settings = *shared.audio_settings.lock().unwrap();
+    let on = match args.first().copied() {
+        Some("COMP") => settings.tx_compressor,
+        Some("VOX") => settings.vox,
+        _ => return Reply::Error(hamlib::RIG_ENAVAIL),
+    };
+    Reply::Values(vec![if on { "1" } else { "0" }.to_string()])
+}
+
+fn execute(req: &Request<'_>, shared: &RigctlShared, session: &mut ClientSession) -> Reply {
+    let args = req.args.as_slice();
+    // Check if client asked for the list of accepted values
+    if args.first() == Some(&"?") {
+        return match req.def.cmd {
+            RigCmd::SetMode | RigCmd::SetSplitMode => Reply::Values(vec![hamlib::HAMLIB_MODES.join(" ")]),
+            RigCmd::SetVfo => Reply::Values(vec![hamlib::HAMLIB_VFOS.join(" ")]),
+            RigCmd::SetLevel => Reply::Values(vec![caps::TRUSDX.set_levels.join(" ")]),
+            RigCmd::GetLevel => Reply::Values(vec![caps::TRUSDX.get_levels.join(" ")]),
+            RigCmd::SetFunc => Reply::Values(vec![caps::TRUSDX.set_funcs.join(" ")]),
+            RigCmd::GetFunc => Reply::Values(vec![caps::TRUSDX.get_funcs.join(" ")]),
+            RigCmd::SetParm | RigCmd::GetParm => Reply::Values(vec![String::new()]),
+            _ => Reply::Error(hamlib::RIG_EINVAL),
+        };
+    }
+    // Check if the command is one the trUSDX cannot do at all
+    if !hamlib::is_supported(req.def.cmd) {
+        return Reply::Error(hamlib::RIG_ENAVAIL);
+    }
+    match req.def.cmd {
+        RigCmd::GetFreq => {
+            let freq_a = *shared.freq_state.lock().unwrap();
+            let hz = shared.vfo_state.lock().unwrap().rx_freq(freq_a);
+            Reply::Values(vec![hz.to_string()])
+        }
+        RigCmd::SetFreq => set_freq(args, false, shared),
+        RigCmd::GetSplitFreq => {
+            let freq_a = *shared.freq_state.lock().unwrap();
+            let state = *shared.vfo_state.lock().unwrap();
+            Reply::Values(vec![state.freq_of(state.split_vfo(), freq_a).to_string()])
+        }
+        RigCmd::SetSplitFreq => set_freq(args, true, shared),
+        RigCmd::GetMode | RigCmd::GetSplitMode => Reply::Values(get_mode_values(shared, session)),
+        RigCmd::SetMode => set_mode(args, shared, session),
+        // TX and RX share one mode on the trUSDX; accept it only if it matches
+        RigCmd::SetSplitMode => {
+            let current = *shared.mode_state.lock().unwrap();
+            match args.first().map(|name| hamlib::parse_mode(name)) {
+                Some(Some((mode, _))) if mode == current => Reply::Ok,
+                Some(Some(_)) | Some(None) => Reply::Error(hamlib::RIG_ENAVAIL),
+                None => Reply::Error(hamlib::RIG_EINVAL),
+            }
+        }
+        RigCmd::GetVfo => {
+            let state = *shared.vfo_state.lock().unwrap();
+            Reply::Values(vec![hamlib::vfo_name(state.rx_vfo).to_string()])
+        }
+        RigCmd::SetVfo => set_vfo(args, shared),
+        RigCmd::GetSplitVfo => {
+            let state = *shared.vfo_state.lock().unwrap();
+            let split = if state.split() { "1" } else { "0" };
+            Reply::Values(vec![split.to_string(), hamlib::vfo_name(state.tx_vfo).to_string()])
+        }
+        RigCmd::SetSplitVfo => set_split_vfo(args, shared),
+        RigCmd::GetPtt => {
+            let on = *shared.tx_state.lock().unwrap();
+            Reply::Values(vec![if on { "1" } else { "0" }.to_string()])
+        }
+        RigCmd::SetPtt => set_ptt(args, shared, session),
+        RigCmd::GetVfoInfo => {
+            let freq_a = *shared.freq_state.lock().unwrap();
+            let state = *shared.vfo_state.lock().unwrap();
+            let vfo = args.first().and_then(|arg| hamlib::parse_vfo(arg, state.rx_vfo)).unwrap_or(state.rx_vfo);
+            let mode = get_mode_values(shared, session);
+            Reply::Values(vec![
+                state.freq_of(vfo, freq_a).to_string(),
+                mode[0].clone(),
+                mode[1].clone(),
+                if state.split() { "1" } else { "0" }.to_string(),
+                "0".to_string(),
+            ])
+        }
+        RigCmd::GetInfo => Reply::Values(vec!["trUSDX".to_string()]),
+        RigCmd::ChkVfo => Reply::Values(vec!["0".to_string()]),
+        RigCmd::GetPowerstat => Reply::Values(vec!["1".to_string()]),
+        RigCmd::GetLockMode => Reply::Values(vec!["0".to_string()]),
+        // Powering the radio on/off or locking it is not possible over CAT; accept the no-op values
+        RigCmd::SetPowerstat | RigCmd::SetLockMode => {
+            match (req.def.cmd, args.first().and_then(|arg| hamlib::parse_bool(arg))) {
+                (RigCmd::SetPowerstat, Some(true)) | (RigCmd::SetLockMode, Some(false)) => Reply::Ok,
+                (_, Some(_)) => Reply::Error(hamlib::RIG_ENAVAIL),
+                (_, None) => Reply::Error(hamlib::RIG_EINVAL),
+            }
+        }
+        RigCmd::SendVoiceMem => {
+            let Some(slot) = args.first().and_then(|arg| arg.parse::<usize>().ok()) else {
+                return Reply::Error(hamlib::RIG_EINVAL);
+            };
+            // Check if someone else is transmitting; a message must not cut in
+            if *shared.tx_state.lock().unwrap() && crate::keyer::status().is_none() {
+                return Reply::Error(hamlib::RIG_ERJCTED);
+            }
+            if crate::keyer::play(slot) { Reply::Ok } else { Reply::Error(hamlib::RIG_EINVAL) }
+        }
+        RigCmd::StopVoiceMem => {
+            crate::keyer::stop();
+            Reply::Ok
+        }
+        RigCmd::GetRecording => {
+            let on = shared.audio_settings.lock().unwrap().record;
+            Reply::Values(vec![if on { "1" } else { "0" }.to_string()])
+        }
+        RigCmd::SetRecording => match args.first().and_then(|arg| hamlib::parse_bool(arg)) {
+            Some(on) => {
+                shared.audio_settings.lock().unwrap().record = on;
+                Reply::Ok
+            }
+            None => Reply::Error(hamlib::RIG_EINVAL),
+        },
+        RigCmd::SetLevel => set_level(args, shared),
+        RigCmd::GetLevel => get_level(args, shared),
+        RigCmd::SetFunc => set_func(args, shared),
+        RigCmd::GetFunc => get_func(args, shared),
+        RigCmd::DumpState => Reply::Lines(caps::dump_state_lines(&caps::TRUSDX)),
+        RigCmd::DumpCaps => Reply::Lines(caps::dump_caps_lines(&caps::TRUSDX)),
+        RigCmd::Quit => Reply::Ok,
+        // Filtered out by is_supported above
+        _ => Reply::Error(hamlib::RIG_ENAVAIL),
+    }
+}
+
+// Helper function to answer one protocol line; also tells whether the client asked to quit
+fn handle_line(line: &str, shared: &RigctlShared, session: &mut ClientSession) -> (String, bool) {
+    match hamlib::parse_line(line) {
+        Ok(req) => {
+            let reply = execute(&req, shared, session);
+            (hamlib::format_reply(&req, &reply), req.def.cmd == RigCmd::Quit)
+        }
+        Err(_) => (format!("RPRT {}\n", hamlib::RIG_ENIMPL), false),
+    }
+}
+
+fn handle_rigctl_client(mut stream: TcpStream, client_id: u64, shared: RigctlShared) {
     let mut reader = BufReader::new(stream.try_clone().unwrap());
     let mut line = String::new();
+    let mut session = ClientSession { id: client_id, pkt_alias: None };
     loop {
         line.clear();
         // Check if line read failed (client disconnected)
@@ -24,210 +382,408 @@ fn handle_rigctl_client(
         if line.is_empty() {
             break;
         }
-        let cmd = line.trim();
         // Check if command is empty after trimming
-        if cmd.is_empty() {
+        if line.trim().is_empty() {
             continue;
         }
-        match cmd.chars().next().unwrap_or('\0') {
-            '\\' => {
-                let meta = &cmd[1..];
-                match meta {
-                    "chk_vfo" => {
-                        let _ = writeln!(stream, "0");
-                    }
-                    "get_powerstat" => {
-                        let _ = writeln!(stream, "1");
-                    }
-                    "dump_state" => {
-                        let lines = [
-                            "0",
-                            "0",
-                            "0",
-                            "0 0 0 0 0 0 0",
-                            "0 0 0 0 0 0 0",
-                            "0 0",
-                            "0 0",
-                            "0",
-                            "0",
-                            "0",
-                            "0",
-                            "0 0 0 0 0 0 0",
-                            "0 0 0 0 0 0 0",
-                            "0",
-                            "0",
-                            "0",
-                            "0",
-                            "0",
-                            "0",
-                        ];
-                        for l in lines {
-                            let _ = writeln!(stream, "{}", l);
-                        }
-                    }
-                    "dump_caps" => {
-                        let _ = writeln!(stream, "RPRT 0");
-                    }
-                    _ => {
-                        let _ = writeln!(stream, "RPRT 0");
-                    }
-                }
-            }
-            'f' => {
-                let hz = *freq_state.lock().unwrap();
-                let _ = writeln!(stream, "{}", hz);
-            }
-            'F' => {
-                let parts: Vec<&str> = cmd.split_whitespace().collect();
-                // Check if command has frequency parameter
-                if parts.len() >= 2 {
-                    let parsed_hz: Option<u64> = if let Ok(hz_int) = parts[1].parse::<u64>() {
-                        Some(hz_int)
-                    } else if let Ok(hz_f) = parts[1].parse::<f64>() {
-                        Some(hz_f.round() as u64)
-                    } else {
-                        None
-                    };
-                    // Check if frequency parsed successfully
-                    if let Some(hz) = parsed_hz {
-                        *freq_state.lock().unwrap() = hz;
-                        {
-                            let mut q = cat_queue.lock().unwrap();
-                            q.push(format!("FA{:011};", hz).into_bytes());
-                        }
-                        let _ = writeln!(stream, "RPRT 0");
-                        continue;
-                    }
-                }
-                let _ = writeln!(stream, "RPRT -1");
-            }
-            'm' => {
-                let _ = writeln!(stream, "USB");
-                let _ = writeln!(stream, "2400");
-            }
-            'M' => {
-                {
-                    let mut q = cat_queue.lock().unwrap();
-                    q.push(b"MD2;".to_vec());
-                }
-                let _ = writeln!(stream, "RPRT 0");
-            }
-            'v' => {
-                let _ = writeln!(stream, "VFOA");
-            }
-            'V' => {
-                let _ = writeln!(stream, "RPRT 0");
-            }
-            't' => {
-                let on = *tx_state.lock().unwrap();
-                let _ = writeln!(stream, "{}", if on { 1 } else { 0 });
-            }
-            'T' => {
-                let parts: Vec<&str> = cmd.split_whitespace().collect();
-                // Check if command has TX state parameter
-                if parts.len() >= 2 {
-                    let on = parts[1].parse::<i32>().map(|v| v != 0).unwrap_or(false);
-                    // Check if serial port lock acquired successfully
-                    if let Ok(mut s) = _ser.lock() {
-                        // Check if TX should be enabled
-                        if on {
-                            let _ = trusdx::start_transmit_baseband(&mut **s);
-                        } else {
-                            let _ = trusdx::enable_streaming_speaker_off(&mut **s);
-                        }
-                    }
-                    *tx_state.lock().unwrap() = on;
-                    let _ = writeln!(stream, "RPRT 0");
-                } else {
-                    let _ = writeln!(stream, "RPRT 0");
+        let (response, quit) = handle_line(&line, &shared, &mut session);
+        let _ = stream.write_all(response.as_bytes());
+        // Check if client asked to close the session
+        if quit {
+            break;
+        }
+    }
+
+    let mut owner = shared.ptt_owner.lock().unwrap();
+    // Check if this client left the radio keyed; nobody else is allowed to un-key it
+    if *owner == Some(client_id) {
+        *owner = None;
+        // Check if the radio is still transmitting for this client
+        if *shared.tx_state.lock().unwrap() {
+            crate::watchdog::force_rx(&shared.ser, &shared.tx_state);
+            eprintln!("rigctl: client {} disconnected while keyed, radio forced back to RX", client_id);
+        }
+    }
+}
+
+// Resolve a --rigctl-bind value: "addr:port", "[v6]:port", a bare IP, or a host name
+pub fn parse_bind(spec: &str, default_port: u16) -> anyhow::Result<Vec<SocketAddr>> {
+    // Check if spec already carries a port
+    if let Ok(addr) = spec.parse::<SocketAddr>() {
+        return Ok(vec![addr]);
+    }
+    let host = spec.trim_start_matches('[').trim_end_matches(']');
+    // Check if spec is a bare IPv4/IPv6 address
+    if let Ok(ip) = host.parse::<IpAddr>() {
+        return Ok(vec![SocketAddr::new(ip, default_port)]);
+    }
+    let addrs: Vec<SocketAddr> = (host, default_port)
+        .to_socket_addrs()
+        .map_err(|e| anyhow::anyhow!("rigctl: cannot resolve bind address '{}': {}", spec, e))?
+        .collect();
+    // Check if the name resolved to anything
+    if addrs.is_empty() {
+        anyhow::bail!("rigctl: bind address '{}' did not resolve", spec);
+    }
+    Ok(addrs)
+}
+
+fn bind_with_fallback(addr: SocketAddr, port_fallback: u16) -> anyhow::Result<TcpListener> {
+    let mut candidate = addr;
+    for attempt in 0..=port_fallback {
+        candidate.set_port(addr.port().saturating_add(attempt));
+        match TcpListener::bind(candidate) {
+            Ok(listener) => {
+                // Check if we had to move off the requested port
+                if attempt > 0 {
+                    eprintln!("rigctl: {} is in use, listening on {} instead", addr, candidate);
                 }
+                return Ok(listener);
             }
-            'q' => {
-                let _ = writeln!(stream, "RPRT 0");
-                break;
+            // Check if the port is busy and there are fallback ports left to try
+            Err(e) if e.kind() == std::io::ErrorKind::AddrInUse && attempt < port_fallback => continue,
+            Err(e) if e.kind() == std::io::ErrorKind::AddrInUse => {
+                anyhow::bail!(
+                    "rigctl: cannot listen on {}: address already in use (is rigctld or another driver running?)",
+                    candidate
+                );
             }
-            _ => {
-                let _ = writeln!(stream, "RPRT 0");
+            Err(e) => anyhow::bail!("rigctl: cannot listen on {}: {}", candidate, e),
+        }
+    }
+    unreachable!("loop always returns")
+}
+
+fn accept_loop(listener: TcpListener, shared: RigctlShared, active: Arc<AtomicUsize>, next_client_id: Arc<AtomicU64>, max_clients: usize) {
+    for stream in listener.incoming() {
+        // Check if client connection accepted successfully
+        if let Ok(mut stream) = stream {
+            // Check if the connection limit is reached
+            if active.load(Ordering::SeqCst) >= max_clients {
+                let _ = writeln!(stream, "RPRT {}", hamlib::RIG_EBUSBUSY);
+                continue;
             }
+            active.fetch_add(1, Ordering::SeqCst);
+            let slot = ConnectionSlot(active.clone());
+            let client_id = next_client_id.fetch_add(1, Ordering::SeqCst) + 1;
+            let shared = shared.clone();
+            thread::spawn(move || {
+                let _slot = slot;
+                handle_rigctl_client(stream, client_id, shared);
+            });
         }
     }
 }
 
+// Binds every listen address up front so errors reach the caller; returns the bound addresses
+#[allow(clippy::too_many_arguments)]
 pub fn spawn_rigctl_server(
     ser: Arc<Mutex<Box<dyn serialport::SerialPort + Send>>>,
     freq_state: Arc<Mutex<u64>>,
+    vfo_state: Arc<Mutex<trusdx::VfoState>>,
+    mode_state: Arc<Mutex<Mode>>,
     tx_state: Arc<Mutex<bool>>,
-    cat_queue: Arc<Mutex<Vec<Vec<u8>>>>,
-) {
-    let _ = std::process::Command::new("pkill")
-        .args(["-f", "rigctl"])
-        .stdout(std::process::Stdio::null())
-        .stderr(std::process::Stdio::null())
-        .stdin(std::process::Stdio::null())
-        .spawn()
-        .and_then(|mut child| {
-            let _ = child.try_wait();
-            Ok(())
-        });
-    
-    let _ = std::process::Command::new("fuser")
-        .args(["-k", "4532/tcp"])
-        .stdout(std::process::Stdio::null())
-        .stderr(std::process::Stdio::null())
-        .stdin(std::process::Stdio::null())
-        .spawn()
-        .and_then(|mut child| {
-            let _ = child.try_wait();
+    cat_queue: Arc<Mutex<Vec<CatCommand>>>,
+    audio_settings: Arc<Mutex<AudioSettings>>,
+    options: &RigctlOptions,
+) -> anyhow::Result<Vec<SocketAddr>> {
+    let mut listeners = Vec::new();
+    for addr in &options.listen {
+        listeners.push(bind_with_fallback(*addr, options.port_fallback)?);
+    }
+
+    let shared = RigctlShared {
+        ser,
+        freq_state,
+        vfo_state,
+        mode_state,
+        tx_state,
+        cat_queue,
+        audio_settings,
+        ptt_owner: Arc::new(Mutex::new(None)),
+    };
+    let active = Arc::new(AtomicUsize::new(0));
+    let next_client_id = Arc::new(AtomicU64::new(0));
+    let mut bound = Vec::new();
+    for listener in listeners {
+        // Check if the bound address can be reported back
+        if let Ok(addr) = listener.local_addr() {
+            bound.push(addr);
+        }
+        let shared = shared.clone();
+        let active = active.clone();
+        let next_client_id = next_client_id.clone();
+        let max_clients = options.max_clients;
+        thread::spawn(move || accept_loop(listener, shared, active, next_client_id, max_clients));
+    }
+    Ok(bound)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::io::Read;
+    use std::time::Duration;
+
+    // Serial port that keeps what the driver writes to the radio
+    #[derive(Clone, Default)]
+    struct FakePort {
+        written: Arc<Mutex<Vec<u8>>>,
+    }
+
+    impl Read for FakePort {
+        fn read(&mut self, _buf: &mut [u8]) -> std::io::Result<usize> {
+            Err(std::io::ErrorKind::TimedOut.into())
+        }
+    }
+
+    impl Write for FakePort {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.written.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
             Ok(())
-        });
-    
-    // Check if lsof command executed successfully
-    if let Ok(output) = std::process::Command::new("lsof")
-        .args(["-ti:4532"])
-        .stdout(std::process::Stdio::piped())
-        .stderr(std::process::Stdio::null())
-        .stdin(std::process::Stdio::null())
-        .output()
-    {
-        // Check if port 4532 is in use
-        if !output.stdout.is_empty() {
-            let pid_str = String::from_utf8_lossy(&output.stdout);
-            let pid = pid_str.trim();
-            let current_pid = std::process::id().to_string();
-            // Check if process using port is not current process
-            if pid != current_pid {
-                let _ = std::process::Command::new("kill")
-                    .args(["-9", pid])
-                    .stdout(std::process::Stdio::null())
-                    .stderr(std::process::Stdio::null())
-                    .stdin(std::process::Stdio::null())
-                    .spawn()
-                    .and_then(|mut child| {
-                        let _ = child.try_wait();
-                        Ok(())
-                    });
-            }
         }
     }
 
-    std::thread::spawn(move || {
-        let addr = ("127.0.0.1", 4532);
-        // Check if TCP listener bound successfully
-        if let Ok(listener) = TcpListener::bind(addr) {
-            for stream in listener.incoming() {
-                // Check if client connection accepted successfully
-                if let Ok(stream) = stream {
-                    handle_rigctl_client(
-                        stream,
-                        ser.clone(),
-                        freq_state.clone(),
-                        tx_state.clone(),
-                        cat_queue.clone(),
-                    );
+    impl serialport::SerialPort for FakePort {
+        fn name(&self) -> Option<String> { Some("fake".to_string()) }
+        fn baud_rate(&self) -> serialport::Result<u32> { Ok(115_200) }
+        fn data_bits(&self) -> serialport::Result<serialport::DataBits> { Ok(serialport::DataBits::Eight) }
+        fn flow_control(&self) -> serialport::Result<serialport::FlowControl> { Ok(serialport::FlowControl::None) }
+        fn parity(&self) -> serialport::Result<serialport::Parity> { Ok(serialport::Parity::None) }
+        fn stop_bits(&self) -> serialport::Result<serialport::StopBits> { Ok(serialport::StopBits::One) }
+        fn timeout(&self) -> Duration { Duration::from_millis(10) }
+        fn set_baud_rate(&mut self, _: u32) -> serialport::Result<()> { Ok(()) }
+        fn set_data_bits(&mut self, _: serialport::DataBits) -> serialport::Result<()> { Ok(()) }
+        fn set_flow_control(&mut self, _: serialport::FlowControl) -> serialport::Result<()> { Ok(()) }
+        fn set_parity(&mut self, _: serialport::Parity) -> serialport::Result<()> { Ok(()) }
+        fn set_stop_bits(&mut self, _: serialport::StopBits) -> serialport::Result<()> { Ok(()) }
+        fn set_timeout(&mut self, _: Duration) -> serialport::Result<()> { Ok(()) }
+        fn write_request_to_send(&mut self, _: bool) -> serialport::Result<()> { Ok(()) }
+        fn write_data_terminal_ready(&mut self, _: bool) -> serialport::Result<()> { Ok(()) }
+        fn read_clear_to_send(&mut self) -> serialport::Result<bool> { Ok(false) }
+        fn read_data_set_ready(&mut self) -> serialport::Result<bool> { Ok(false) }
+        fn read_ring_indicator(&mut self) -> serialport::Result<bool> { Ok(false) }
+        fn read_carrier_detect(&mut self) -> serialport::Result<bool> { Ok(false) }
+        fn bytes_to_read(&self) -> serialport::Result<u32> { Ok(0) }
+        fn bytes_to_write(&self) -> serialport::Result<u32> { Ok(0) }
+        fn clear(&self, _: serialport::ClearBuffer) -> serialport::Result<()> { Ok(()) }
+        fn try_clone(&self) -> serialport::Result<Box<dyn serialport::SerialPort>> { Ok(Box::new(self.clone())) }
+        fn set_break(&self) -> serialport::Result<()> { Ok(()) }
+        fn clear_break(&self) -> serialport::Result<()> { Ok(()) }
+    }
+
+    // Helper function for a radio on 14.074 MHz USB, VFO A, receiving
+    fn fake_radio() -> (RigctlShared, FakePort) {
+        let port = FakePort::default();
+        let shared = RigctlShared {
+            ser: Arc::new(Mutex::new(Box::new(port.clone()))),
+            freq_state: Arc::new(Mutex::new(14_074_000)),
+            vfo_state: Arc::new(Mutex::new(trusdx::VfoState::default())),
+            mode_state: Arc::new(Mutex::new(Mode::Usb)),
+            tx_state: Arc::new(Mutex::new(false)),
+            cat_queue: Arc::new(Mutex::new(Vec::new())),
+            audio_settings: Arc::new(Mutex::new(AudioSettings::default())),
+            ptt_owner: Arc::new(Mutex::new(None)),
+        };
+        (shared, port)
+    }
+
+    // One client request from a session file, with what it must produce
+    #[derive(Default)]
+    struct Exchange {
+        client: u64,
+        line: String,
+        reply: String,
+        serial: String,
+        cat: String,
+    }
+
+    // Session files hold the lines a client sent ("> "), the exact reply lines ("< "), the bytes
+    // written to the serial port ("= ") and the CAT commands queued for the radio ("& ").
+    // "[client n]" switches to another connection.
+    fn parse_session(text: &str) -> Vec<Exchange> {
+        let mut exchanges: Vec<Exchange> = Vec::new();
+        let mut client = 1;
+        for line in text.lines() {
+            // Check if the line is a comment or spacing
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if let Some(id) = line.strip_prefix("[client ").and_then(|rest| rest.strip_suffix(']')) {
+                client = id.parse().unwrap();
+                continue;
+            }
+            let (tag, body) = line.split_at(1);
+            let body = body.strip_prefix(' ').unwrap_or(body);
+            // Check if a new request starts
+            if tag == ">" {
+                exchanges.push(Exchange { client, line: body.to_string(), ..Default::default() });
+                continue;
+            }
+            let exchange = exchanges.last_mut().expect("reply before the first request");
+            match tag {
+                "<" => {
+                    exchange.reply.push_str(body);
+                    exchange.reply.push('\n');
                 }
+                "=" => exchange.serial.push_str(body),
+                "&" => exchange.cat.push_str(body),
+                _ => panic!("unknown session line: {}", line),
             }
-        } else {
-            eprintln!("rigctl: failed to bind 127.0.0.1:4532");
         }
-    });
+        exchanges
+    }
+
+    // Helper function to replay a session and compare every reply byte for byte
+    fn replay(name: &str, text: &str) {
+        // Keying takes the transmission over from VOX, which the VOX tests watch
+        let _turn = crate::vox::KEYED_TESTS.lock().unwrap_or_else(|e| e.into_inner());
+        let (shared, port) = fake_radio();
+        let mut sessions: HashMap<u64, ClientSession> = HashMap::new();
+        for exchange in parse_session(text) {
+            let session = sessions.entry(exchange.client).or_insert(ClientSession { id: exchange.client, pkt_alias: None });
+            let (reply, _) = handle_line(&format!("{}\n", exchange.line), &shared, session);
+            assert_eq!(reply, exchange.reply, "{}: reply to {:?}", name, exchange.line);
+            let serial = std::mem::take(&mut *port.written.lock().unwrap());
+            assert_eq!(String::from_utf8_lossy(&serial), exchange.serial, "{}: serial writes for {:?}", name, exchange.line);
+            let cat: Vec<u8> = shared.cat_queue.lock().unwrap().drain(..).flat_map(|cmd| cmd.encode()).collect();
+            assert_eq!(String::from_utf8_lossy(&cat), exchange.cat, "{}: CAT queued for {:?}", name, exchange.line);
+        }
+    }
+
+    #[test]
+    fn wsjtx_session() {
+        replay("wsjtx", include_str!("../tests/sessions/wsjtx.session"));
+    }
+
+    #[test]
+    fn fldigi_session() {
+        replay("fldigi", include_str!("../tests/sessions/fldigi.session"));
+    }
+
+    #[test]
+    fn rigctl_session() {
+        replay("rigctl", include_str!("../tests/sessions/rigctl.session"));
+    }
+
+    // Helper function to serve a fake radio on a free local port
+    fn serve(max_clients: usize) -> (SocketAddr, RigctlShared, FakePort) {
+        let (shared, port) = fake_radio();
+        let options = RigctlOptions { listen: vec!["127.0.0.1:0".parse().unwrap()], port_fallback: 0, max_clients };
+        let bound = spawn_rigctl_server(
+            shared.ser.clone(),
+            shared.freq_state.clone(),
+            shared.vfo_state.clone(),
+            shared.mode_state.clone(),
+            shared.tx_state.clone(),
+            shared.cat_queue.clone(),
+            shared.audio_settings.clone(),
+            &options,
+        )
+        .unwrap();
+        (bound[0], shared, port)
+    }
+
+    // One TCP client talking to the server line by line
+    struct Client {
+        stream: TcpStream,
+        reader: BufReader<TcpStream>,
+    }
+
+    impl Client {
+        fn connect(addr: SocketAddr) -> Client {
+            let stream = TcpStream::connect(addr).unwrap();
+            stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            let reader = BufReader::new(stream.try_clone().unwrap());
+            Client { stream, reader }
+        }
+
+        // Helper function to read one reply line
+        fn read_line(&mut self) -> String {
+            let mut line = String::new();
+            self.reader.read_line(&mut line).unwrap();
+            line
+        }
+
+        // Helper function to send a command with a one-line reply
+        fn ask(&mut self, line: &str) -> String {
+            writeln!(self.stream, "{}", line).unwrap();
+            self.read_line()
+        }
+    }
+
+    // Helper function to wait for a condition the server threads bring about
+    fn wait_for(what: &str, mut done: impl FnMut() -> bool) {
+        for _ in 0..500 {
+            // Check if the condition holds yet
+            if done() {
+                return;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        panic!("timed out waiting for {}", what);
+    }
+
+    #[test]
+    fn only_the_keying_client_can_unkey_over_tcp() {
+        let _turn = crate::vox::KEYED_TESTS.lock().unwrap_or_else(|e| e.into_inner());
+        let (addr, shared, port) = serve(DEFAULT_MAX_CLIENTS);
+        let mut first = Client::connect(addr);
+        let mut second = Client::connect(addr);
+
+        assert_eq!(first.ask("T 1"), "RPRT 0\n");
+        assert_eq!(second.ask("T 0"), "RPRT -9\n");
+        assert!(*shared.tx_state.lock().unwrap());
+        assert_eq!(second.ask("t"), "1\n");
+
+        assert_eq!(first.ask("T 0"), "RPRT 0\n");
+        assert!(!*shared.tx_state.lock().unwrap());
+        assert_eq!(String::from_utf8_lossy(&port.written.lock().unwrap()), ";TX0;;RX;UA2;");
+    }
+
+    #[test]
+    fn client_disconnecting_while_keyed_returns_radio_to_rx() {
+        let _turn = crate::vox::KEYED_TESTS.lock().unwrap_or_else(|e| e.into_inner());
+        let (addr, shared, port) = serve(DEFAULT_MAX_CLIENTS);
+        let mut first = Client::connect(addr);
+        let mut second = Client::connect(addr);
+
+        assert_eq!(first.ask("T 1"), "RPRT 0\n");
+        assert_eq!(second.ask("T 0"), "RPRT -9\n");
+        drop(first);
+        wait_for("the radio to return to RX", || !*shared.tx_state.lock().unwrap());
+        assert!(shared.ptt_owner.lock().unwrap().is_none());
+        assert!(String::from_utf8_lossy(&port.written.lock().unwrap()).ends_with(";RX;UA2;"));
+
+        // The remaining client may key now
+        assert_eq!(second.ask("T 1"), "RPRT 0\n");
+        assert_eq!(second.ask("T 0"), "RPRT 0\n");
+    }
+
+    #[test]
+    fn clients_over_the_limit_are_turned_away() {
+        let (addr, _shared, _port) = serve(2);
+        let mut first = Client::connect(addr);
+        let mut second = Client::connect(addr);
+        assert_eq!(first.ask("t"), "0\n");
+        assert_eq!(second.ask("t"), "0\n");
+
+        let mut third = Client::connect(addr);
+        assert_eq!(third.read_line(), format!("RPRT {}\n", hamlib::RIG_EBUSBUSY));
+
+        // A slot frees up once a client leaves
+        drop(first);
+        wait_for("a free client slot", || {
+            // A turned-away probe may see the connection reset rather than a reply
+            let mut probe = Client::connect(addr);
+            let mut line = String::new();
+            let _ = writeln!(probe.stream, "t");
+            probe.reader.read_line(&mut line).is_ok() && line == "0\n"
+        });
+    }
 }
@@ -0,0 +1,549 @@
+// Optional RX audio processing for listening by ear: automatic notch, noise reduction, bandpass
+// and AGC, run at the radio rate before the audio reaches the sound system. Decoders want the
+// untouched stream, so the whole chain is bypassed unless switched on.
+
+use std::collections::VecDeque;
+use std::f32::consts::PI;
+
+use crate::audio::AudioSettings;
+
+// Tone the trUSDX puts a CW signal on, which the CW filter is centred on
+const CW_PITCH_HZ: f32 = 700.0;
+// Butterworth Q values for a fourth-order section pair
+const BUTTERWORTH_Q: [f32; 2] = [0.5412, 1.3066];
+const AGC_TARGET: f32 = 0.3;
+const AGC_MAX_GAIN: f32 = 100.0;
+const AGC_ATTACK_MS: f32 = 2.0;
+// Adaptive line enhancer: the prediction looks this far back, past where speech is still correlated
+const NOTCH_DELAY: usize = 16;
+const NOTCH_TAPS: usize = 32;
+const NOTCH_STEP: f32 = 0.005;
+// Noise reduction frames, at 50% overlap
+const NR_FRAME: usize = 256;
+const NR_HOP: usize = NR_FRAME / 2;
+// Bin power is smoothed over frames before its minimum is taken as the noise level
+const NR_POWER_SMOOTHING: f32 = 0.8;
+// How far above the noise estimate a bin must be to pass, and the floor for those that do not
+const NR_OVERSUBTRACT: f32 = 4.0;
+const NR_FLOOR: f32 = 0.1;
+// Per-frame rise of the noise estimate, so it follows noise that gets louder (about 2 dB/s)
+const NR_NOISE_RISE: f32 = 1.008;
+const NR_GAIN_SMOOTHING: f32 = 0.5;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RxFilter {
+    Off,
+    // 500 Hz around the CW pitch
+    Cw,
+    // 300-2700 Hz
+    Ssb,
+    // 200-3200 Hz
+    Data,
+}
+
+impl RxFilter {
+    pub fn from_name(name: &str) -> Option<RxFilter> {
+        match name {
+            "off" => Some(RxFilter::Off),
+            "cw" => Some(RxFilter::Cw),
+            "ssb" => Some(RxFilter::Ssb),
+            "data" => Some(RxFilter::Data),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            RxFilter::Off => "off",
+            RxFilter::Cw => "cw",
+            RxFilter::Ssb => "ssb",
+            RxFilter::Data => "data",
+        }
+    }
+
+    // The next choice, for controls that cycle through them
+    pub fn next(self) -> RxFilter {
+        match self {
+            RxFilter::Off => RxFilter::Cw,
+            RxFilter::Cw => RxFilter::Ssb,
+            RxFilter::Ssb => RxFilter::Data,
+            RxFilter::Data => RxFilter::Off,
+        }
+    }
+
+    fn passband(self) -> Option<(f32, f32)> {
+        match self {
+            RxFilter::Off => None,
+            RxFilter::Cw => Some((CW_PITCH_HZ - 250.0, CW_PITCH_HZ + 250.0)),
+            RxFilter::Ssb => Some((300.0, 2700.0)),
+            RxFilter::Data => Some((200.0, 3200.0)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RxAgc {
+    Off,
+    Slow,
+    Fast,
+}
+
+impl RxAgc {
+    pub fn from_name(name: &str) -> Option<RxAgc> {
+        match name {
+            "off" => Some(RxAgc::Off),
+            "slow" => Some(RxAgc::Slow),
+            "fast" => Some(RxAgc::Fast),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            RxAgc::Off => "off",
+            RxAgc::Slow => "slow",
+            RxAgc::Fast => "fast",
+        }
+    }
+
+    pub fn next(self) -> RxAgc {
+        match self {
+            RxAgc::Off => RxAgc::Slow,
+            RxAgc::Slow => RxAgc::Fast,
+            RxAgc::Fast => RxAgc::Off,
+        }
+    }
+
+    // (hang, release) in ms
+    fn timing(self) -> Option<(f32, f32)> {
+        match self {
+            RxAgc::Off => None,
+            RxAgc::Slow => Some((500.0, 1000.0)),
+            RxAgc::Fast => Some((100.0, 200.0)),
+        }
+    }
+}
+
+// Helper function for a one-pole smoothing coefficient
+fn coefficient(ms: f32, rate: u32) -> f32 {
+    1.0 - (-1000.0 / (ms * rate as f32)).exp()
+}
+
+// Second-order section, transposed direct form II
+struct Biquad {
+    b: [f32; 3],
+    a: [f32; 2],
+    z: [f32; 2],
+}
+
+impl Biquad {
+    // Lowpass or highpass from the RBJ cookbook
+    fn new(rate: u32, freq: f32, q: f32, highpass: bool) -> Biquad {
+        let w = 2.0 * PI * freq / rate as f32;
+        let alpha = w.sin() / (2.0 * q);
+        let cos = w.cos();
+        let a0 = 1.0 + alpha;
+        let (b0, b1) = if highpass { ((1.0 + cos) / 2.0, -(1.0 + cos)) } else { ((1.0 - cos) / 2.0, 1.0 - cos) };
+        Biquad {
+            b: [b0 / a0, b1 / a0, b0 / a0],
+            a: [-2.0 * cos / a0, (1.0 - alpha) / a0],
+            z: [0.0; 2],
+        }
+    }
+
+    fn process(&mut self, x: f32) -> f32 {
+        let y = self.b[0] * x + self.z[0];
+        self.z[0] = self.b[1] * x - self.a[0] * y + self.z[1];
+        self.z[1] = self.b[2] * x - self.a[1] * y;
+        y
+    }
+}
+
+struct Agc {
+    envelope: f32,
+    hang_left: usize,
+    attack: f32,
+}
+
+// NLMS predictor of the delayed signal; what it can predict (steady tones) is removed
+struct Notch {
+    weights: [f32; NOTCH_TAPS],
+    // Newest sample first
+    history: VecDeque<f32>,
+}
+
+impl Notch {
+    fn new() -> Notch {
+        Notch {
+            weights: [0.0; NOTCH_TAPS],
+            history: VecDeque::from(vec![0.0; NOTCH_DELAY + NOTCH_TAPS]),
+        }
+    }
+
+    fn process(&mut self, x: f32) -> f32 {
+        let past = self.history.range(NOTCH_DELAY..);
+        let predicted: f32 = past.clone().zip(&self.weights).map(|(h, w)| h * w).sum();
+        let power: f32 = past.clone().map(|h| h * h).sum();
+        let error = x - predicted;
+        let step = NOTCH_STEP * error / (power + 1e-6);
+        for (w, h) in self.weights.iter_mut().zip(past) {
+            *w += step * h;
+        }
+        self.history.pop_back();
+        self.history.push_front(x);
+        error
+    }
+}
+
+// Helper function for an in-place radix-2 FFT; `inverse` leaves the 1/N scaling to the caller
+fn fft(re: &mut [f32], im: &mut [f32], inverse: bool) {
+    let n = re.len();
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        // Check if this pair still needs swapping into bit-reversed order
+        if i < j {
+            re.swap(i, j);
+            im.swap(i, j);
+        }
+    }
+    let sign = if inverse { 1.0 } else { -1.0 };
+    let mut len = 2;
+    while len <= n {
+        let angle = sign * 2.0 * PI / len as f32;
+        for start in (0..n).step_by(len) {
+            for k in 0..len / 2 {
+                let (s, c) = (angle * k as f32).sin_cos();
+                let (a, b) = (start + k, start + k + len / 2);
+                let tr = re[b] * c - im[b] * s;
+                let ti = re[b] * s + im[b] * c;
+                re[b] = re[a] - tr;
+                im[b] = im[a] - ti;
+                re[a] += tr;
+                im[a] += ti;
+            }
+        }
+        len <<= 1;
+    }
+}
+
+// Spectral subtraction over overlapping frames, with a noise estimate that follows each bin's minimum
+struct NoiseReduction {
+    window: Vec<f32>,
+    input: Vec<f32>,
+    overlap: Vec<f32>,
+    power: Vec<f32>,
+    noise: Vec<f32>,
+    gains: Vec<f32>,
+    re: Vec<f32>,
+    im: Vec<f32>,
+}
+
+impl NoiseReduction {
+    fn new() -> NoiseReduction {
+        // Square-root Hann for both analysis and synthesis, which overlap-adds to one at 50%
+        let window = (0..NR_FRAME).map(|i| (PI * i as f32 / NR_FRAME as f32).sin()).collect();
+        NoiseReduction {
+            window,
+            input: Vec::with_capacity(NR_FRAME),
+            overlap: vec![0.0; NR_FRAME],
+            power: Vec::new(),
+            noise: Vec::new(),
+            gains: vec![1.0; NR_FRAME / 2 + 1],
+            re: vec![0.0; NR_FRAME],
+            im: vec![0.0; NR_FRAME],
+        }
+    }
+
+    fn process(&mut self, input: &[f32], output: &mut Vec<f32>) {
+        for &x in input {
+            self.input.push(x);
+            // Check if a full frame is ready
+            if self.input.len() == NR_FRAME {
+                self.frame();
+                output.extend_from_slice(&self.overlap[..NR_HOP]);
+                self.overlap.copy_within(NR_HOP.., 0);
+                self.overlap[NR_FRAME - NR_HOP..].fill(0.0);
+                self.input.drain(..NR_HOP);
+            }
+        }
+    }
+
+    // Helper function to process the frame in `input` into `overlap`
+    fn frame(&mut self) {
+        for i in 0..NR_FRAME {
+            self.re[i] = self.input[i] * self.window[i];
+            self.im[i] = 0.0;
+        }
+        fft(&mut self.re, &mut self.im, false);
+        let bins = NR_FRAME / 2 + 1;
+        let first = self.noise.is_empty();
+        for k in 0..bins {
+            let power = self.re[k] * self.re[k] + self.im[k] * self.im[k];
+            // Check if this is the first frame, which seeds the estimates
+            if first {
+                self.power.push(power);
+                self.noise.push(power);
+            }
+            self.power[k] = NR_POWER_SMOOTHING * self.power[k] + (1.0 - NR_POWER_SMOOTHING) * power;
+            self.noise[k] = (self.noise[k] * NR_NOISE_RISE).min(self.power[k]);
+            let gain = (1.0 - NR_OVERSUBTRACT * self.noise[k] / power.max(1e-12)).max(NR_FLOOR);
+            self.gains[k] = NR_GAIN_SMOOTHING * self.gains[k] + (1.0 - NR_GAIN_SMOOTHING) * gain;
+        }
+        for k in 0..NR_FRAME {
+            // Bins above Nyquist mirror the ones below
+            let gain = self.gains[if k < bins { k } else { NR_FRAME - k }];
+            self.re[k] *= gain;
+            self.im[k] *= gain;
+        }
+        fft(&mut self.re, &mut self.im, true);
+        for i in 0..NR_FRAME {
+            self.overlap[i] += self.re[i] / NR_FRAME as f32 * self.window[i];
+        }
+    }
+}
+
+pub struct RxDsp {
+    rate: u32,
+    notch: Option<Notch>,
+    nr: Option<NoiseReduction>,
+    bandpass: Option<(RxFilter, Vec<Biquad>)>,
+    agc: Option<(RxAgc, Agc)>,
+    buf: Vec<f32>,
+    out: Vec<f32>,
+}
+
+impl RxDsp {
+    pub fn new(rate: u32) -> RxDsp {
+        RxDsp {
+            rate,
+            notch: None,
+            nr: None,
+            bandpass: None,
+            agc: None,
+            buf: Vec::new(),
+            out: Vec::new(),
+        }
+    }
+
+    // Helper function to bring the stages in line with the settings; a stage switched off loses its state
+    fn configure(&mut self, settings: &AudioSettings) {
+        // Check if each stage is wanted and, where it has a choice, still set up for the right one
+        match (settings.rx_notch, self.notch.is_some()) {
+            (true, false) => self.notch = Some(Notch::new()),
+            (false, true) => self.notch = None,
+            _ => {}
+        }
+        match (settings.rx_nr, self.nr.is_some()) {
+            (true, false) => self.nr = Some(NoiseReduction::new()),
+            (false, true) => self.nr = None,
+            _ => {}
+        }
+        if self.bandpass.as_ref().map(|(filter, _)| *filter) != Some(settings.rx_filter) {
+            self.bandpass = settings.rx_filter.passband().map(|(low, high)| {
+                let mut stages = Vec::new();
+                for q in BUTTERWORTH_Q {
+                    stages.push(Biquad::new(self.rate, low, q, true));
+                    stages.push(Biquad::new(self.rate, high, q, false));
+                }
+                (settings.rx_filter, stages)
+            });
+        }
+        if self.agc.as_ref().map(|(agc, _)| *agc) != Some(settings.rx_agc) {
+            self.agc = settings.rx_agc.timing().map(|_| {
+                let agc = Agc {
+                    // Start fully open, so a weak signal is heard at once
+                    envelope: AGC_TARGET / AGC_MAX_GAIN,
+                    hang_left: 0,
+                    attack: coefficient(AGC_ATTACK_MS, self.rate),
+                };
+                (settings.rx_agc, agc)
+            });
+        }
+    }
+
+    // Process a block; noise reduction holds back up to one frame at a time
+    pub fn process(&mut self, settings: &AudioSettings, input: &[f32]) -> &[f32] {
+        self.configure(settings);
+        let output = &mut self.out;
+        output.clear();
+        self.buf.clear();
+        self.buf.extend_from_slice(input);
+        // Check if the notch is switched in
+        if let Some(notch) = &mut self.notch {
+            for x in &mut self.buf {
+                *x = notch.process(*x);
+            }
+        }
+        // Check if noise reduction is switched in
+        match &mut self.nr {
+            Some(nr) => nr.process(&self.buf, output),
+            None => output.extend_from_slice(&self.buf),
+        }
+        // Check if a bandpass is selected
+        if let Some((_, stages)) = &mut self.bandpass {
+            for x in output.iter_mut() {
+                *x = stages.iter_mut().fold(*x, |x, stage| stage.process(x));
+            }
+        }
+        // Check if AGC is switched in
+        if let Some((kind, agc)) = &mut self.agc {
+            let (hang_ms, release_ms) = kind.timing().unwrap_or((0.0, 0.0));
+            let hang = (hang_ms * self.rate as f32 / 1000.0) as usize;
+            let release = coefficient(release_ms, self.rate);
+            for x in output.iter_mut() {
+                let level = x.abs();
+                // Check if the signal got louder, otherwise hold the gain before letting it recover
+                if level > agc.envelope {
+                    agc.envelope += (level - agc.envelope) * agc.attack;
+                    agc.hang_left = hang;
+                } else if agc.hang_left > 0 {
+                    agc.hang_left -= 1;
+                } else {
+                    agc.envelope += (level - agc.envelope) * release;
+                }
+                let gain = AGC_TARGET / agc.envelope.max(AGC_TARGET / AGC_MAX_GAIN);
+                *x = (*x * gain).clamp(-1.0, 1.0);
+            }
+        }
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: u32 = 7812;
+    const SECOND: usize = RATE as usize;
+
+    // Helper function for settings with every stage switched off
+    fn bypassed() -> AudioSettings {
+        AudioSettings { rx_filter: RxFilter::Off, rx_agc: RxAgc::Off, rx_notch: false, rx_nr: false, ..Default::default() }
+    }
+
+    fn tone(freq: f32, amplitude: f32, len: usize) -> Vec<f32> {
+        (0..len).map(|n| amplitude * (2.0 * PI * freq * n as f32 / RATE as f32).sin()).collect()
+    }
+
+    fn rms(samples: &[f32]) -> f32 {
+        (samples.iter().map(|x| x * x).sum::<f32>() / samples.len() as f32).sqrt()
+    }
+
+    fn peak(samples: &[f32]) -> f32 {
+        samples.iter().fold(0.0, |max, x| max.max(x.abs()))
+    }
+
+    // Helper function for the amplitude of `freq` in `samples`
+    fn amplitude(samples: &[f32], freq: f32) -> f32 {
+        let (mut re, mut im) = (0.0, 0.0);
+        for (n, &x) in samples.iter().enumerate() {
+            let phase = 2.0 * PI * freq * n as f32 / RATE as f32;
+            re += x * phase.cos();
+            im += x * phase.sin();
+        }
+        2.0 * (re * re + im * im).sqrt() / samples.len() as f32
+    }
+
+    // Helper function to run the chain over `input` in blocks, as the RX bridge does
+    fn run(settings: &AudioSettings, input: &[f32]) -> Vec<f32> {
+        let mut dsp = RxDsp::new(RATE);
+        let mut output = Vec::new();
+        for block in input.chunks(300) {
+            output.extend_from_slice(dsp.process(settings, block));
+        }
+        output
+    }
+
+    #[test]
+    fn bandpass_passes_the_band_and_stops_the_rest() {
+        // (filter, tone inside, tones outside)
+        let cases = [
+            (RxFilter::Cw, 700.0, [200.0, 1500.0]),
+            (RxFilter::Ssb, 1500.0, [100.0, 3600.0]),
+            (RxFilter::Data, 1500.0, [100.0, 3800.0]),
+        ];
+        for (filter, inside, outside) in cases {
+            let settings = AudioSettings { rx_filter: filter, ..bypassed() };
+            let gain_db = |freq: f32| {
+                let output = run(&settings, &tone(freq, 0.5, SECOND));
+                20.0 * (rms(&output[SECOND / 2..]) / (0.5 / 2f32.sqrt())).log10()
+            };
+            let gain = gain_db(inside);
+            assert!(gain.abs() < 1.0, "{:?}: {} Hz at {:.1} dB", filter, inside, gain);
+            for freq in outside {
+                let gain = gain_db(freq);
+                assert!(gain < -18.0, "{:?}: {} Hz at {:.1} dB", filter, freq, gain);
+            }
+        }
+    }
+
+    #[test]
+    fn notch_removes_a_carrier_and_keeps_a_sweep() {
+        // A 1 kHz carrier under a tone sweeping from 500 to 2300 Hz over three seconds
+        let sweep: Vec<f32> = (0..3 * SECOND)
+            .map(|n| {
+                let t = n as f32 / RATE as f32;
+                0.3 * (2.0 * PI * (500.0 * t + 300.0 * t * t)).sin()
+            })
+            .collect();
+        let carrier = tone(1000.0, 0.3, 3 * SECOND);
+        let input: Vec<f32> = sweep.iter().zip(&carrier).map(|(s, c)| s + c).collect();
+        let output = run(&AudioSettings { rx_notch: true, ..bypassed() }, &input);
+
+        // In the last second the sweep is well clear of 1 kHz
+        let last = 2 * SECOND..3 * SECOND;
+        let carrier_left = amplitude(&output[last.clone()], 1000.0) / 0.3;
+        assert!(carrier_left < 0.03, "carrier down to {:.3}", carrier_left);
+        let kept_db = 20.0 * (rms(&output[last.clone()]) / rms(&sweep[last])).log10();
+        assert!(kept_db > -8.0, "sweep at {:.1} dB", kept_db);
+    }
+
+    #[test]
+    fn noise_reduction_passes_a_clean_signal_unchanged() {
+        // Silence first, so the noise estimate settles at nothing and every bin passes
+        let mut input = vec![0.0; NR_FRAME];
+        input.extend((0..2 * SECOND).map(|n| {
+            let t = n as f32 / RATE as f32;
+            0.4 * (2.0 * PI * 600.0 * t).sin() + 0.2 * (2.0 * PI * (900.0 * t + 400.0 * t * t)).sin()
+        }));
+        let output = run(&AudioSettings { rx_nr: true, ..bypassed() }, &input);
+        // Output lines up with the input and trails it by at most one frame
+        assert!(output.len() + NR_FRAME >= input.len());
+        // The first half frame only has one window on it
+        let error = output[NR_HOP..].iter().zip(&input[NR_HOP..]).fold(0.0f32, |max, (y, x)| max.max((y - x).abs()));
+        assert!(error < 1e-4, "output differs by {}", error);
+    }
+
+    #[test]
+    fn agc_settles_at_its_target() {
+        // (AGC, hang in ms, seconds to recover after the signal drops)
+        let cases = [(RxAgc::Fast, 100, 1.5), (RxAgc::Slow, 500, 6.0)];
+        for (agc, hang_ms, recover) in cases {
+            let mut input = tone(1000.0, 0.01, SECOND);
+            input.extend(tone(1000.0, 0.5, SECOND));
+            input.extend(tone(1000.0, 0.01, 8 * SECOND));
+            let output = run(&AudioSettings { rx_agc: agc, ..bypassed() }, &input);
+            let at = |secs: f32| (secs * RATE as f32) as usize;
+            let settled = |level: f32| (level - AGC_TARGET).abs() < 0.03;
+
+            // Weak signal brought up to the target
+            assert!(settled(peak(&output[at(0.5)..at(1.0)])), "{:?}: weak signal", agc);
+            // A strong one is pulled down within 50 ms
+            assert!(settled(peak(&output[at(1.05)..at(1.1)])), "{:?}: after the attack", agc);
+            // The gain is held through the hang time after the signal drops
+            let hang = hang_ms as f32 / 1000.0;
+            let held = peak(&output[at(2.0)..at(2.0 + hang * 0.8)]);
+            assert!(held < AGC_TARGET * 0.01 / 0.5 * 1.1, "{:?}: gain not held, {}", agc, held);
+            // Then it recovers at the release speed, and not sooner
+            let halfway = peak(&output[at(2.0 + recover * 0.3)..at(2.0 + recover * 0.3 + 0.05)]);
+            assert!(halfway < AGC_TARGET * 0.8, "{:?}: recovered too soon, {}", agc, halfway);
+            assert!(settled(peak(&output[at(2.0 + recover)..at(2.0 + recover + 0.2)])), "{:?}: not recovered", agc);
+        }
+    }
+}
@@ -1,4 +1,4 @@
-use std::sync::{Arc, Mutex, atomic::{AtomicBool, Ordering}};
+use std::sync::{Arc, Mutex, TryLockError, atomic::{AtomicBool, Ordering}};
 use serialport;
 
 pub fn shutdown(
@@ -17,3 +17,49 @@ pub fn shutdown(
     }
 }
 
+// SIGINT and SIGTERM always shut down. SIGHUP is a terminal hangup when the console UI
+// is attached, and a config reload request when running headless.
+pub fn install_signal_handlers(
+    shutting_down: Arc<AtomicBool>,
+    ser: Arc<Mutex<Box<dyn serialport::SerialPort + Send>>>,
+    stop_audio: Arc<Mutex<bool>>,
+    reload_on_hangup: bool,
+) -> anyhow::Result<()> {
+    ctrlc::set_handler(move || {
+        shutdown(shutting_down.clone(), ser.clone(), stop_audio.clone());
+    })?;
+    // Check if SIGHUP should reload instead; this replaces the handler ctrlc installed
+    if reload_on_hangup {
+        crate::config::install_reload_handler();
+    }
+    Ok(())
+}
+
+// Helper function to take a lock from a panic hook without blocking on the panicking thread
+fn try_lock_any<T: ?Sized>(m: &Mutex<T>) -> Option<std::sync::MutexGuard<'_, T>> {
+    match m.try_lock() {
+        Ok(guard) => Some(guard),
+        Err(TryLockError::Poisoned(poisoned)) => Some(poisoned.into_inner()),
+        Err(TryLockError::WouldBlock) => None,
+    }
+}
+
+// Un-key the radio before the default panic report, whichever thread panicked
+pub fn install_panic_hook(
+    ser: Arc<Mutex<Box<dyn serialport::SerialPort + Send>>>,
+    tx_state: Arc<Mutex<bool>>,
+) {
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        // Check if the TX flag is reachable so the bridge stops feeding TX audio
+        if let Some(mut tx) = try_lock_any(&tx_state) {
+            *tx = false;
+        }
+        // Check if the serial port is reachable (the panicking thread may be holding it)
+        if let Some(mut s) = try_lock_any(&ser) {
+            let _ = crate::trusdx::enable_streaming_speaker_off(&mut **s);
+            let _ = s.flush();
+        }
+        default_hook(info);
+    }));
+}
@@ -1,24 +1,54 @@
 use serialport::SerialPort;
+use crate::cat::{AudioStream, CatCommand, Mode, Vfo};
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::OnceLock;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 
-const CMD_RX: &[u8] = b";RX;";
-const CMD_TX0: &[u8] = b";TX0;";
+// VFO A lives in the shared freq_state; this tracks the rest of the VFO/split setup
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct VfoState {
+    pub freq_b: u64,
+    pub rx_vfo: Vfo,
+    pub tx_vfo: Vfo,
+}
+
+impl Default for VfoState {
+    fn default() -> Self {
+        VfoState { freq_b: 0, rx_vfo: Vfo::A, tx_vfo: Vfo::A }
+    }
+}
+
+impl VfoState {
+    pub fn split(&self) -> bool { self.rx_vfo != self.tx_vfo }
+
+    pub fn freq_of(&self, vfo: Vfo, freq_a: u64) -> u64 {
+        match vfo {
+            Vfo::B => self.freq_b,
+            _ => freq_a,
+        }
+    }
 
-const CMD_FA_QUERY: &[u8] = b"FA;";
+    pub fn rx_freq(&self, freq_a: u64) -> u64 { self.freq_of(self.rx_vfo, freq_a) }
 
-const CMD_MD0: &[u8] = b"MD0;";
-const CMD_MD1_LSB: &[u8] = b"MD1;";
-const CMD_MD2_USB: &[u8] = b"MD2;";
-const CMD_MD3_CW: &[u8] = b"MD3;";
-const CMD_MD4_FM: &[u8] = b"MD4;";
-const CMD_MD5_AM: &[u8] = b"MD5;";
+    // VFO that split TX lands on: the TX VFO while split, otherwise the one we are not listening on
+    pub fn split_vfo(&self) -> Vfo {
+        // Check if split is already active
+        if self.split() { return self.tx_vfo; }
+        match self.rx_vfo {
+            Vfo::B => Vfo::A,
+            _ => Vfo::B,
+        }
+    }
+}
 
-const CMD_UA1: &[u8] = b"UA1;";
-const CMD_UA2: &[u8] = b"UA2;";
+pub fn set_freq_command(vfo: Vfo, hz: u64) -> CatCommand {
+    match vfo {
+        Vfo::B => CatCommand::SetFreqB(hz),
+        _ => CatCommand::SetFreqA(hz),
+    }
+}
 
 static LAST_RTS: OnceLock<AtomicBool> = OnceLock::new();
 static LAST_DTR: OnceLock<AtomicBool> = OnceLock::new();
@@ -28,33 +58,188 @@ fn set_last_dtr(v: bool) { let _ = LAST_DTR.get_or_init(|| AtomicBool::new(v)).s
 fn get_last_rts() -> bool { LAST_RTS.get_or_init(|| AtomicBool::new(false)).load(Ordering::Relaxed) }
 pub fn last_rts_state() -> bool { get_last_rts() }
 
-pub fn find_trusdx_device() -> Option<String> {
-    for entry in fs::read_dir("/dev").ok()? {
-        let entry = entry.ok()?;
-        let fname = entry.file_name().into_string().ok()?;
-        // Check if device name starts with ttyUSB
-        if !fname.starts_with("ttyUSB") { continue; }
-        let dev_path = PathBuf::from("/sys/class/tty").join(&fname).join("device");
-        let dev_real_abs = std::fs::canonicalize(&dev_path).ok()?;
-        let usb_device_path = dev_real_abs.parent()?.parent()?;
-        let id_vendor_path = usb_device_path.join("idVendor");
-        let id_product_path = usb_device_path.join("idProduct");
-        // Check if vendor and product ID files exist
-        if id_vendor_path.exists() && id_product_path.exists() {
-            let vid = fs::read_to_string(id_vendor_path).ok()?.trim().to_lowercase();
-            let pid = fs::read_to_string(id_product_path).ok()?.trim().to_lowercase();
-            // Check if device matches CH340 USB-to-serial chip (1a86:7523)
-            if vid == "1a86" && pid == "7523" {
-                return Some(format!("/dev/{}", fname));
+// USB IDs matched when no --usb-id is given (CH340 used on the trUSDX)
+pub const DEFAULT_USB_IDS: &[(u16, u16)] = &[(0x1a86, 0x7523)];
+
+const TTY_PREFIXES: &[&str] = &["ttyUSB", "ttyACM"];
+const SERIAL_BY_ID_DIR: &str = "/dev/serial/by-id";
+
+#[derive(Clone, Debug, Default)]
+pub struct DeviceSelector {
+    pub port: Option<String>,
+    pub usb_ids: Vec<(u16, u16)>,
+    pub usb_serial: Option<String>,
+}
+
+#[derive(Clone, Debug)]
+pub struct CandidateDevice {
+    pub path: String,
+    pub sysfs_path: PathBuf,
+    pub vid: u16,
+    pub pid: u16,
+    pub serial: Option<String>,
+    pub manufacturer: Option<String>,
+    pub product: Option<String>,
+    pub by_id: Vec<PathBuf>,
+}
+
+impl DeviceSelector {
+    fn effective_usb_ids(&self) -> &[(u16, u16)] {
+        // Check if user supplied an explicit VID:PID list
+        if self.usb_ids.is_empty() { DEFAULT_USB_IDS } else { &self.usb_ids }
+    }
+
+    fn matches(&self, dev: &CandidateDevice) -> bool {
+        // Check if VID:PID pair is in the accepted list
+        if !self.effective_usb_ids().contains(&(dev.vid, dev.pid)) { return false; }
+        match &self.usb_serial {
+            Some(wanted) => dev.serial.as_deref() == Some(wanted.as_str()),
+            None => true,
+        }
+    }
+}
+
+// Parse a "vvvv:pppp" hex pair as printed by lsusb
+pub fn parse_usb_id(s: &str) -> Option<(u16, u16)> {
+    let (vid, pid) = s.trim().split_once(':')?;
+    let vid = u16::from_str_radix(vid.trim(), 16).ok()?;
+    let pid = u16::from_str_radix(pid.trim(), 16).ok()?;
+    Some((vid, pid))
+}
+
+fn read_sysfs_attr(dir: &Path, name: &str) -> Option<String> {
+    let value = fs::read_to_string(dir.join(name)).ok()?;
+    let value = value.trim();
+    // Check if attribute has content
+    if value.is_empty() { None } else { Some(value.to_string()) }
+}
+
+// Walk up from the tty's device node until the USB device directory (the one with idVendor)
+fn find_usb_device_dir(tty_name: &str) -> Option<PathBuf> {
+    let dev_path = PathBuf::from("/sys/class/tty").join(tty_name).join("device");
+    let mut dir = fs::canonicalize(&dev_path).ok()?;
+    loop {
+        // Check if this level is the USB device
+        if dir.join("idVendor").exists() && dir.join("idProduct").exists() {
+            return Some(dir);
+        }
+        // Check if we reached the sysfs root without finding it
+        if !dir.pop() { return None; }
+    }
+}
+
+fn by_id_links_for(dev_path: &Path) -> Vec<PathBuf> {
+    let mut links = Vec::new();
+    // Check if the by-id directory exists
+    if let Ok(entries) = fs::read_dir(SERIAL_BY_ID_DIR) {
+        for entry in entries.flatten() {
+            let link = entry.path();
+            // Check if the link resolves to this device
+            if fs::canonicalize(&link).ok().as_deref() == Some(dev_path) {
+                links.push(link);
             }
         }
     }
-    None
+    links.sort();
+    links
+}
+
+fn probe_tty(tty_name: &str) -> Option<CandidateDevice> {
+    let usb_dir = find_usb_device_dir(tty_name)?;
+    let vid = u16::from_str_radix(&read_sysfs_attr(&usb_dir, "idVendor")?, 16).ok()?;
+    let pid = u16::from_str_radix(&read_sysfs_attr(&usb_dir, "idProduct")?, 16).ok()?;
+    let path = format!("/dev/{}", tty_name);
+    let by_id = by_id_links_for(Path::new(&path));
+    Some(CandidateDevice {
+        path,
+        vid,
+        pid,
+        serial: read_sysfs_attr(&usb_dir, "serial"),
+        manufacturer: read_sysfs_attr(&usb_dir, "manufacturer"),
+        product: read_sysfs_attr(&usb_dir, "product"),
+        sysfs_path: usb_dir,
+        by_id,
+    })
+}
+
+pub fn list_candidate_devices(sel: &DeviceSelector) -> Vec<CandidateDevice> {
+    let mut found = Vec::new();
+    let entries = match fs::read_dir("/dev") {
+        Ok(entries) => entries,
+        Err(_) => return found,
+    };
+    for entry in entries.flatten() {
+        let fname = match entry.file_name().into_string() {
+            Ok(name) => name,
+            Err(_) => continue,
+        };
+        // Check if device name is a USB serial tty
+        if !TTY_PREFIXES.iter().any(|prefix| fname.starts_with(prefix)) { continue; }
+        // Check if sysfs describes a USB device matching the selector
+        if let Some(dev) = probe_tty(&fname) {
+            if sel.matches(&dev) { found.push(dev); }
+        }
+    }
+    found.sort_by(|a, b| a.path.cmp(&b.path));
+    found
 }
 
-pub fn open_trusdx_serial() -> anyhow::Result<Box<dyn SerialPort + Send>> {
-    let serial_device = find_trusdx_device().ok_or_else(|| anyhow::anyhow!("No CH340 device found"))?;
-    let port = serialport::new(&serial_device, 115200)
+pub fn print_candidate_devices(sel: &DeviceSelector) {
+    let devices = list_candidate_devices(sel);
+    // Check if nothing matched
+    if devices.is_empty() {
+        println!("No matching serial devices found");
+        return;
+    }
+    for dev in devices {
+        println!("{}", dev.path);
+        println!("  usb id:       {:04x}:{:04x}", dev.vid, dev.pid);
+        println!("  manufacturer: {}", dev.manufacturer.as_deref().unwrap_or("-"));
+        println!("  product:      {}", dev.product.as_deref().unwrap_or("-"));
+        println!("  serial:       {}", dev.serial.as_deref().unwrap_or("-"));
+        println!("  sysfs:        {}", dev.sysfs_path.display());
+        for link in &dev.by_id {
+            println!("  by-id:        {}", link.display());
+        }
+    }
+}
+
+pub fn find_trusdx_device(sel: &DeviceSelector) -> anyhow::Result<String> {
+    // Check if an explicit port path was given
+    if let Some(port) = &sel.port {
+        // Check if the path exists (by-id links are resolved by open)
+        if !Path::new(port).exists() {
+            anyhow::bail!("Serial device {} does not exist", port);
+        }
+        return Ok(port.clone());
+    }
+    let mut devices = list_candidate_devices(sel);
+    let ids: Vec<String> = sel
+        .effective_usb_ids()
+        .iter()
+        .map(|(vid, pid)| format!("{:04x}:{:04x}", vid, pid))
+        .collect();
+    match devices.len() {
+        0 => Err(anyhow::anyhow!("No serial device found matching USB id {}", ids.join(", "))),
+        1 => Ok(devices.remove(0).path),
+        _ => {
+            let paths: Vec<&str> = devices.iter().map(|d| d.path.as_str()).collect();
+            Err(anyhow::anyhow!(
+                "Several matching serial devices found ({}); pick one with --device or --usb-serial",
+                paths.join(", ")
+            ))
+        }
+    }
+}
+
+pub fn open_trusdx_serial(sel: &DeviceSelector) -> anyhow::Result<(String, Box<dyn SerialPort + Send>)> {
+    let serial_device = find_trusdx_device(sel)?;
+    let port = open_serial_path(&serial_device)?;
+    Ok((serial_device, port))
+}
+
+pub fn open_serial_path(serial_device: &str) -> anyhow::Result<Box<dyn SerialPort + Send>> {
+    let port = serialport::new(serial_device, 115200)
         .timeout(Duration::from_millis(10))
         .parity(serialport::Parity::None)
         .data_bits(serialport::DataBits::Eight)
@@ -69,29 +254,38 @@ fn send_command_to_radio(s: &mut dyn SerialPort, data: &[u8]) -> std::io::Result
     let _ = flush_serial_line(s);
     let res = s.write_all(data);
     let _ = flush_serial_line(s);
+    // Check if write failed so the reconnect supervisor can take over
+    if let Err(e) = &res { crate::reconnect::note_io_error(e); }
     res
 }
 
+// Leading ';' terminates any half-sent command still sitting in the radio's parser
+fn send_commands(s: &mut dyn SerialPort, cmds: &[CatCommand]) -> std::io::Result<()> {
+    let mut buf = vec![b';'];
+    for cmd in cmds {
+        cmd.encode_into(&mut buf);
+    }
+    send_command_to_radio(s, &buf)
+}
+
 pub fn start_transmit_baseband(s: &mut dyn SerialPort) -> std::io::Result<()> { 
-    let res = send_command_to_radio(s, CMD_TX0);
-    res
+    send_commands(s, &[CatCommand::Transmit])
 }
 
-pub fn query_vfo_a(s: &mut dyn SerialPort) -> std::io::Result<()> { 
-    send_command_to_radio(s, CMD_FA_QUERY) 
+// Poll both VFOs, plus IF for the active VFO, split and mode set on the front panel
+pub fn query_vfos(s: &mut dyn SerialPort) -> std::io::Result<()> {
+    let mut buf = CatCommand::GetFreqA.encode();
+    CatCommand::GetFreqB.encode_into(&mut buf);
+    CatCommand::GetInfo.encode_into(&mut buf);
+    send_command_to_radio(s, &buf)
 }
 
-pub fn set_mode(s: &mut dyn SerialPort, mode: u8) -> std::io::Result<()> {
-    let buf = match mode {
-        0 => CMD_MD0,
-        1 => CMD_MD1_LSB,
-        2 => CMD_MD2_USB,
-        3 => CMD_MD3_CW,
-        4 => CMD_MD4_FM,
-        5 => CMD_MD5_AM,
-        _ => CMD_MD2_USB,
-    };
-    send_command_to_radio(s, buf)
+pub fn set_vfo_a(s: &mut dyn SerialPort, hz: u64) -> std::io::Result<()> {
+    send_command_to_radio(s, &CatCommand::SetFreqA(hz).encode())
+}
+
+pub fn set_mode(s: &mut dyn SerialPort, mode: Mode) -> std::io::Result<()> {
+    send_command_to_radio(s, &CatCommand::SetMode(mode).encode())
 }
 
 
@@ -102,20 +296,14 @@ pub fn flush_serial_line(s: &mut dyn SerialPort) -> std::io::Result<()> {
 
 pub fn enable_streaming_speaker_off(s: &mut dyn SerialPort) -> std::io::Result<()> { 
     let _ = control_rts(s,true)?;
-    let mut combined = Vec::new();
-    combined.extend_from_slice(CMD_RX);
-    combined.extend_from_slice(CMD_UA2);
-    let result = send_command_to_radio(s, &combined);
+    let result = send_commands(s, &[CatCommand::Receive, CatCommand::SetAudioStream(AudioStream::SpeakerOff)]);
     let _ = control_rts(s,false)?;
     result
 }
 
 pub fn enable_streaming_speaker_on(s: &mut dyn SerialPort) -> std::io::Result<()> { 
     let _ = control_rts(s,true)?;
-    let mut combined = Vec::new();
-    combined.extend_from_slice(CMD_RX);
-    combined.extend_from_slice(CMD_UA1);
-    let result = send_command_to_radio(s, &combined);
+    let result = send_commands(s, &[CatCommand::Receive, CatCommand::SetAudioStream(AudioStream::SpeakerOn)]);
     let _ = control_rts(s,false)?;
     result
 }
@@ -124,21 +312,30 @@ pub fn enable_streaming_speaker_on(s: &mut dyn SerialPort) -> std::io::Result<()
 pub fn send_audio_stream_raw(s: &mut dyn SerialPort, audio_data: &[u8]) -> std::io::Result<()> {
     match s.write(audio_data) {
         Ok(_) => Ok(()),
-        Err(e) => Err(std::io::Error::new(std::io::ErrorKind::Other, e)),
+        Err(e) => {
+            crate::reconnect::note_io_error(&e);
+            Err(std::io::Error::new(std::io::ErrorKind::Other, e))
+        }
     }
 }
 
 pub fn control_rts(s: &mut dyn SerialPort, high: bool) -> std::io::Result<()> {
-    let r = s.write_request_to_send(high).map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e));
+    let r = s.write_request_to_send(high).map_err(std::io::Error::from);
     // Check if RTS control succeeded to update state
-    if r.is_ok() { set_last_rts(high); }
+    match &r {
+        Ok(()) => set_last_rts(high),
+        Err(e) => crate::reconnect::note_io_error(e),
+    }
     r
 }
 
 pub fn control_dtr(s: &mut dyn SerialPort, high: bool) -> std::io::Result<()> {
-    let r = s.write_data_terminal_ready(high).map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e));
+    let r = s.write_data_terminal_ready(high).map_err(std::io::Error::from);
     // Check if DTR control succeeded to update state
-    if r.is_ok() { set_last_dtr(high); }
+    match &r {
+        Ok(()) => set_last_dtr(high),
+        Err(e) => crate::reconnect::note_io_error(e),
+    }
     r
 }
 
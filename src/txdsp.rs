@@ -0,0 +1,230 @@
+// TX audio processing ahead of the 8-bit conversion: gain, an optional speech compressor,
+// and a look-ahead limiter that keeps peaks under full scale instead of clipping them
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::audio::AudioSettings;
+
+// Highest gain the GUI and rigctl offer; MICGAIN 1.0 maps to this
+pub const MAX_TX_GAIN: f32 = 2.0;
+const I16_SCALE: f32 = 32768.0;
+// Limiter output ceiling, leaving room for the quantizer's dither
+const CEILING: f32 = 0.95;
+const LOOKAHEAD_MS: u32 = 5;
+const LIMITER_RELEASE_MS: f32 = 50.0;
+const COMPRESSOR_ATTACK_MS: f32 = 3.0;
+const COMPRESSOR_RELEASE_MS: f32 = 150.0;
+const COMPRESSOR_RATIO: f32 = 4.0;
+// Threshold at full compression; lower amounts scale it towards 0 dBFS
+const COMPRESSOR_MAX_THRESHOLD_DB: f32 = -20.0;
+// How long the meters keep showing an overload
+const CLIP_HOLD: Duration = Duration::from_secs(1);
+
+// When TX audio last went over full scale, and whether the limiter caught it
+static LAST_OVERLOAD: Mutex<Option<(Instant, bool)>> = Mutex::new(None);
+
+// "LIMIT" or "CLIP" while a recent overload should be shown on the meters
+pub fn overload_indicator() -> Option<&'static str> {
+    match *LAST_OVERLOAD.lock().unwrap() {
+        Some((at, limited)) if at.elapsed() < CLIP_HOLD => Some(if limited { "LIMIT" } else { "CLIP" }),
+        _ => None,
+    }
+}
+
+// Helper function for a one-pole smoothing coefficient
+fn coefficient(ms: f32, rate: u32) -> f32 {
+    1.0 - (-1000.0 / (ms * rate as f32)).exp()
+}
+
+pub struct TxDsp {
+    lookahead: usize,
+    // Samples waiting for the limiter to see what follows them
+    delay: VecDeque<f32>,
+    // Gain each pending sample, and the one just being output, needs to stay under the ceiling
+    required: VecDeque<f32>,
+    // Released gain over the last `lookahead` samples, averaged into the applied gain
+    smoothing: VecDeque<f32>,
+    held_gain: f32,
+    envelope: f32,
+    limiter_release: f32,
+    compressor_attack: f32,
+    compressor_release: f32,
+}
+
+impl TxDsp {
+    pub fn new(rate: u32) -> TxDsp {
+        let mut dsp = TxDsp {
+            lookahead: (rate * LOOKAHEAD_MS / 1000).max(1) as usize,
+            delay: VecDeque::new(),
+            required: VecDeque::new(),
+            smoothing: VecDeque::new(),
+            held_gain: 1.0,
+            envelope: 0.0,
+            limiter_release: coefficient(LIMITER_RELEASE_MS, rate),
+            compressor_attack: coefficient(COMPRESSOR_ATTACK_MS, rate),
+            compressor_release: coefficient(COMPRESSOR_RELEASE_MS, rate),
+        };
+        dsp.reset();
+        dsp
+    }
+
+    // Start from silence, e.g. at the start of a transmission
+    pub fn reset(&mut self) {
+        self.delay.clear();
+        self.delay.resize(self.lookahead, 0.0);
+        self.required.clear();
+        self.required.resize(self.lookahead + 1, 1.0);
+        self.smoothing.clear();
+        self.smoothing.resize(self.lookahead, 1.0);
+        self.held_gain = 1.0;
+        self.envelope = 0.0;
+    }
+
+    // Helper function for the compressor gain at the current envelope
+    fn compressor_gain(&mut self, sample: f32, amount: f32) -> f32 {
+        let level = sample.abs();
+        let rate = if level > self.envelope { self.compressor_attack } else { self.compressor_release };
+        self.envelope += (level - self.envelope) * rate;
+        let threshold_db = COMPRESSOR_MAX_THRESHOLD_DB * amount;
+        let makeup_db = -threshold_db * (1.0 - 1.0 / COMPRESSOR_RATIO);
+        let envelope_db = 20.0 * self.envelope.max(1e-6).log10();
+        // Check if the envelope is above the threshold and needs reducing
+        let reduction_db = if envelope_db > threshold_db {
+            (threshold_db - envelope_db) * (1.0 - 1.0 / COMPRESSOR_RATIO)
+        } else {
+            0.0
+        };
+        10f32.powf((makeup_db + reduction_db) / 20.0)
+    }
+
+    // Process one block into `output` (full scale = 1.0); the limiter delays it by a few ms
+    pub fn process(&mut self, settings: &AudioSettings, input: &[i16], output: &mut Vec<f32>) {
+        output.clear();
+        let mut overload = false;
+        for &sample in input {
+            let mut value = sample as f32 / I16_SCALE * settings.tx_gain;
+            // Check if the speech compressor is switched in
+            if settings.tx_compressor {
+                value *= self.compressor_gain(value, settings.tx_compression);
+            }
+            overload |= value.abs() > 1.0;
+
+            // Check if the limiter is on; when off, audio still goes through the delay so latency stays put
+            let required = if settings.tx_limiter && value.abs() > CEILING { CEILING / value.abs() } else { 1.0 };
+            self.required.push_back(required);
+            self.required.pop_front();
+            let window = self.required.iter().copied().fold(1.0f32, f32::min);
+            // Check if the gain must drop now; rises follow the release time
+            self.held_gain = if window < self.held_gain {
+                window
+            } else {
+                self.held_gain + (window - self.held_gain) * self.limiter_release
+            };
+            self.smoothing.push_back(self.held_gain);
+            self.smoothing.pop_front();
+            let gain = self.smoothing.iter().sum::<f32>() / self.lookahead as f32;
+
+            self.delay.push_back(value);
+            let delayed = self.delay.pop_front().unwrap_or(0.0);
+            output.push((delayed * gain).clamp(-1.0, 1.0));
+        }
+        // Check if anything went over full scale in this block
+        if overload {
+            *LAST_OVERLOAD.lock().unwrap() = Some((Instant::now(), settings.tx_limiter));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::PI;
+
+    const RATE: u32 = 11520;
+
+    // Tests that overload the TX audio share LAST_OVERLOAD, so they take turns
+    static OVERLOAD: Mutex<()> = Mutex::new(());
+
+    fn tone(freq: f32, amplitude: f32, len: usize) -> Vec<i16> {
+        (0..len).map(|n| (amplitude * 32767.0 * (2.0 * PI * freq * n as f32 / RATE as f32).sin()) as i16).collect()
+    }
+
+    // Helper function to run `input` through in odd-sized blocks
+    fn run(dsp: &mut TxDsp, settings: &AudioSettings, input: &[i16]) -> Vec<f32> {
+        let mut output = Vec::new();
+        let mut block = Vec::new();
+        for chunk in input.chunks(317) {
+            dsp.process(settings, chunk, &mut block);
+            output.extend_from_slice(&block);
+        }
+        output
+    }
+
+    #[test]
+    fn limiter_holds_the_ceiling() {
+        let _turn = OVERLOAD.lock().unwrap_or_else(|e| e.into_inner());
+        // Quiet speech-band tone, a burst 12 dB over full scale, single-sample spikes, then quiet again
+        let mut input = tone(800.0, 0.1, RATE as usize / 2);
+        input.extend(tone(1300.0, 1.0, RATE as usize / 4));
+        input.extend((0..RATE as usize / 4).map(|n| if n % 97 == 0 { i16::MAX } else { 0 }));
+        input.extend(tone(800.0, 0.1, RATE as usize / 2));
+        for compressor in [false, true] {
+            let settings = AudioSettings { tx_gain: 4.0, tx_limiter: true, tx_compressor: compressor, tx_compression: 1.0, ..Default::default() };
+            let output = run(&mut TxDsp::new(RATE), &settings, &input);
+            let peak = output.iter().fold(0.0f32, |max, x| max.max(x.abs()));
+            assert!(peak <= CEILING + 1e-6, "compressor {}: peak {}", compressor, peak);
+            // The burst really is limited rather than passed through quietly
+            assert!(peak > CEILING * 0.99, "compressor {}: peak {}", compressor, peak);
+        }
+    }
+
+    #[test]
+    fn compressor_gain_stays_between_unity_and_makeup() {
+        for amount in [0.0, 0.25, 0.5, 1.0] {
+            let makeup = 10f32.powf(-COMPRESSOR_MAX_THRESHOLD_DB * amount * (1.0 - 1.0 / COMPRESSOR_RATIO) / 20.0);
+            for level_db in (-80..=0).step_by(10) {
+                let level = 10f32.powf(level_db as f32 / 20.0);
+                let mut dsp = TxDsp::new(RATE);
+                let mut gain = 0.0;
+                for _ in 0..RATE {
+                    gain = dsp.compressor_gain(level, amount);
+                }
+                assert!(gain >= 1.0 - 1e-3 && gain <= makeup * (1.0 + 1e-3), "amount {} at {} dBFS: gain {}", amount, level_db, gain);
+                // Check if the level is below the threshold and gets all of the makeup
+                if (level_db as f32) < COMPRESSOR_MAX_THRESHOLD_DB * amount {
+                    assert!((gain / makeup - 1.0).abs() < 1e-3, "amount {} at {} dBFS: gain {}", amount, level_db, gain);
+                }
+            }
+            // Full scale comes out at full scale
+            let mut dsp = TxDsp::new(RATE);
+            let mut gain = 0.0;
+            for _ in 0..RATE {
+                gain = dsp.compressor_gain(1.0, amount);
+            }
+            assert!((gain - 1.0).abs() < 1e-3, "amount {} at 0 dBFS: gain {}", amount, gain);
+        }
+    }
+
+    #[test]
+    fn overload_shows_and_clears() {
+        let _turn = OVERLOAD.lock().unwrap_or_else(|e| e.into_inner());
+        let loud = tone(1000.0, 1.0, 1000);
+        let quiet = tone(1000.0, 0.1, 1000);
+        let mut output = Vec::new();
+        for (limiter, shown) in [(true, "LIMIT"), (false, "CLIP")] {
+            let settings = AudioSettings { tx_gain: 2.0, tx_limiter: limiter, tx_compressor: false, ..Default::default() };
+            let mut dsp = TxDsp::new(RATE);
+            dsp.process(&settings, &loud, &mut output);
+            assert_eq!(overload_indicator(), Some(shown));
+        }
+
+        // Audio under full scale does not overload, so the indicator goes once the hold is over
+        let settings = AudioSettings { tx_gain: 2.0, tx_compressor: false, ..Default::default() };
+        let mut dsp = TxDsp::new(RATE);
+        std::thread::sleep(CLIP_HOLD);
+        dsp.process(&settings, &quiet, &mut output);
+        assert_eq!(overload_indicator(), None);
+    }
+}
@@ -0,0 +1,224 @@
+// VOX: keys the radio when applications play TX audio, for programs without CAT control
+//
+// The bridge feeds it the level of every TX audio block, also while receiving, and the level of
+// the RX audio it hands to applications. Anti-trip raises the threshold by the RX level so
+// received audio looping back into the TX device cannot key the radio.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::audio::AudioSettings;
+
+// How fast the anti-trip RX level falls after received audio stops
+const RX_LEVEL_DECAY_MS: f32 = 300.0;
+
+// Set while the current transmission was keyed by VOX and is still VOX's to end
+static KEYED: AtomicBool = AtomicBool::new(false);
+
+// Tests that key VOX or take a transmission over share KEYED, so they take turns
+#[cfg(test)]
+pub static KEYED_TESTS: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+pub fn is_keyed() -> bool {
+    KEYED.load(Ordering::Relaxed)
+}
+
+// Another keyer (e.g. a rigctl "T 1") took over the transmission; VOX leaves it alone
+pub fn hand_over() {
+    KEYED.store(false, Ordering::Relaxed);
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VoxAction {
+    None,
+    Key,
+    Unkey,
+}
+
+pub struct Vox {
+    rx_decay: f32,
+    // Decaying RMS of the RX audio, for anti-trip
+    rx_level: f32,
+    // Start of the current run of TX audio over the threshold
+    above_since: Option<Instant>,
+    last_above: Instant,
+    // Set when a VOX transmission was ended by something else; the audio must go quiet before
+    // VOX keys again, so a TX timeout is not undone straight away
+    needs_quiet: bool,
+}
+
+impl Vox {
+    pub fn new(rx_rate: u32) -> Vox {
+        Vox {
+            rx_decay: (-1000.0 / (RX_LEVEL_DECAY_MS * rx_rate as f32)).exp(),
+            rx_level: 0.0,
+            above_since: None,
+            last_above: Instant::now(),
+            needs_quiet: false,
+        }
+    }
+
+    // Note RX audio as applications receive it
+    pub fn feed_rx(&mut self, samples: &[f32]) {
+        // Check if there is anything to measure
+        if samples.is_empty() {
+            return;
+        }
+        let rms = (samples.iter().map(|s| s * s).sum::<f32>() / samples.len() as f32).sqrt();
+        self.rx_level = rms.max(self.rx_level * self.rx_decay.powi(samples.len() as i32));
+    }
+
+    // Decide on one TX audio block of RMS `level`; `transmitting` is the current TX state
+    pub fn update(&mut self, settings: &AudioSettings, level: f32, transmitting: bool, now: Instant) -> VoxAction {
+        // Check if a VOX transmission was ended by the watchdog, rigctl or a reconnect
+        if is_keyed() && !transmitting {
+            hand_over();
+            self.needs_quiet = true;
+        }
+        // Check if anti-trip applies; it only guards keying, not a transmission under way
+        let threshold = if transmitting {
+            settings.vox_threshold
+        } else {
+            settings.vox_threshold.max(self.rx_level * settings.vox_anti_trip)
+        };
+        // Check if the run of audio over the threshold continues
+        if level >= threshold {
+            self.above_since.get_or_insert(now);
+            self.last_above = now;
+        } else {
+            self.above_since = None;
+        }
+        let hang = Duration::from_millis(settings.vox_hang_ms as u64);
+        let quiet = now.duration_since(self.last_above) >= hang;
+        // Check if the audio has been quiet for a hang time since being cut off
+        if quiet {
+            self.needs_quiet = false;
+        }
+
+        // Check if VOX is in charge of the current transmission
+        if transmitting {
+            // Check if VOX was switched off or the audio stayed quiet for the hang time
+            if is_keyed() && (!settings.vox || quiet) {
+                hand_over();
+                return VoxAction::Unkey;
+            }
+            return VoxAction::None;
+        }
+
+        let attack = Duration::from_millis(settings.vox_attack_ms as u64);
+        // Check if the audio has stayed over the threshold long enough to key
+        match self.above_since {
+            Some(since) if settings.vox && !self.needs_quiet && now.duration_since(since) >= attack => {
+                KEYED.store(true, Ordering::Relaxed);
+                VoxAction::Key
+            }
+            _ => VoxAction::None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BLOCK: Duration = Duration::from_millis(10);
+
+    fn settings(anti_trip: f32) -> AudioSettings {
+        AudioSettings {
+            vox: true,
+            vox_threshold: 0.1,
+            vox_attack_ms: 50,
+            vox_hang_ms: 300,
+            vox_anti_trip: anti_trip,
+            ..Default::default()
+        }
+    }
+
+    // Drives a Vox block by block, keeping the TX state the way the bridge does
+    struct Run {
+        vox: Vox,
+        settings: AudioSettings,
+        now: Instant,
+        transmitting: bool,
+    }
+
+    impl Run {
+        fn new(settings: AudioSettings) -> Run {
+            hand_over();
+            Run { vox: Vox::new(7812), settings, now: Instant::now(), transmitting: false }
+        }
+
+        // Helper function for `blocks` blocks of TX audio at `level`, RX audio at `rx_level` alongside;
+        // returns the block number of every action
+        fn blocks(&mut self, blocks: usize, level: f32, rx_level: f32) -> Vec<(usize, VoxAction)> {
+            let mut actions = Vec::new();
+            for block in 0..blocks {
+                self.now += BLOCK;
+                self.vox.feed_rx(&[rx_level; 78]);
+                let action = self.vox.update(&self.settings, level, self.transmitting, self.now);
+                match action {
+                    VoxAction::Key => self.transmitting = true,
+                    VoxAction::Unkey => self.transmitting = false,
+                    VoxAction::None => continue,
+                }
+                actions.push((block, action));
+            }
+            actions
+        }
+    }
+
+    #[test]
+    fn keys_after_the_attack_time() {
+        let _turn = KEYED_TESTS.lock().unwrap_or_else(|e| e.into_inner());
+        let mut run = Run::new(settings(0.0));
+        // A blip shorter than the attack time does nothing
+        assert_eq!(run.blocks(3, 0.3, 0.0), []);
+        assert_eq!(run.blocks(10, 0.0, 0.0), []);
+        // Keys on the block that completes 50 ms over the threshold
+        assert_eq!(run.blocks(10, 0.3, 0.0), [(5, VoxAction::Key)]);
+        assert!(is_keyed());
+    }
+
+    #[test]
+    fn unkeys_after_the_hang_time() {
+        let _turn = KEYED_TESTS.lock().unwrap_or_else(|e| e.into_inner());
+        let mut run = Run::new(settings(0.0));
+        assert_eq!(run.blocks(10, 0.3, 0.0), [(5, VoxAction::Key)]);
+        // Pauses shorter than the hang time keep the radio keyed
+        assert_eq!(run.blocks(20, 0.0, 0.0), []);
+        assert_eq!(run.blocks(5, 0.3, 0.0), []);
+        // 300 ms after the last loud block
+        assert_eq!(run.blocks(50, 0.0, 0.0), [(29, VoxAction::Unkey)]);
+        assert!(!is_keyed());
+    }
+
+    #[test]
+    fn anti_trip_ignores_received_audio() {
+        let _turn = KEYED_TESTS.lock().unwrap_or_else(|e| e.into_inner());
+        // Received audio at 0.5 looping back into the TX device at 0.4
+        let mut run = Run::new(settings(1.0));
+        assert_eq!(run.blocks(100, 0.4, 0.5), []);
+        // Without anti-trip the same audio keys the radio
+        let mut run = Run::new(settings(0.0));
+        assert_eq!(run.blocks(100, 0.4, 0.5), [(5, VoxAction::Key)]);
+        // Louder TX audio still gets through
+        let mut run = Run::new(settings(1.0));
+        assert_eq!(run.blocks(100, 0.6, 0.5), [(5, VoxAction::Key)]);
+    }
+
+    #[test]
+    fn waits_for_quiet_after_being_cut_off() {
+        let _turn = KEYED_TESTS.lock().unwrap_or_else(|e| e.into_inner());
+        let mut run = Run::new(settings(0.0));
+        assert_eq!(run.blocks(10, 0.3, 0.0), [(5, VoxAction::Key)]);
+        // The TX timeout watchdog forces RX while the audio carries on
+        run.transmitting = false;
+        assert_eq!(run.blocks(200, 0.3, 0.0), []);
+        // Short pauses are not enough
+        assert_eq!(run.blocks(20, 0.0, 0.0), []);
+        assert_eq!(run.blocks(100, 0.3, 0.0), []);
+        // After a hang time of quiet, VOX keys again
+        assert_eq!(run.blocks(30, 0.0, 0.0), []);
+        assert_eq!(run.blocks(10, 0.3, 0.0), [(5, VoxAction::Key)]);
+    }
+}
@@ -0,0 +1,63 @@
+// Transmit timeout: forces the radio back to RX after too long a continuous transmission
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::trusdx;
+
+const WATCHDOG_INTERVAL_MS: u64 = 100;
+
+// Set when the watchdog un-keyed the radio, cleared on the next key-up
+static TIMED_OUT: AtomicBool = AtomicBool::new(false);
+
+pub fn tx_timed_out() -> bool { TIMED_OUT.load(Ordering::Relaxed) }
+
+// Put the radio back in RX and clear the TX flag, as a rigctl "T 0" would
+pub fn force_rx(
+    ser: &Arc<Mutex<Box<dyn serialport::SerialPort + Send>>>,
+    tx_state: &Arc<Mutex<bool>>,
+) {
+    *tx_state.lock().unwrap() = false;
+    // Check if serial port lock acquired successfully
+    if let Ok(mut s) = ser.lock() {
+        let _ = trusdx::enable_streaming_speaker_off(&mut **s);
+    }
+}
+
+// tx_timeout of None disables the watchdog; it is re-read every pass so it can be reloaded
+pub fn spawn_tx_watchdog(
+    ser: Arc<Mutex<Box<dyn serialport::SerialPort + Send>>>,
+    tx_state: Arc<Mutex<bool>>,
+    tx_timeout: Arc<Mutex<Option<Duration>>>,
+    shutting_down: Arc<AtomicBool>,
+) {
+    thread::spawn(move || {
+        let mut tx_since: Option<Instant> = None;
+        loop {
+            // Check if shutdown flag is set
+            if shutting_down.load(Ordering::Relaxed) { break; }
+            thread::sleep(Duration::from_millis(WATCHDOG_INTERVAL_MS));
+
+            let tx_now = *tx_state.lock().unwrap();
+            // Check if the radio was keyed since the last pass
+            if !tx_now {
+                tx_since = None;
+                continue;
+            }
+            let since = *tx_since.get_or_insert_with(|| {
+                TIMED_OUT.store(false, Ordering::Relaxed);
+                Instant::now()
+            });
+            let limit = *tx_timeout.lock().unwrap();
+            // Check if the transmission has run past the limit
+            if let Some(limit) = limit.filter(|limit| since.elapsed() >= *limit) {
+                force_rx(&ser, &tx_state);
+                TIMED_OUT.store(true, Ordering::Relaxed);
+                tx_since = None;
+                eprintln!("TX timeout: transmitted for {} s, radio forced back to RX", limit.as_secs());
+            }
+        }
+    });
+}